@@ -1,11 +1,21 @@
 #!/usr/bin/env node
 
 import axios from "axios";
+import { AsyncLocalStorage } from "async_hooks";
+import { execFile, spawn } from "child_process";
+import crypto from "crypto";
+import dns from "dns";
 import fs from "fs";
+import http from "http";
+import https from "https";
+import net from "net";
+import os from "os";
 import path from "path";
 import readline from "readline";
-import { fileURLToPath } from "url";
-import { GoogleGenAI } from "@google/genai";
+import { promisify } from "util";
+import zlib from "zlib";
+import { fileURLToPath, pathToFileURL } from "url";
+import { GoogleGenAI, Type, RawReferenceImage, MaskReferenceImage } from "@google/genai";
 import sharp from "sharp";
 
 const __filename = fileURLToPath(import.meta.url);
@@ -14,15 +24,217 @@ const __dirname = path.dirname(__filename);
 // Environment Variables
 const GEMINI_API_KEY = process.env.GEMINI_API_KEY;
 const REPLICATE_API_TOKEN = process.env.REPLICATE_API_TOKEN || process.env.REPLICATE_API_KEY;
-const HUGGING_FACE_TOKEN = process.env.HUGGING_FACE_TOKEN;
+const HUGGING_FACE_TOKEN = process.env.HUGGING_FACE_TOKEN || process.env.HF_TOKEN;
+const HF_MODEL = process.env.HF_MODEL || "stabilityai/stable-diffusion-xl-base-1.0";
+const HF_ENDPOINT_URL = process.env.HF_ENDPOINT_URL;
+const HF_LOADING_TIMEOUT_MS = parseInt(process.env.HF_LOADING_TIMEOUT_MS || "300000", 10);
+const OPENAI_API_KEY = process.env.OPENAI_API_KEY;
+const OPENAI_IMAGE_MODEL = process.env.OPENAI_IMAGE_MODEL || "gpt-image-1";
+const OPENAI_IMAGE_QUALITY = process.env.OPENAI_IMAGE_QUALITY;
+const STABILITY_API_KEY = process.env.STABILITY_API_KEY;
+const STABILITY_MODEL = process.env.STABILITY_MODEL || "core";
+const GOOGLE_CLOUD_PROJECT = process.env.GOOGLE_CLOUD_PROJECT;
+const GOOGLE_CLOUD_LOCATION = process.env.GOOGLE_CLOUD_LOCATION || "us-central1";
+const VERTEX_IMAGEN_MODEL = process.env.VERTEX_IMAGEN_MODEL || "imagen-4.0-generate-001";
+const VERTEX_IMAGEN_EDIT_MODEL = process.env.VERTEX_IMAGEN_EDIT_MODEL || "imagen-3.0-capability-001";
+const AUTOMATIC1111_URL = (process.env.AUTOMATIC1111_URL || "http://127.0.0.1:7860").replace(/\/$/, "");
+const AUTOMATIC1111_AUTH = process.env.AUTOMATIC1111_AUTH;
+const AUTOMATIC1111_SAMPLER = process.env.AUTOMATIC1111_SAMPLER;
+const AUTOMATIC1111_UPSCALER = process.env.AUTOMATIC1111_UPSCALER || "R-ESRGAN 4x+";
+const AUTOMATIC1111_STEPS = process.env.AUTOMATIC1111_STEPS ? parseInt(process.env.AUTOMATIC1111_STEPS, 10) : undefined;
+const AUTOMATIC1111_CFG_SCALE = process.env.AUTOMATIC1111_CFG_SCALE ? parseFloat(process.env.AUTOMATIC1111_CFG_SCALE) : undefined;
+const COMFYUI_URL = (process.env.COMFYUI_URL || "http://127.0.0.1:8188").replace(/\/$/, "");
+const COMFYUI_WORKFLOW = process.env.COMFYUI_WORKFLOW;
+const MOCK_FIXTURES_DIR = process.env.MOCK_FIXTURES_DIR;
+const MOCK_LATENCY_MS = parseInt(process.env.MOCK_LATENCY_MS || "0", 10);
+const COMFYUI_EDIT_WORKFLOW = process.env.COMFYUI_EDIT_WORKFLOW;
+const FAL_KEY = process.env.FAL_KEY;
+const FAL_MODEL = process.env.FAL_MODEL || "fal-ai/flux/schnell";
+const FAL_EDIT_MODEL = process.env.FAL_EDIT_MODEL || "fal-ai/flux/dev/image-to-image";
+const FAL_UPSCALE_MODEL = process.env.FAL_UPSCALE_MODEL || "fal-ai/esrgan";
+const TOGETHER_API_KEY = process.env.TOGETHER_API_KEY;
+const TOGETHER_IMAGE_MODEL = process.env.TOGETHER_IMAGE_MODEL || "black-forest-labs/FLUX.1-schnell";
+const TOGETHER_EDIT_MODEL = process.env.TOGETHER_EDIT_MODEL || "black-forest-labs/FLUX.1-kontext-dev";
+const RECRAFT_API_TOKEN = process.env.RECRAFT_API_TOKEN;
+const RECRAFT_MODEL = process.env.RECRAFT_MODEL || "recraftv3";
+const RECRAFT_STYLE = process.env.RECRAFT_STYLE || "realistic_image";
+const LEONARDO_API_KEY = process.env.LEONARDO_API_KEY;
+const LEONARDO_MODEL_ID = process.env.LEONARDO_MODEL_ID || "de7d3faf-762f-48e0-b3b7-9d0ac3a3fcf3"; // Leonardo Phoenix 1.0
+const LEONARDO_PRESET_STYLE = process.env.LEONARDO_PRESET_STYLE;
+const LEONARDO_STYLE_UUID = process.env.LEONARDO_STYLE_UUID;
+const AWS_REGION = process.env.AWS_REGION || process.env.AWS_DEFAULT_REGION || "us-east-1";
+const AWS_ACCESS_KEY_ID = process.env.AWS_ACCESS_KEY_ID;
+const AWS_SECRET_ACCESS_KEY = process.env.AWS_SECRET_ACCESS_KEY;
+const AWS_SESSION_TOKEN = process.env.AWS_SESSION_TOKEN;
+const BEDROCK_IMAGE_MODEL = process.env.BEDROCK_IMAGE_MODEL || "amazon.titan-image-generator-v2:0";
+const OPENAI_BASE_URL = (process.env.OPENAI_BASE_URL || "https://api.openai.com/v1").replace(/\/$/, "");
+const AZURE_OPENAI_ENDPOINT = (process.env.AZURE_OPENAI_ENDPOINT || "").replace(/\/$/, "");
+const AZURE_OPENAI_API_KEY = process.env.AZURE_OPENAI_API_KEY;
+const AZURE_OPENAI_DEPLOYMENT = process.env.AZURE_OPENAI_DEPLOYMENT;
+const AZURE_OPENAI_API_VERSION = process.env.AZURE_OPENAI_API_VERSION || "2025-04-01-preview";
+const AZURE_OPENAI_IMAGE_MODEL = process.env.AZURE_OPENAI_IMAGE_MODEL || "gpt-image-1";
+// Microsoft Entra ID (Azure AD) auth, used when AZURE_OPENAI_API_KEY is empty: a fixed token, a service principal or a managed identity.
+const AZURE_OPENAI_AD_TOKEN = process.env.AZURE_OPENAI_AD_TOKEN;
+const AZURE_TENANT_ID = process.env.AZURE_TENANT_ID;
+const AZURE_CLIENT_ID = process.env.AZURE_CLIENT_ID;
+const AZURE_CLIENT_SECRET = process.env.AZURE_CLIENT_SECRET;
+const AZURE_AUTHORITY_HOST = (process.env.AZURE_AUTHORITY_HOST || "https://login.microsoftonline.com").replace(/\/$/, "");
+const AZURE_OPENAI_USE_MANAGED_IDENTITY = process.env.AZURE_OPENAI_USE_MANAGED_IDENTITY === "true";
+const AZURE_OPENAI_AD_AUTH = !AZURE_OPENAI_API_KEY
+  && Boolean(AZURE_OPENAI_AD_TOKEN || (AZURE_TENANT_ID && AZURE_CLIENT_ID && AZURE_CLIENT_SECRET) || AZURE_OPENAI_USE_MANAGED_IDENTITY);
 const IMAGE_GENERATION_PROVIDER = process.env.IMAGE_GENERATION_PROVIDER || "gemini";
 const IMAGE_GENERATION_MODE = process.env.IMAGE_GENERATION_MODE || "default";
+const REPLICATE_POSE_MODEL = process.env.REPLICATE_POSE_MODEL;
+const REPLICATE_UPSCALE_MODEL = process.env.REPLICATE_UPSCALE_MODEL || "nightmareai/real-esrgan:f121d640bd286e1fdc67f9799164c1d5be36ff74576ee11c803ae5b665dd46aa";
+const REPLICATE_MODEL = process.env.REPLICATE_MODEL;
+const ENABLE_DESKTOP_TOOLS = process.env.ENABLE_DESKTOP_TOOLS === "true";
+const NOTIFY_WEBHOOK_URL = process.env.NOTIFY_WEBHOOK_URL;
+const SLACK_BOT_TOKEN = process.env.SLACK_BOT_TOKEN;
+const SLACK_CHANNEL_ID = process.env.SLACK_CHANNEL_ID;
+const SLACK_API_URL = (process.env.SLACK_API_URL || "https://slack.com/api").replace(/\/+$/, "");
+const WRITE_METADATA_SIDECARS = process.env.WRITE_METADATA_SIDECARS === "true";
+const OUTPUT_LOCALE = process.env.OUTPUT_LOCALE;
+const GRAPHVIZ_DOT_PATH = process.env.GRAPHVIZ_DOT_PATH || "dot";
+const MERMAID_CLI_PATH = process.env.MERMAID_CLI_PATH || "mmdc";
+const LATEX_PATH = process.env.LATEX_PATH || "latex";
+const DVISVGM_PATH = process.env.DVISVGM_PATH || "dvisvgm";
+const CHROME_PATH = process.env.CHROME_PATH;
+const PLUGINS_CONFIG = process.env.PLUGINS_CONFIG;
+const PROVIDER_HTTP_HEADERS = parseJsonEnv("PROVIDER_HTTP_HEADERS");
+const PROVIDER_HTTP_QUERY = parseJsonEnv("PROVIDER_HTTP_QUERY");
+const FORCE_IPV4 = process.env.FORCE_IPV4 === "true";
+const HAPPY_EYEBALLS_TIMEOUT_MS = parseInt(process.env.HAPPY_EYEBALLS_TIMEOUT_MS || "0", 10);
+const MAX_UPLOAD_RATE = parseFloat(process.env.MAX_UPLOAD_RATE || "0");
+const MAX_DOWNLOAD_RATE = parseFloat(process.env.MAX_DOWNLOAD_RATE || "0");
+const HTTP_RETRIES = parseInt(process.env.HTTP_RETRIES || "2", 10);
+const HTTP_RETRY_BASE_MS = parseInt(process.env.HTTP_RETRY_BASE_MS || "500", 10);
+const PROVIDER_RATE_LIMITS = parseJsonEnv("PROVIDER_RATE_LIMITS");
+const CIRCUIT_BREAKER_THRESHOLD = parseInt(process.env.CIRCUIT_BREAKER_THRESHOLD || "5", 10);
+const CIRCUIT_BREAKER_COOLDOWN_MS = parseInt(process.env.CIRCUIT_BREAKER_COOLDOWN_MS || "30000", 10);
+const HTTP_TRACE = process.env.HTTP_TRACE === "true";
+const HTTP_RECORD_FILE = process.env.HTTP_RECORD_FILE;
+const REQUEST_SIGNING = parseJsonEnv("REQUEST_SIGNING");
+const DNS_OVERRIDES = parseJsonEnv("DNS_OVERRIDES");
+const DNS_SERVERS = process.env.DNS_SERVERS ? process.env.DNS_SERVERS.split(",").map(s => s.trim()).filter(Boolean) : [];
+const PRE_GENERATION_HOOK = process.env.PRE_GENERATION_HOOK;
+const POST_GENERATION_HOOK = process.env.POST_GENERATION_HOOK;
+const HOOK_TOOLS = process.env.HOOK_TOOLS ? process.env.HOOK_TOOLS.split(",").map(s => s.trim()).filter(Boolean) : null;
+const POSTPROCESS_SCRIPT = process.env.POSTPROCESS_SCRIPT;
+const HOOK_TIMEOUT_MS = parseInt(process.env.HOOK_TIMEOUT_MS || "60000", 10);
+const WARMUP_PROVIDERS = process.env.WARMUP_PROVIDERS === "true";
+const PROBE_TIMEOUT_MS = parseInt(process.env.PROBE_TIMEOUT_MS || "10000", 10);
+const POLL_TIMEOUT_MS = parseCountEnv("POLL_TIMEOUT_MS", 600000);
+const POLL_RETRIES = parseCountEnv("POLL_RETRIES", 1);
+const COALESCE_REQUESTS = process.env.COALESCE_REQUESTS !== "false";
+const PROVIDER_FALLBACKS = (process.env.PROVIDER_FALLBACKS || "").split(",").map(s => s.trim()).filter(Boolean);
+const BALANCED_PROVIDERS = (process.env.BALANCED_PROVIDERS || "").trim();
+const SAFE_REWRITE_STRATEGY = process.env.SAFE_REWRITE_STRATEGY || "soften";
+const SAFE_REWRITE_ATTEMPTS = parseInt(process.env.SAFE_REWRITE_ATTEMPTS || "2", 10);
+const CONTENT_POLICY_QUARANTINE_FILE = process.env.CONTENT_POLICY_QUARANTINE_FILE;
+const MODEL_ROUTES = parseJsonEnv("MODEL_ROUTES");
+const ALLOWED_MODELS = parseJsonEnv("ALLOWED_MODELS");
+const STYLE_PROFILES_DIR = process.env.STYLE_PROFILES_DIR || path.join(os.homedir(), ".mcp-image-generator", "styles");
+const EXPERIMENTS_DIR = process.env.EXPERIMENTS_DIR || path.join(os.homedir(), ".mcp-image-generator", "experiments");
+const REFERENCE_CACHE_MB = parseFloat(process.env.REFERENCE_CACHE_MB || "64");
+
+// A whole number >= 0; anything else (e.g. "10m") is ignored with a warning so it can't become NaN.
+function parseCountEnv(name, fallback) {
+  const raw = process.env[name];
+  if (raw === undefined || raw.trim() === "") return fallback;
+  if (!/^\d+$/.test(raw.trim())) {
+    console.error(`Ignoring invalid ${name}=${raw}; using ${fallback}`);
+    return fallback;
+  }
+  return parseInt(raw, 10);
+}
+
+function parseJsonEnv(name) {
+  if (!process.env[name]) return {};
+  try {
+    return JSON.parse(process.env[name]);
+  } catch (error) {
+    console.error(`Ignoring invalid JSON in ${name}:`, error.message);
+    return {};
+  }
+}
+
+// Network Configuration
+// Happy eyeballs: try IPv6 and IPv4 addresses in parallel instead of waiting for a broken IPv6 route to time out.
+// These settings cover axios, the Gemini SDK and fetch, which all connect through Node's net module.
+if (typeof net.setDefaultAutoSelectFamily === "function") {
+  net.setDefaultAutoSelectFamily(true);
+  if (HAPPY_EYEBALLS_TIMEOUT_MS > 0) net.setDefaultAutoSelectFamilyAttemptTimeout(HAPPY_EYEBALLS_TIMEOUT_MS);
+}
+if (FORCE_IPV4) {
+  dns.setDefaultResultOrder("ipv4first");
+  axios.defaults.httpAgent = new http.Agent({ family: 4, keepAlive: true });
+  axios.defaults.httpsAgent = new https.Agent({ family: 4, keepAlive: true });
+}
+
+// DNS overrides: DNS_OVERRIDES pins hostnames to fixed addresses, and DNS_SERVERS resolves everything else through
+// specific resolvers (falling back to the system resolver). Node's net module, and with it axios, fetch and the Gemini SDK,
+// resolves through dns.lookup, so replacing it covers every provider.
+if (Object.keys(DNS_OVERRIDES).length > 0 || DNS_SERVERS.length > 0) {
+  const systemLookup = dns.lookup;
+  const pinnedHosts = Object.fromEntries(Object.entries(DNS_OVERRIDES).map(([host, addresses]) => [host.toLowerCase(), [].concat(addresses)]));
+  const resolver = DNS_SERVERS.length > 0 ? new dns.promises.Resolver() : null;
+  if (resolver) resolver.setServers(DNS_SERVERS);
+
+  const respond = (hostname, addresses, options, callback) => {
+    const family = options.family === "IPv4" ? 4 : options.family === "IPv6" ? 6 : options.family;
+    const matching = addresses.filter(a => !family || net.isIP(a) === family);
+    if (matching.length === 0) {
+      const error = new Error(`getaddrinfo ENOTFOUND ${hostname}`);
+      error.code = "ENOTFOUND";
+      callback(error);
+    } else if (options.all) {
+      callback(null, matching.map(address => ({ address, family: net.isIP(address) })));
+    } else {
+      callback(null, matching[0], net.isIP(matching[0]));
+    }
+  };
+
+  dns.lookup = function lookup(hostname, options, callback) {
+    if (typeof options === "function") {
+      callback = options;
+      options = {};
+    } else if (typeof options === "number") {
+      options = { family: options };
+    }
+    options = options || {};
+
+    const pinned = pinnedHosts[String(hostname).toLowerCase()];
+    if (pinned) return respond(hostname, pinned, options, callback);
+    if (!resolver || net.isIP(hostname) || hostname === "localhost") return systemLookup.call(dns, hostname, options, callback);
+
+    Promise.allSettled([resolver.resolve4(hostname), resolver.resolve6(hostname)]).then(([v4, v6]) => {
+      const ipv4 = v4.status === "fulfilled" ? v4.value : [];
+      const ipv6 = v6.status === "fulfilled" ? v6.value : [];
+      const addresses = FORCE_IPV4 ? [...ipv4, ...ipv6] : [...ipv6, ...ipv4];
+      if (addresses.length === 0) return systemLookup.call(dns, hostname, options, callback);
+      respond(hostname, addresses, options, callback);
+    });
+  };
+}
 
 // Provider Configuration
 const PROVIDERS = {
   GEMINI: "gemini",
   REPLICATE: "replicate",
   HUGGINGFACE: "huggingface",
+  OPENAI: "openai",
+  AZURE_OPENAI: "azure-openai",
+  STABILITY: "stability",
+  VERTEX: "vertex",
+  AUTOMATIC1111: "automatic1111",
+  COMFYUI: "comfyui",
+  FAL: "fal",
+  TOGETHER: "together",
+  RECRAFT: "recraft",
+  LEONARDO: "leonardo",
+  BEDROCK: "bedrock",
+  MOCK: "mock",
+  BALANCED: "balanced",
 };
 
 const MODES = {
@@ -34,34 +246,380 @@ const MODES = {
 function getActiveProvider() {
   if (IMAGE_GENERATION_PROVIDER === PROVIDERS.REPLICATE && REPLICATE_API_TOKEN) return PROVIDERS.REPLICATE;
   if (IMAGE_GENERATION_PROVIDER === PROVIDERS.HUGGINGFACE && HUGGING_FACE_TOKEN) return PROVIDERS.HUGGINGFACE;
+  if (IMAGE_GENERATION_PROVIDER === PROVIDERS.OPENAI && OPENAI_API_KEY) return PROVIDERS.OPENAI;
+  if (IMAGE_GENERATION_PROVIDER === PROVIDERS.AZURE_OPENAI && (AZURE_OPENAI_API_KEY || AZURE_OPENAI_AD_AUTH)) return PROVIDERS.AZURE_OPENAI;
+  if (IMAGE_GENERATION_PROVIDER === PROVIDERS.STABILITY && STABILITY_API_KEY) return PROVIDERS.STABILITY;
+  if (IMAGE_GENERATION_PROVIDER === PROVIDERS.FAL && FAL_KEY) return PROVIDERS.FAL;
+  if (IMAGE_GENERATION_PROVIDER === PROVIDERS.TOGETHER && TOGETHER_API_KEY) return PROVIDERS.TOGETHER;
+  if (IMAGE_GENERATION_PROVIDER === PROVIDERS.RECRAFT && RECRAFT_API_TOKEN) return PROVIDERS.RECRAFT;
+  if (IMAGE_GENERATION_PROVIDER === PROVIDERS.LEONARDO && LEONARDO_API_KEY) return PROVIDERS.LEONARDO;
+  // AWS credentials are often present for unrelated reasons, so Bedrock is only used when selected explicitly.
+  if (IMAGE_GENERATION_PROVIDER === PROVIDERS.BEDROCK && AWS_ACCESS_KEY_ID && AWS_SECRET_ACCESS_KEY) return PROVIDERS.BEDROCK;
+  // Vertex AI authenticates with Application Default Credentials, so it is only used when selected explicitly.
+  if (IMAGE_GENERATION_PROVIDER === PROVIDERS.VERTEX && GOOGLE_CLOUD_PROJECT) return PROVIDERS.VERTEX;
+  // A local WebUI needs no key; it is used only when selected explicitly.
+  if (IMAGE_GENERATION_PROVIDER === PROVIDERS.AUTOMATIC1111) return PROVIDERS.AUTOMATIC1111;
+  if (IMAGE_GENERATION_PROVIDER === PROVIDERS.COMFYUI && COMFYUI_WORKFLOW) return PROVIDERS.COMFYUI;
+  // Deterministic offline images for development and CI; never selected automatically.
+  if (IMAGE_GENERATION_PROVIDER === PROVIDERS.MOCK) return PROVIDERS.MOCK;
+  // Spreads requests over the providers/keys in BALANCED_PROVIDERS; only used when selected explicitly.
+  if (IMAGE_GENERATION_PROVIDER === PROVIDERS.BALANCED && BALANCED_PROVIDERS) return PROVIDERS.BALANCED;
   if (GEMINI_API_KEY) return PROVIDERS.GEMINI;
   if (REPLICATE_API_TOKEN) return PROVIDERS.REPLICATE;
   if (HUGGING_FACE_TOKEN) return PROVIDERS.HUGGINGFACE;
+  if (OPENAI_API_KEY) return PROVIDERS.OPENAI;
+  if (AZURE_OPENAI_API_KEY) return PROVIDERS.AZURE_OPENAI;
+  if (STABILITY_API_KEY) return PROVIDERS.STABILITY;
+  if (FAL_KEY) return PROVIDERS.FAL;
+  if (TOGETHER_API_KEY) return PROVIDERS.TOGETHER;
+  if (RECRAFT_API_TOKEN) return PROVIDERS.RECRAFT;
+  if (LEONARDO_API_KEY) return PROVIDERS.LEONARDO;
   return null;
 }
 
 const activeProvider = getActiveProvider();
 if (!activeProvider) {
-  console.error("Error: No valid API key found. Please set GEMINI_API_KEY, REPLICATE_API_TOKEN, or HUGGING_FACE_TOKEN.");
+  console.error("Error: No valid API key found. Please set GEMINI_API_KEY, REPLICATE_API_TOKEN, HUGGING_FACE_TOKEN, OPENAI_API_KEY, AZURE_OPENAI_API_KEY, STABILITY_API_KEY, FAL_KEY, TOGETHER_API_KEY, RECRAFT_API_TOKEN, or LEONARDO_API_KEY (or IMAGE_GENERATION_PROVIDER=bedrock with AWS credentials, IMAGE_GENERATION_PROVIDER=azure-openai with Azure AD credentials, IMAGE_GENERATION_PROVIDER=vertex with GOOGLE_CLOUD_PROJECT, IMAGE_GENERATION_PROVIDER=automatic1111, IMAGE_GENERATION_PROVIDER=comfyui with COMFYUI_WORKFLOW, IMAGE_GENERATION_PROVIDER=balanced with BALANCED_PROVIDERS, or IMAGE_GENERATION_PROVIDER=mock for offline testing).");
   process.exit(1);
 }
 
 console.error(`[System] Using Image Provider: ${activeProvider.toUpperCase()}`);
 
+// --- Provider HTTP Middleware ---
+// Every axios request runs through one stack of layers wrapped around axios's own adapter, so a new backend gets them
+// all by listing its API host in PROVIDER_API_HOSTS. Layers run outermost first as `(request, next)`, where `request`
+// is `{ config, provider }` (provider is null for other hosts, such as image CDNs) and `next()` returns the response.
+// The Gemini and Vertex SDKs use their own fetch client and bypass the stack; only extra headers reach them (httpOptions).
+
+// Extra headers/query parameters for provider requests: PROVIDER_HTTP_HEADERS / PROVIDER_HTTP_QUERY map a provider
+// (or "*") to values, and a tool call can add its own via http_headers / http_query for the duration of the call.
+const requestHttpContext = new AsyncLocalStorage();
+const PROVIDER_API_HOSTS = {
+    "api.replicate.com": PROVIDERS.REPLICATE,
+    "api-inference.huggingface.co": PROVIDERS.HUGGINGFACE,
+    ...(HF_ENDPOINT_URL ? { [new URL(HF_ENDPOINT_URL).host]: PROVIDERS.HUGGINGFACE } : {}),
+    "router.huggingface.co": PROVIDERS.HUGGINGFACE,
+    [new URL(OPENAI_BASE_URL).host]: PROVIDERS.OPENAI,
+    ...(AZURE_OPENAI_ENDPOINT ? { [new URL(AZURE_OPENAI_ENDPOINT).host]: PROVIDERS.AZURE_OPENAI } : {}),
+    "api.stability.ai": PROVIDERS.STABILITY,
+    "queue.fal.run": PROVIDERS.FAL,
+    "api.together.xyz": PROVIDERS.TOGETHER,
+    "external.api.recraft.ai": PROVIDERS.RECRAFT,
+    "cloud.leonardo.ai": PROVIDERS.LEONARDO,
+    [`bedrock-runtime.${AWS_REGION}.amazonaws.com`]: PROVIDERS.BEDROCK,
+    [`bedrock.${AWS_REGION}.amazonaws.com`]: PROVIDERS.BEDROCK,
+    [new URL(AUTOMATIC1111_URL).host]: PROVIDERS.AUTOMATIC1111,
+    [new URL(COMFYUI_URL).host]: PROVIDERS.COMFYUI,
+};
+
+function getProviderHttpExtras(provider) {
+    const store = requestHttpContext.getStore() || {};
+    return {
+        headers: { ...PROVIDER_HTTP_HEADERS["*"], ...PROVIDER_HTTP_HEADERS[provider], ...store.headers, ...store.providerHeaders?.[provider] },
+        query: { ...PROVIDER_HTTP_QUERY["*"], ...PROVIDER_HTTP_QUERY[provider], ...store.query },
+    };
+}
+
+// Per-provider requests per minute (PROVIDER_RATE_LIMITS, "*" for every provider) and circuit breaker state.
+const providerRateSlots = new Map();
+const providerCircuits = new Map();
+
+const RETRYABLE_HTTP_STATUSES = [429, 502, 503, 504];
+// Non-idempotent requests (generation POSTs) are only retried when the provider can't have run them: 429/503
+// rejections and connections that never reached it. A 502/504 may come from a gateway after the job was accepted.
+const NON_IDEMPOTENT_RETRYABLE_STATUSES = [429, 503];
+const UNSENT_REQUEST_CODES = ["ECONNREFUSED", "ENOTFOUND", "EAI_AGAIN"];
+const IDEMPOTENT_RETRY_CODES = ["ECONNRESET", "ETIMEDOUT", "ECONNABORTED", "EPIPE"];
+
+function isRetryableHttpError(error, config) {
+    if (typeof config.data?.pipe === "function") return false;
+    if (UNSENT_REQUEST_CODES.includes(error.code)) return true;
+    const status = error.response?.status;
+    if (!["get", "head", "put", "delete"].includes((config.method || "get").toLowerCase())) {
+        return NON_IDEMPOTENT_RETRYABLE_STATUSES.includes(status);
+    }
+    return RETRYABLE_HTTP_STATUSES.includes(status) || IDEMPOTENT_RETRY_CODES.includes(error.code);
+}
+
+// Retry-After (seconds or an HTTP date) when the provider sends one, otherwise exponential backoff with jitter.
+function getRetryDelayMs(error, attempt) {
+    const retryAfter = error.response?.headers?.["retry-after"];
+    if (retryAfter) {
+        const ms = /^\d+$/.test(retryAfter) ? Number(retryAfter) * 1000 : Date.parse(retryAfter) - Date.now();
+        if (ms >= 0) return Math.min(ms, 60000);
+    }
+    return HTTP_RETRY_BASE_MS * 2 ** attempt * (0.5 + Math.random() / 2);
+}
+
+// Opens after CIRCUIT_BREAKER_THRESHOLD consecutive server/network failures: requests then fail at once with an
+// "unavailable" error (which provider fallback picks up) until the cooldown ends and one trial request is let through.
+async function circuitBreakerLayer({ provider }, next) {
+    if (!provider || CIRCUIT_BREAKER_THRESHOLD <= 0) return next();
+    const circuit = providerCircuits.get(provider) || { failures: 0, openUntil: 0 };
+    providerCircuits.set(provider, circuit);
+    if (circuit.failures >= CIRCUIT_BREAKER_THRESHOLD) {
+        const remaining = circuit.openUntil - Date.now();
+        if (remaining > 0) {
+            throw new Error(`${provider} is unavailable: circuit breaker open after ${circuit.failures} consecutive failures (retry in ${Math.ceil(remaining / 1000)}s)`);
+        }
+        circuit.openUntil = Date.now() + CIRCUIT_BREAKER_COOLDOWN_MS;
+    }
+    try {
+        const response = await next();
+        circuit.failures = 0;
+        return response;
+    } catch (error) {
+        const status = error.response?.status;
+        if (status === undefined || status >= 500) {
+            circuit.failures++;
+            if (circuit.failures === CIRCUIT_BREAKER_THRESHOLD) {
+                circuit.openUntil = Date.now() + CIRCUIT_BREAKER_COOLDOWN_MS;
+                console.error(`[HTTP] Circuit breaker for ${provider} opened for ${CIRCUIT_BREAKER_COOLDOWN_MS}ms`);
+            }
+        } else {
+            circuit.failures = 0;
+        }
+        throw error;
+    }
+}
+
+async function retryLayer({ config, provider }, next) {
+    if (!provider) return next();
+    for (let attempt = 0; ; attempt++) {
+        try {
+            return await next();
+        } catch (error) {
+            if (attempt >= HTTP_RETRIES || !isRetryableHttpError(error, config)) throw error;
+            const delay = getRetryDelayMs(error, attempt);
+            console.error(`[HTTP] ${provider} request failed (${error.response?.status || error.code}); retry ${attempt + 1}/${HTTP_RETRIES} in ${Math.round(delay)}ms`);
+            await new Promise(resolve => setTimeout(resolve, delay));
+        }
+    }
+}
+
+async function rateLimitLayer({ provider }, next) {
+    const perMinute = provider && (PROVIDER_RATE_LIMITS[provider] ?? PROVIDER_RATE_LIMITS["*"]);
+    if (!(perMinute > 0)) return next();
+    const now = Date.now();
+    const slot = Math.max(now, providerRateSlots.get(provider) || 0);
+    providerRateSlots.set(provider, slot + 60000 / perMinute);
+    if (slot > now) await new Promise(resolve => setTimeout(resolve, slot - now));
+    return next();
+}
+
+// HTTP_TRACE logs every provider request to stderr; HTTP_RECORD_FILE appends them as JSON lines. Query strings and
+// headers are left out since they can carry credentials.
+async function traceLayer({ config, provider }, next) {
+    if (!provider || (!HTTP_TRACE && !HTTP_RECORD_FILE)) return next();
+    const started = Date.now();
+    const method = (config.method || "get").toUpperCase();
+    let url;
+    try {
+        url = new URL(config.url);
+        url = `${url.origin}${url.pathname}`;
+    } catch (err) {
+        url = config.url;
+    }
+    const record = (status, error) => {
+        const durationMs = Date.now() - started;
+        if (HTTP_TRACE) console.error(`[HTTP] ${provider} ${method} ${url} -> ${status ?? error} (${durationMs}ms)`);
+        if (HTTP_RECORD_FILE) {
+            const entry = { time: new Date(started).toISOString(), provider, method, url, status, duration_ms: durationMs, ...(error ? { error } : {}) };
+            fs.promises.appendFile(HTTP_RECORD_FILE, JSON.stringify(entry) + "\n")
+                .catch(err => console.error(`[HTTP] Could not write ${HTTP_RECORD_FILE}:`, err.message));
+        }
+    };
+    try {
+        const response = await next();
+        record(response.status);
+        return response;
+    } catch (error) {
+        record(error.response?.status, error.response ? undefined : error.code || error.message);
+        throw error;
+    }
+}
+
+// Injects PROVIDER_HTTP_HEADERS / PROVIDER_HTTP_QUERY, the call's own values and per-member keys (load balancing).
+async function providerExtrasLayer({ config, provider }, next) {
+    if (provider) {
+        const { headers, query } = getProviderHttpExtras(provider);
+        for (const [name, value] of Object.entries(headers)) config.headers.set(name, String(value));
+        // Bedrock requests are SigV4-signed before they reach the stack, and extra query parameters would break the signature.
+        if (Object.keys(query).length > 0 && provider !== PROVIDERS.BEDROCK) config.params = { ...query, ...config.params };
+    }
+    return next();
+}
+
+// HMAC signatures for gateways that require signed requests: REQUEST_SIGNING maps a provider (or "*") to a scheme.
+// `string_to_sign` and `header_value` are templates over {method}, {host}, {path} (with the query string), {timestamp},
+// {nonce}, {key_id} and {body_sha256}; bodies that can't be hashed before sending (multipart uploads) hash as
+// UNSIGNED-PAYLOAD. Signing runs after every other header and query parameter has been added.
+const DEFAULT_SIGNING_SCHEME = {
+    algorithm: "sha256",
+    encoding: "hex",
+    header: "X-Signature",
+    header_value: "{signature}",
+    timestamp_header: "X-Timestamp",
+    timestamp_format: "unix",
+    key_id_header: "X-Key-Id",
+    string_to_sign: "{method}\n{path}\n{timestamp}\n{body_sha256}",
+};
+
+for (const [provider, scheme] of Object.entries(REQUEST_SIGNING)) {
+    if (provider !== "*" && !Object.values(PROVIDERS).includes(provider)) console.error(`Ignoring unknown provider in REQUEST_SIGNING: ${provider}`);
+    else if (!scheme?.secret) console.error(`Ignoring REQUEST_SIGNING entry for ${provider}: secret is required`);
+}
+
+function getSigningScheme(provider) {
+    const scheme = REQUEST_SIGNING[provider]?.secret ? REQUEST_SIGNING[provider] : REQUEST_SIGNING["*"];
+    return scheme?.secret ? { ...DEFAULT_SIGNING_SCHEME, ...scheme } : null;
+}
+
+function getBodySha256(data) {
+    if (data === undefined || data === null) return sha256("");
+    if (typeof data === "string" || Buffer.isBuffer(data)) return sha256(data);
+    if (data instanceof ArrayBuffer) return sha256(Buffer.from(data));
+    if (ArrayBuffer.isView(data)) return sha256(Buffer.from(data.buffer, data.byteOffset, data.byteLength));
+    return "UNSIGNED-PAYLOAD";
+}
+
+function fillSigningTemplate(template, values) {
+    return template.replace(/\{(\w+)\}/g, (match, name) => (values[name] ?? match));
+}
+
+async function signingLayer({ config, provider }, next) {
+    const scheme = provider && getSigningScheme(provider);
+    if (!scheme) return next();
+    const url = new URL(axios.getUri(config));
+    const values = {
+        method: (config.method || "get").toUpperCase(),
+        host: url.host,
+        path: `${url.pathname}${url.search}`,
+        timestamp: scheme.timestamp_format === "iso" ? new Date().toISOString() : String(Math.floor(Date.now() / 1000)),
+        nonce: crypto.randomUUID(),
+        key_id: scheme.key_id ?? "",
+        body_sha256: getBodySha256(config.data),
+    };
+    values.signature = crypto.createHmac(scheme.algorithm, scheme.secret)
+        .update(fillSigningTemplate(scheme.string_to_sign, values))
+        .digest(scheme.encoding);
+    config.headers.set(scheme.header, fillSigningTemplate(scheme.header_value, values));
+    if (scheme.timestamp_header) config.headers.set(scheme.timestamp_header, values.timestamp);
+    if (scheme.nonce_header) config.headers.set(scheme.nonce_header, values.nonce);
+    if (scheme.key_id && scheme.key_id_header) config.headers.set(scheme.key_id_header, scheme.key_id);
+    return next();
+}
+
+// Bandwidth limits in KB/s: MAX_UPLOAD_RATE / MAX_DOWNLOAD_RATE, overridable per call with max_upload_rate / max_download_rate.
+// Only axios requests pass through here; the Google SDK (Gemini, Vertex AI) uses its own fetch and is not throttled.
+async function bandwidthLayer({ config }, next) {
+    const store = requestHttpContext.getStore() || {};
+    const upload = store.maxUploadRate ?? MAX_UPLOAD_RATE;
+    const download = store.maxDownloadRate ?? MAX_DOWNLOAD_RATE;
+    if (upload > 0 || download > 0) {
+        config.maxRate = [upload > 0 ? upload * 1024 : undefined, download > 0 ? download * 1024 : undefined];
+    }
+    return next();
+}
+
+// Each retry waits for its own rate-limit slot and is counted by the circuit breaker.
+const HTTP_MIDDLEWARE = [retryLayer, rateLimitLayer, circuitBreakerLayer, traceLayer, providerExtrasLayer, signingLayer, bandwidthLayer];
+
+function getRequestProvider(config) {
+    try {
+        return PROVIDER_API_HOSTS[new URL(config.url).host] || null;
+    } catch (err) {
+        return null;
+    }
+}
+
+const baseHttpAdapter = axios.getAdapter(axios.defaults.adapter);
+axios.defaults.adapter = (config) => {
+    const request = { config, provider: getRequestProvider(config) };
+    const dispatch = (index) => index < HTTP_MIDDLEWARE.length
+        ? HTTP_MIDDLEWARE[index](request, () => dispatch(index + 1))
+        : baseHttpAdapter(config);
+    return dispatch(0);
+};
+
 // --- Gemini Implementation ---
 const GEMINI_MODEL = "gemini-3-pro-image-preview";
+const GEMINI_VISION_MODEL = process.env.GEMINI_VISION_MODEL || "gemini-2.5-flash";
+// Vision/analysis tools can run on a local Ollama model instead, while image generation stays on the active provider.
+const VISION_PROVIDER = process.env.VISION_PROVIDER || "gemini";
+const OLLAMA_URL = (process.env.OLLAMA_URL || "http://127.0.0.1:11434").replace(/\/+$/, "");
+const OLLAMA_VISION_MODEL = process.env.OLLAMA_VISION_MODEL || "llama3.2-vision";
+
+// The Gemini SDK has no query passthrough; headers go through httpOptions.
+function applyGeminiHttpOptions(config, provider = PROVIDERS.GEMINI) {
+    const { headers } = getProviderHttpExtras(provider);
+    if (Object.keys(headers).length > 0) config.httpOptions = { headers };
+    return config;
+}
+
+// Vertex AI client. Credentials come from ADC: GOOGLE_APPLICATION_CREDENTIALS (service-account JSON) or `gcloud auth application-default login`.
+let vertexClient = null;
+if (activeProvider === PROVIDERS.VERTEX) {
+    vertexClient = new GoogleGenAI({ vertexai: true, project: GOOGLE_CLOUD_PROJECT, location: GOOGLE_CLOUD_LOCATION });
+}
 
+// Without an API key, Gemini models (vision tools) are served through Vertex AI as well.
 let geminiClient = null;
 if (GEMINI_API_KEY) {
     geminiClient = new GoogleGenAI({ apiKey: GEMINI_API_KEY });
+} else if (vertexClient) {
+    geminiClient = vertexClient;
+}
+
+// Balanced Gemini members with their own api_key get one client per key.
+const geminiMemberClients = new Map();
+function getGeminiClient() {
+    const key = getMemberApiKey(PROVIDERS.GEMINI);
+    if (!key) return geminiClient;
+    if (!geminiMemberClients.has(key)) geminiMemberClients.set(key, new GoogleGenAI({ apiKey: key }));
+    return geminiMemberClients.get(key);
+}
+
+// Extracts image buffers plus response metadata (model version, finish reasons, token usage) from a Gemini response.
+function parseGeminiImages(response) {
+    const images = [];
+    for (const candidate of response.candidates || []) {
+      const parts = candidate.content?.parts || [];
+      for (const part of parts) {
+        if (part.inlineData?.data) {
+          images.push(Buffer.from(part.inlineData.data, "base64"));
+        } else if (part.text) {
+          const cleanText = part.text.replace(/```base64/g, "").replace(/```/g, "").trim();
+          if (/^[A-Za-z0-9+/=]+$/.test(cleanText)) {
+            images.push(Buffer.from(cleanText, "base64"));
+          }
+        }
+      }
+    }
+
+    if (images.length === 0) {
+      const reasons = (response.candidates || []).map(c => c.finishReason).filter(r => r && r !== "STOP");
+      throw new Error(`No image data in Gemini API response${reasons.length > 0 ? ` (finish reason: ${reasons.join(", ")})` : ""}`);
+    }
+
+    return {
+      images,
+      metadata: {
+        model: response.modelVersion || GEMINI_MODEL,
+        response_id: response.responseId,
+        finish_reasons: (response.candidates || []).map(c => c.finishReason).filter(Boolean),
+        usage: response.usageMetadata,
+      },
+    };
 }
 
 async function generateImageGemini(prompt, options = {}) {
-  if (!geminiClient) throw new Error("Gemini API Key not initialized");
+  const client = getGeminiClient();
+  if (!client) throw new Error("Gemini API Key not initialized");
 
-  const config = {
+  const config = applyGeminiHttpOptions({
     responseModalities: ["IMAGE"],
-  };
+  });
 
   if (options.aspectRatio || options.resolution) {
     config.imageConfig = {};
@@ -74,8 +632,8 @@ async function generateImageGemini(prompt, options = {}) {
   }
 
   try {
-    const response = await geminiClient.models.generateContent({
-      model: GEMINI_MODEL,
+    const response = await client.models.generateContent({
+      model: options.model || GEMINI_MODEL,
       contents: [
         {
           role: "user",
@@ -86,29 +644,11 @@ async function generateImageGemini(prompt, options = {}) {
     });
 
     if (!response.candidates || response.candidates.length === 0) {
-      throw new Error("No candidates in Gemini API response");
-    }
-
-    const images = [];
-    for (const candidate of response.candidates) {
-      const parts = candidate.content?.parts || [];
-      for (const part of parts) {
-        if (part.inlineData?.data) {
-          images.push(Buffer.from(part.inlineData.data, "base64"));
-        } else if (part.text) {
-          const cleanText = part.text.replace(/```base64/g, "").replace(/```/g, "").trim();
-          if (/^[A-Za-z0-9+/=]+$/.test(cleanText)) {
-            images.push(Buffer.from(cleanText, "base64"));
-          }
-        }
-      }
-    }
-
-    if (images.length === 0) {
-      throw new Error("No image data in Gemini API response");
+      const blockReason = response.promptFeedback?.blockReason;
+      throw new Error(`No candidates in Gemini API response${blockReason ? ` (prompt blocked: ${blockReason})` : ""}`);
     }
 
-    return images;
+    return parseGeminiImages(response);
   } catch (error) {
     console.error("Gemini Generation Error:", error.response ? error.response.data : error.message);
     throw error;
@@ -116,11 +656,12 @@ async function generateImageGemini(prompt, options = {}) {
 }
 
 async function editImageGemini(base64Image, mimeType, prompt, options = {}) {
-  if (!geminiClient) throw new Error("Gemini API Key not initialized");
+  const client = getGeminiClient();
+  if (!client) throw new Error("Gemini API Key not initialized");
 
-  const config = {
+  const config = applyGeminiHttpOptions({
     responseModalities: ["IMAGE"],
-  };
+  });
 
   if (options.aspectRatio || options.resolution) {
     config.imageConfig = {};
@@ -133,47 +674,132 @@ async function editImageGemini(base64Image, mimeType, prompt, options = {}) {
   }
 
   try {
-    const response = await geminiClient.models.generateContent({
-      model: GEMINI_MODEL,
+    const response = await client.models.generateContent({
+      model: options.model || GEMINI_MODEL,
       contents: [
         {
           role: "user",
           parts: [
             { text: prompt },
             { inlineData: { mimeType: mimeType, data: base64Image } },
+            ...(options.extraImages || []).map(img => ({ inlineData: { mimeType: img.mimeType, data: img.data } })),
           ],
         },
       ],
       config: config,
     });
 
-    const images = [];
-    for (const candidate of response.candidates) {
-      const parts = candidate.content?.parts || [];
-      for (const part of parts) {
-        if (part.inlineData?.data) {
-          images.push(Buffer.from(part.inlineData.data, "base64"));
-        } else if (part.text) {
-          const cleanText = part.text.replace(/```base64/g, "").replace(/```/g, "").trim();
-          if (/^[A-Za-z0-9+/=]+$/.test(cleanText)) {
-            images.push(Buffer.from(cleanText, "base64"));
-          }
-        }
-      }
-    }
+    return parseGeminiImages(response);
+  } catch (error) {
+    console.error("Gemini Edit Error:", error.response ? error.response.data : error.message);
+    throw error;
+  }
+}
 
-    if (images.length === 0) {
-      throw new Error("No image data in Gemini API response");
-    }
+// Image understanding with a Gemini text model. Returns the response text (JSON text when a responseSchema is given).
+async function describeImageGemini(base64Image, mimeType, instruction, options = {}) {
+  if (!geminiClient) throw new Error("Vision tools require GEMINI_API_KEY (or VISION_PROVIDER=ollama with a local vision model).");
+
+  const config = applyGeminiHttpOptions({});
+  if (options.responseSchema) {
+    config.responseMimeType = "application/json";
+    config.responseSchema = options.responseSchema;
+  }
+
+  try {
+    const response = await geminiClient.models.generateContent({
+      model: GEMINI_VISION_MODEL,
+      contents: [
+        {
+          role: "user",
+          parts: [
+            { inlineData: { mimeType: mimeType, data: base64Image } },
+            { text: instruction },
+          ],
+        },
+      ],
+      config: config,
+    });
 
-    return images;
+    const text = response.text || (response.candidates?.[0]?.content?.parts || []).map(p => p.text || "").join("");
+    if (!text) throw new Error("No text in Gemini vision response");
+    return text.trim();
   } catch (error) {
-    console.error("Gemini Edit Error:", error.response ? error.response.data : error.message);
+    console.error("Gemini Vision Error:", error.response ? error.response.data : error.message);
+    throw error;
+  }
+}
+
+// Gemini response schemas use upper-case type names ("OBJECT"); Ollama's structured outputs take standard JSON Schema.
+function toJsonSchema(schema) {
+  if (Array.isArray(schema)) return schema.map(toJsonSchema);
+  if (!schema || typeof schema !== "object") return schema;
+  return Object.fromEntries(Object.entries(schema).map(([key, value]) => [key, key === "type" && typeof value === "string" ? value.toLowerCase() : toJsonSchema(value)]));
+}
+
+// Same contract as describeImageGemini, against a multimodal model served by Ollama (llama3.2-vision, qwen2.5vl, llava, ...).
+async function describeImageOllama(base64Image, mimeType, instruction, options = {}) {
+  try {
+    const response = await axios.post(`${OLLAMA_URL}/api/chat`, {
+      model: OLLAMA_VISION_MODEL,
+      messages: [{ role: "user", content: instruction, images: [base64Image] }],
+      format: options.responseSchema ? toJsonSchema(options.responseSchema) : undefined,
+      stream: false,
+      options: { temperature: 0 },
+    });
+    const text = response.data.message?.content;
+    if (!text) throw new Error("No text in Ollama vision response");
+    return text.trim();
+  } catch (error) {
+    if (error.code === "ECONNREFUSED") throw new Error(`Ollama is not reachable at ${OLLAMA_URL}. Start it with 'ollama serve' and pull ${OLLAMA_VISION_MODEL}.`);
+    if (error.response) throw new Error(`Ollama error ${error.response.status}: ${error.response.data?.error || JSON.stringify(error.response.data)}`);
     throw error;
   }
 }
 
+async function describeImageData(base64Image, mimeType, instruction, options = {}) {
+  if (VISION_PROVIDER === "ollama") return await describeImageOllama(base64Image, mimeType, instruction, options);
+  return await describeImageGemini(base64Image, mimeType, instruction, options);
+}
+
+// --- Poll Watchdog ---
+// Async providers (Replicate, fal, Leonardo, ComfyUI) are polled until their job finishes. A job still pending after
+// POLL_TIMEOUT_MS (0 = no limit) is cancelled on the provider and the poll fails with a retryable PollTimeoutError;
+// runWithFallback resubmits the request up to POLL_RETRIES times before moving on to a fallback provider.
+
+class PollTimeoutError extends Error {
+  constructor(label, elapsedMs) {
+    super(`${label} timed out after ${Math.round(elapsedMs / 1000)}s and was cancelled by the poll watchdog`);
+    this.name = "PollTimeoutError";
+    this.retryable = true;
+  }
+}
+
+// `check()` goes in the poll loop; `cancel` is best-effort, a failure to cancel is only logged.
+function startPollWatchdog(label, cancel) {
+  const started = Date.now();
+  return {
+    async check() {
+      const elapsed = Date.now() - started;
+      if (POLL_TIMEOUT_MS <= 0 || elapsed < POLL_TIMEOUT_MS) return;
+      console.error(`[Watchdog] ${label} exceeded ${POLL_TIMEOUT_MS}ms; cancelling`);
+      try {
+        await cancel?.();
+      } catch (error) {
+        console.error(`[Watchdog] Could not cancel ${label}:`, error.message);
+      }
+      throw new PollTimeoutError(label, elapsed);
+    },
+  };
+}
+
 // --- Replicate Implementation ---
+
+function cancelReplicatePrediction(prediction) {
+  if (!prediction.urls?.cancel) return;
+  return axios.post(prediction.urls.cancel, null, { headers: { Authorization: `Bearer ${REPLICATE_API_TOKEN}` } });
+}
+
 async function generateImageReplicate(prompt) {
   // Using SDXL-Lightning for speed and cost
   const modelVersion = "bytedance/sdxl-lightning-4step:5599ed30703defd1d160a25a63321b4dec97101d98b4674bcc56e41f62f35637"; 
@@ -191,10 +817,12 @@ async function generateImageReplicate(prompt) {
   });
 
   let prediction = response.data;
+  const watchdog = startPollWatchdog(`Replicate prediction ${prediction.id}`, () => cancelReplicatePrediction(prediction));
   
   // Simple polling if wait didn't finish it
-  while (prediction.status !== "succeeded" && prediction.status !== "failed") {
+  while (prediction.status !== "succeeded" && prediction.status !== "failed" && prediction.status !== "canceled") {
     await new Promise(resolve => setTimeout(resolve, 1000));
+    await watchdog.check();
     const statusUrl = prediction.urls.get;
     const statusResponse = await axios.get(statusUrl, {
       headers: { "Authorization": `Token ${REPLICATE_API_TOKEN}` }
@@ -202,7 +830,7 @@ async function generateImageReplicate(prompt) {
     prediction = statusResponse.data;
   }
 
-  if (prediction.status === "failed") throw new Error("Replicate generation failed: " + prediction.error);
+  if (prediction.status === "failed" || prediction.status === "canceled") throw new Error(`Replicate generation ${prediction.status}: ${prediction.error || "unknown error"}`);
   
   // Replicate returns a URL to the image
   const imageUrl = prediction.output[0];
@@ -236,16 +864,19 @@ async function generateImageNanoBanana(prompt, options = {}) {
   );
 
   let prediction = response.data;
+  const watchdog = startPollWatchdog(`Replicate prediction ${prediction.id}`, () => cancelReplicatePrediction(prediction));
 
   // In case the API still returns an in-progress prediction even with Prefer: wait
   while (
     prediction.status &&
     prediction.status !== "succeeded" &&
     prediction.status !== "failed" &&
+    prediction.status !== "canceled" &&
     prediction.urls &&
     prediction.urls.get
   ) {
     await new Promise((resolve) => setTimeout(resolve, 1000));
+    await watchdog.check();
     const statusUrl = prediction.urls.get;
     const statusResponse = await axios.get(statusUrl, {
       headers: { Authorization: `Bearer ${REPLICATE_API_TOKEN}` },
@@ -253,9 +884,9 @@ async function generateImageNanoBanana(prompt, options = {}) {
     prediction = statusResponse.data;
   }
 
-  if (prediction.status === "failed") {
+  if (prediction.status === "failed" || prediction.status === "canceled") {
     throw new Error(
-      "nano-banana-pro generation failed: " + (prediction.error || "unknown error")
+      `nano-banana-pro generation ${prediction.status}: ` + (prediction.error || "unknown error")
     );
   }
 
@@ -319,9 +950,11 @@ async function editImageReplicate(base64Image, mimeType, prompt) {
     });
   
     let prediction = response.data;
+    const watchdog = startPollWatchdog(`Replicate prediction ${prediction.id}`, () => cancelReplicatePrediction(prediction));
     
-    while (prediction.status !== "succeeded" && prediction.status !== "failed") {
+    while (prediction.status !== "succeeded" && prediction.status !== "failed" && prediction.status !== "canceled") {
       await new Promise(resolve => setTimeout(resolve, 1000));
+      await watchdog.check();
       const statusUrl = prediction.urls.get;
       const statusResponse = await axios.get(statusUrl, {
         headers: { "Authorization": `Token ${REPLICATE_API_TOKEN}` }
@@ -329,7 +962,7 @@ async function editImageReplicate(base64Image, mimeType, prompt) {
       prediction = statusResponse.data;
     }
   
-    if (prediction.status === "failed") throw new Error("Replicate edit failed: " + prediction.error);
+    if (prediction.status === "failed" || prediction.status === "canceled") throw new Error(`Replicate edit ${prediction.status}: ${prediction.error || "unknown error"}`);
     
     const imageUrl = prediction.output; 
     const imageResponse = await axios.get(imageUrl, { responseType: "arraybuffer" });
@@ -357,9 +990,11 @@ async function removeBackgroundReplicate(base64Image, mimeType) {
   });
 
   let prediction = response.data;
+  const watchdog = startPollWatchdog(`Replicate prediction ${prediction.id}`, () => cancelReplicatePrediction(prediction));
   
-  while (prediction.status !== "succeeded" && prediction.status !== "failed") {
+  while (prediction.status !== "succeeded" && prediction.status !== "failed" && prediction.status !== "canceled") {
     await new Promise(resolve => setTimeout(resolve, 1000));
+    await watchdog.check();
     const statusUrl = prediction.urls.get;
     const statusResponse = await axios.get(statusUrl, {
       headers: { "Authorization": `Token ${REPLICATE_API_TOKEN}` }
@@ -367,7 +1002,7 @@ async function removeBackgroundReplicate(base64Image, mimeType) {
     prediction = statusResponse.data;
   }
 
-  if (prediction.status === "failed") throw new Error("Replicate background removal failed: " + prediction.error);
+  if (prediction.status === "failed" || prediction.status === "canceled") throw new Error(`Replicate background removal ${prediction.status}: ${prediction.error || "unknown error"}`);
   
   const imageUrl = prediction.output; 
   const imageResponse = await axios.get(imageUrl, { responseType: "arraybuffer" });
@@ -408,15 +1043,18 @@ async function generateSvgRecraft(prompt, options = {}) {
   );
 
   let prediction = response.data;
+  const watchdog = startPollWatchdog(`Replicate prediction ${prediction.id}`, () => cancelReplicatePrediction(prediction));
 
   while (
     prediction.status &&
     prediction.status !== "succeeded" &&
     prediction.status !== "failed" &&
+    prediction.status !== "canceled" &&
     prediction.urls &&
     prediction.urls.get
   ) {
     await new Promise((resolve) => setTimeout(resolve, 1000));
+    await watchdog.check();
     const statusUrl = prediction.urls.get;
     const statusResponse = await axios.get(statusUrl, {
       headers: { Authorization: `Bearer ${REPLICATE_API_TOKEN}` },
@@ -424,8 +1062,8 @@ async function generateSvgRecraft(prompt, options = {}) {
     prediction = statusResponse.data;
   }
 
-  if (prediction.status === "failed") {
-    throw new Error("Recraft SVG generation failed: " + (prediction.error || "unknown error"));
+  if (prediction.status === "failed" || prediction.status === "canceled") {
+    throw new Error(`Recraft SVG generation ${prediction.status}: ` + (prediction.error || "unknown error"));
   }
 
   let svgUrl = null;
@@ -449,27 +1087,135 @@ async function generateSvgRecraft(prompt, options = {}) {
   return Buffer.from(svgResponse.data);
 }
 
-// --- Hugging Face Implementation ---
-async function generateImageHuggingFace(prompt) {
-    // Using Stable Diffusion XL Base 1.0
-    const modelId = "stabilityai/stable-diffusion-xl-base-1.0";
-    const url = `https://api-inference.huggingface.co/models/${modelId}`;
+// Creates a prediction for a model version ("owner/name:version") or an official model ("owner/name"), waits for it and returns its output.
+// Replicate logs diffusion steps as tqdm bars (" 45%|####      | 9/20 [...]"); the last one gives the progress.
+function parseReplicateLogProgress(logs) {
+  const matches = [...(logs || "").matchAll(/(\d{1,3})%\|/g)];
+  return matches.length > 0 ? Math.min(100, parseInt(matches[matches.length - 1][1], 10)) : null;
+}
 
-    const response = await axios.post(url, { inputs: prompt }, {
-        headers: {
-            "Authorization": `Bearer ${HUGGING_FACE_TOKEN}`,
-            "Content-Type": "application/json"
-        },
-        responseType: "arraybuffer"
+// When the client asked for progress, the prediction is polled from the start (instead of Prefer: wait) so each status update can be reported.
+async function runReplicatePrediction(model, input) {
+  if (!REPLICATE_API_TOKEN && !getMemberApiKey(PROVIDERS.REPLICATE)) throw new Error("Replicate API token is required.");
+
+  const [modelName, version] = model.split(":");
+  const url = version
+    ? "https://api.replicate.com/v1/predictions"
+    : `https://api.replicate.com/v1/models/${modelName}/predictions`;
+  const body = version ? { version, input } : { input };
+  const trackProgress = progressContext.getStore() !== undefined;
+
+  const headers = {
+    Authorization: `Bearer ${REPLICATE_API_TOKEN}`,
+    "Content-Type": "application/json",
+  };
+  if (!trackProgress) headers.Prefer = "wait";
+  const response = await axios.post(url, body, { headers });
+
+  let prediction = response.data;
+  const watchdog = startPollWatchdog(`Replicate prediction ${prediction.id}`, () => cancelReplicatePrediction(prediction));
+  let lastProgress = 0;
+  while (
+    prediction.status &&
+    prediction.status !== "succeeded" &&
+    prediction.status !== "failed" &&
+    prediction.status !== "canceled" &&
+    prediction.urls &&
+    prediction.urls.get
+  ) {
+    if (trackProgress) {
+      lastProgress = Math.max(lastProgress, parseReplicateLogProgress(prediction.logs) ?? 0);
+      reportProgress(lastProgress, 100, `Replicate ${modelName}: ${prediction.status}`);
+    }
+    await new Promise((resolve) => setTimeout(resolve, 1000));
+    await watchdog.check();
+    const statusResponse = await axios.get(prediction.urls.get, {
+      headers: { Authorization: `Bearer ${REPLICATE_API_TOKEN}` },
     });
+    prediction = statusResponse.data;
+  }
+
+  if (trackProgress) reportProgress(100, 100, `Replicate ${modelName}: ${prediction.status}`);
+  if (prediction.status !== "succeeded") {
+    throw new Error(`Replicate prediction ${prediction.status}: ${prediction.error || "unknown error"}`);
+  }
+  return prediction.output;
+}
+
+// Downloads every image URL found in a Replicate output (string, array or object with image/url fields).
+async function downloadReplicateImages(output) {
+  const urls = [];
+  const collect = (item) => {
+    if (!item) return;
+    if (typeof item === "string") urls.push(item);
+    else if (Array.isArray(item)) item.forEach(collect);
+    else if (typeof item === "object") collect(item.image || item.url || item.image_url || item.images);
+  };
+  collect(output);
+  if (urls.length === 0) throw new Error("Replicate prediction did not return an image URL.");
+
+  const images = [];
+  for (const url of urls) {
+    const imageResponse = await axios.get(url, { responseType: "arraybuffer" });
+    images.push(Buffer.from(imageResponse.data));
+  }
+  return images;
+}
 
-    return Buffer.from(response.data);
+async function upscaleImageReplicate(base64Image, mimeType, scale) {
+  const output = await runReplicatePrediction(REPLICATE_UPSCALE_MODEL, { image: `data:${mimeType};base64,${base64Image}`, scale });
+  return { images: await downloadReplicateImages(output), metadata: { model: REPLICATE_UPSCALE_MODEL, scale } };
 }
 
-async function editImageHuggingFace(base64Image, mimeType, prompt) {
-    throw new Error("Image editing is currently only supported on Gemini and Replicate providers.");
+// --- Hugging Face Implementation ---
+// Serverless Inference API by default (HF_MODEL), or a dedicated Inference Endpoint when HF_ENDPOINT_URL is set.
+// A cold model answers 503 with an estimated load time; the request is retried until HF_LOADING_TIMEOUT_MS.
+async function generateImageHuggingFace(prompt, options = {}) {
+    const url = options.model ? `https://api-inference.huggingface.co/models/${options.model}` : HF_ENDPOINT_URL || `https://api-inference.huggingface.co/models/${HF_MODEL}`;
+    const parameters = {
+        negative_prompt: options.negativePrompt,
+        width: options.width,
+        height: options.height,
+        num_inference_steps: options.steps,
+        guidance_scale: options.cfgScale,
+        seed: options.seed,
+    };
+    const body = { inputs: prompt };
+    if (Object.values(parameters).some(v => v !== undefined)) body.parameters = parameters;
+
+    const deadline = Date.now() + HF_LOADING_TIMEOUT_MS;
+    while (true) {
+        try {
+            const response = await axios.post(url, body, {
+                headers: {
+                    "Authorization": `Bearer ${HUGGING_FACE_TOKEN}`,
+                    "Content-Type": "application/json",
+                    "Accept": "image/png",
+                    "x-wait-for-model": "true"
+                },
+                responseType: "arraybuffer"
+            });
+            return Buffer.from(response.data);
+        } catch (error) {
+            if (error.response?.status !== 503) throw error;
+            let estimated = 20;
+            try {
+                estimated = JSON.parse(Buffer.from(error.response.data).toString("utf8")).estimated_time || estimated;
+            } catch (err) {
+                // Not JSON; use the default wait.
+            }
+            const waitMs = Math.min(Math.max(estimated, 5), 60) * 1000;
+            if (Date.now() + waitMs > deadline) {
+                throw new Error(`Hugging Face model ${options.model || HF_ENDPOINT_URL || HF_MODEL} is still loading after ${Math.round(HF_LOADING_TIMEOUT_MS / 1000)}s`);
+            }
+            console.error(`[HuggingFace] Model loading, retrying in ${Math.round(waitMs / 1000)}s`);
+            reportProgress(0, 100, `Hugging Face model loading (~${Math.round(estimated)}s)`);
+            await new Promise(resolve => setTimeout(resolve, waitMs));
+        }
+    }
 }
 
+
 async function removeBackgroundHuggingFace(imageBuffer) {
   // Using briaai/RMBG-2.0 Space
   const API_URL = "https://briaai-bria-rmbg-20.hf.space/run/predict";
@@ -499,462 +1245,7135 @@ async function removeBackgroundHuggingFace(imageBuffer) {
 }
 
 
-// --- Main Tool Logic ---
+// --- OpenAI Implementation ---
 
-const tools = [
-  {
-    name: "generate_image_from_text",
-    description: "Generate an image from a text description. Note: When using Gemini, it only supports specific resolutions and PNG format.",
-    inputSchema: {
-      type: "object",
-      properties: {
-        prompt: { type: "string", description: "Detailed description of the image." },
-        output_path: { type: "string", description: "Path where the generated image will be saved." },
-      },
-      required: ["prompt"],
+// gpt-image-1 supports square, landscape and portrait sizes; DALL-E 3 uses wider variants.
+function getOpenAiSize(aspectRatio, model) {
+  const ratio = aspectRatio ? parseAspectRatio(aspectRatio) : 1;
+  if (model === "dall-e-2") return "1024x1024";
+  if (model === "dall-e-3") return ratio > 1.2 ? "1792x1024" : ratio < 0.83 ? "1024x1792" : "1024x1024";
+  return ratio > 1.2 ? "1536x1024" : ratio < 0.83 ? "1024x1536" : "1024x1024";
+}
+
+function parseOpenAiImages(data, model) {
+  const images = (data.data || []).filter(d => d.b64_json).map(d => Buffer.from(d.b64_json, "base64"));
+  if (images.length === 0) throw new Error("No images in OpenAI response");
+  return {
+    images,
+    metadata: {
+      model,
+      revised_prompts: (data.data || []).map(d => d.revised_prompt).filter(Boolean),
+      usage: data.usage,
     },
-  },
-  {
-    name: "generate_svg_from_text",
-    description: "Generate an SVG vector image from a text description using the Recraft V3 SVG model on Replicate.",
-    inputSchema: {
-      type: "object",
-      properties: {
-        prompt: { type: "string", description: "Detailed description of the SVG image." },
-        output_path: { type: "string", description: "Path where the generated SVG will be saved. Defaults to output.svg." },
-        size: { type: "string", description: "Size of the image in WIDTHxHEIGHT format, e.g., '1024x1024'." },
-        style: { type: "string", description: "Style parameter supported by Recraft (e.g., 'any')." },
-        aspect_ratio: { type: "string", description: "Aspect ratio parameter for Recraft, or 'Not set' to disable." },
-      },
-      required: ["prompt"],
+  };
+}
+
+// Entra ID tokens for the Cognitive Services scope, cached until five minutes before they expire.
+const AZURE_COGNITIVE_SERVICES_SCOPE = "https://cognitiveservices.azure.com";
+const AZURE_IMDS_TIMEOUT_MS = 5000;
+let azureAdToken = null;
+let azureAdTokenRequest = null;
+
+async function requestAzureAdToken() {
+  if (AZURE_TENANT_ID && AZURE_CLIENT_ID && AZURE_CLIENT_SECRET) {
+    const body = new URLSearchParams({
+      grant_type: "client_credentials",
+      client_id: AZURE_CLIENT_ID,
+      client_secret: AZURE_CLIENT_SECRET,
+      scope: `${AZURE_COGNITIVE_SERVICES_SCOPE}/.default`,
+    });
+    const { data } = await axios.post(`${AZURE_AUTHORITY_HOST}/${encodeURIComponent(AZURE_TENANT_ID)}/oauth2/v2.0/token`, body.toString(), {
+      headers: { "Content-Type": "application/x-www-form-urlencoded" },
+    });
+    return { token: data.access_token, expiresAt: Date.now() + Number(data.expires_in) * 1000 };
+  }
+  // Managed identity through the instance metadata service; AZURE_CLIENT_ID picks a user-assigned identity. Off Azure
+  // the address doesn't answer at all, so the request gets a short timeout instead of hanging until the tool call times out.
+  try {
+    const { data } = await axios.get("http://169.254.169.254/metadata/identity/oauth2/token", {
+      params: { "api-version": "2018-02-01", resource: AZURE_COGNITIVE_SERVICES_SCOPE, client_id: AZURE_CLIENT_ID },
+      headers: { Metadata: "true" },
+      timeout: AZURE_IMDS_TIMEOUT_MS,
+    });
+    return { token: data.access_token, expiresAt: Number(data.expires_on) * 1000 };
+  } catch (error) {
+    if (error.response) throw error;
+    const problem = ["ECONNABORTED", "ETIMEDOUT"].includes(error.code)
+      ? `did not answer within ${AZURE_IMDS_TIMEOUT_MS}ms`
+      : `could not be reached (${error.code || error.message})`;
+    throw new Error(`the Azure instance metadata service (169.254.169.254) ${problem}. AZURE_OPENAI_USE_MANAGED_IDENTITY only works on Azure hosts with a managed identity; elsewhere set AZURE_OPENAI_API_KEY or a service principal.`);
+  }
+}
+
+async function getAzureAdToken() {
+  if (AZURE_OPENAI_AD_TOKEN) return AZURE_OPENAI_AD_TOKEN;
+  if (azureAdToken && azureAdToken.expiresAt - 5 * 60 * 1000 > Date.now()) return azureAdToken.token;
+  // Concurrent requests share one token request.
+  azureAdTokenRequest ??= requestAzureAdToken()
+    .then(result => (azureAdToken = result))
+    .catch(error => {
+      throw new Error(`Could not get an Azure AD token for Azure OpenAI: ${error.response ? JSON.stringify(error.response.data) : error.message}`);
+    })
+    .finally(() => (azureAdTokenRequest = null));
+  return (await azureAdTokenRequest).token;
+}
+
+async function getAzureOpenAiAuthHeaders() {
+  const memberKey = getMemberApiKey(PROVIDERS.AZURE_OPENAI);
+  if (memberKey) return { "api-key": memberKey };
+  return AZURE_OPENAI_AD_AUTH ? { Authorization: `Bearer ${await getAzureAdToken()}` } : { "api-key": AZURE_OPENAI_API_KEY };
+}
+
+// Azure OpenAI serves the same image API under a deployment URL with an api-version query, authenticated with an
+// api-key header or an Azure AD bearer token. `model` overrides the configured model (OpenAI) or deployment name (Azure).
+async function getOpenAiEndpoint(operation, provider = activeProvider, model) {
+  if (provider === PROVIDERS.AZURE_OPENAI) {
+    const deployment = model || AZURE_OPENAI_DEPLOYMENT;
+    if (!AZURE_OPENAI_ENDPOINT || !deployment) throw new Error("Azure OpenAI requires AZURE_OPENAI_ENDPOINT and AZURE_OPENAI_DEPLOYMENT.");
+    return {
+      url: `${AZURE_OPENAI_ENDPOINT}/openai/deployments/${encodeURIComponent(deployment)}/images/${operation}?api-version=${encodeURIComponent(AZURE_OPENAI_API_VERSION)}`,
+      headers: await getAzureOpenAiAuthHeaders(),
+      model: AZURE_OPENAI_IMAGE_MODEL,
+    };
+  }
+  if (!OPENAI_API_KEY && !getMemberApiKey(PROVIDERS.OPENAI)) throw new Error("OpenAI API key is required.");
+  return {
+    url: `${OPENAI_BASE_URL}/images/${operation}`,
+    headers: { Authorization: `Bearer ${OPENAI_API_KEY}` },
+    model: model || OPENAI_IMAGE_MODEL,
+  };
+}
+
+async function generateImageOpenAI(prompt, options = {}) {
+  const { url, headers, model } = await getOpenAiEndpoint("generations", options.provider, options.model);
+  const body = {
+    model,
+    prompt,
+    n: model === "dall-e-3" ? 1 : options.numberOfImages || 1,
+    size: getOpenAiSize(options.aspectRatio, model),
+  };
+  if (OPENAI_IMAGE_QUALITY) body.quality = OPENAI_IMAGE_QUALITY;
+  // DALL-E models return URLs unless asked for base64; gpt-image-1 always returns base64.
+  if (model.startsWith("dall-e")) body.response_format = "b64_json";
+
+  const response = await axios.post(url, body, {
+    headers: { ...headers, "Content-Type": "application/json" },
+  });
+  return parseOpenAiImages(response.data, model);
+}
+
+// OpenAI repaints where the mask is transparent; masks here are greyscale with white = repaint.
+async function toOpenAiMask(mask) {
+  const { width, height } = await sharp(mask).metadata();
+  const alpha = await sharp(mask).greyscale().negate({ alpha: false }).toColourspace("b-w").png().toBuffer();
+  return await sharp({ create: { width, height, channels: 3, background: "#000000" } }).joinChannel(alpha).png().toBuffer();
+}
+
+// Edits use multipart form data. gpt-image-1 accepts several input images (the first is edited, the rest are references) and an optional mask.
+async function editImageOpenAI(base64Image, mimeType, prompt, options = {}) {
+  const { url, headers, model } = await getOpenAiEndpoint("edits", options.provider, options.model);
+  const extension = (mimeType.split("/")[1] || "png").replace("jpeg", "jpg");
+  const multipleImages = (options.extraImages || []).length > 0;
+  // Reference images would otherwise be dropped without a word (e.g. after a per-call model override to a DALL-E model).
+  if (multipleImages && model !== "gpt-image-1") throw new Error(`Reference images need gpt-image-1; ${model} edits a single image.`);
+
+  const form = new FormData();
+  form.append("model", model);
+  form.append("prompt", prompt);
+  form.append("n", String(options.numberOfImages || 1));
+  form.append(multipleImages ? "image[]" : "image", new Blob([Buffer.from(base64Image, "base64")], { type: mimeType }), `image.${extension}`);
+  if (multipleImages) {
+    options.extraImages.forEach((img, i) => {
+      form.append("image[]", new Blob([Buffer.from(img.data, "base64")], { type: img.mimeType }), `reference_${i + 1}.${(img.mimeType.split("/")[1] || "png").replace("jpeg", "jpg")}`);
+    });
+  }
+  if (options.mask) form.append("mask", new Blob([await toOpenAiMask(options.mask)], { type: "image/png" }), "mask.png");
+  if (options.aspectRatio) form.append("size", getOpenAiSize(options.aspectRatio, model));
+  if (OPENAI_IMAGE_QUALITY) form.append("quality", OPENAI_IMAGE_QUALITY);
+  if (model.startsWith("dall-e")) form.append("response_format", "b64_json");
+
+  const response = await axios.post(url, form, { headers });
+  return parseOpenAiImages(response.data, model);
+}
+
+// --- Stability AI Implementation ---
+
+const STABILITY_ASPECT_RATIOS = ["21:9", "16:9", "3:2", "5:4", "1:1", "4:5", "2:3", "9:16", "9:21"];
+
+// Picks the supported ratio closest to the requested one (compared on a log scale so 2:1 and 1:2 are equally far from 1:1).
+function nearestAspectRatio(aspectRatio, supported) {
+  if (!aspectRatio) return "1:1";
+  if (supported.includes(aspectRatio)) return aspectRatio;
+  const target = Math.log(parseAspectRatio(aspectRatio));
+  return supported.reduce((best, r) => (Math.abs(Math.log(parseAspectRatio(r)) - target) < Math.abs(Math.log(parseAspectRatio(best)) - target) ? r : best));
+}
+
+function getStabilityAspectRatio(aspectRatio) {
+  return nearestAspectRatio(aspectRatio, STABILITY_ASPECT_RATIOS);
+}
+
+// STABILITY_MODEL is "core", "ultra", "sd3" or an SD3 variant such as "sd3.5-large" (served by the sd3 endpoint).
+function getStabilityEndpoint(model) {
+  const endpoint = model.startsWith("sd3") ? "sd3" : model;
+  return `https://api.stability.ai/v2beta/stable-image/generate/${endpoint}`;
+}
+
+async function postStability(model, fields, endpoint = getStabilityEndpoint(model)) {
+  if (!STABILITY_API_KEY && !getMemberApiKey(PROVIDERS.STABILITY)) throw new Error("Stability API key is required.");
+  const form = new FormData();
+  for (const [key, value] of Object.entries(fields)) {
+    if (value instanceof Blob) form.append(key, value, `${key}.${(value.type.split("/")[1] || "png").replace("jpeg", "jpg")}`);
+    else if (value !== undefined && value !== null) form.append(key, value);
+  }
+  if (model.startsWith("sd3") && model !== "sd3") form.append("model", model);
+  form.append("output_format", "png");
+
+  const response = await axios.post(endpoint, form, {
+    headers: { Authorization: `Bearer ${STABILITY_API_KEY}`, Accept: "image/*" },
+    responseType: "arraybuffer",
+    validateStatus: () => true,
+  });
+  if (response.status !== 200) {
+    let detail = Buffer.from(response.data).toString("utf8");
+    try {
+      const body = JSON.parse(detail);
+      detail = (body.errors || []).join("; ") || body.message || detail;
+    } catch (err) {
+      // Keep the raw text.
+    }
+    throw new Error(`Stability API error ${response.status}: ${detail}`);
+  }
+  return {
+    images: [Buffer.from(response.data)],
+    metadata: { model, seed: response.headers["seed"], finish_reason: response.headers["finish-reason"] },
+  };
+}
+
+async function generateImageStability(prompt, options = {}) {
+  return await postStability(options.model || STABILITY_MODEL, {
+    prompt,
+    negative_prompt: options.negativePrompt,
+    aspect_ratio: getStabilityAspectRatio(options.aspectRatio),
+    seed: options.seed !== undefined ? String(options.seed) : undefined,
+  });
+}
+
+// Image-to-image. Stable Image Core has no image input, so edits use Ultra unless an SD3 model is configured.
+async function editImageStability(base64Image, mimeType, prompt, options = {}) {
+  const model = options.model || (STABILITY_MODEL.startsWith("sd3") ? STABILITY_MODEL : "ultra");
+  const fields = {
+    prompt,
+    image: new Blob([Buffer.from(base64Image, "base64")], { type: mimeType }),
+    strength: String(options.strength ?? 0.6),
+    negative_prompt: options.negativePrompt,
+  };
+  if (model.startsWith("sd3")) fields.mode = "image-to-image";
+  return await postStability(model, fields);
+}
+
+// The fast upscaler always enlarges 4x.
+async function upscaleImageStability(base64Image, mimeType) {
+  return await postStability("fast-upscale", {
+    image: new Blob([Buffer.from(base64Image, "base64")], { type: mimeType }),
+  }, "https://api.stability.ai/v2beta/stable-image/upscale/fast");
+}
+
+// --- fal.ai Implementation ---
+// Requests go through the fal queue API (submit, poll status, fetch result) so long-running models don't hit HTTP timeouts.
+
+const FAL_IMAGE_SIZES = {
+  "1:1": "square_hd",
+  "4:3": "landscape_4_3",
+  "16:9": "landscape_16_9",
+  "3:4": "portrait_4_3",
+  "9:16": "portrait_16_9",
+};
+
+function getFalImageSize(options) {
+  if (options.width && options.height) return { width: options.width, height: options.height };
+  if (!options.aspectRatio) return undefined;
+  return FAL_IMAGE_SIZES[nearestAspectRatio(options.aspectRatio, Object.keys(FAL_IMAGE_SIZES))];
+}
+
+async function runFalModel(model, input) {
+  if (!FAL_KEY && !getMemberApiKey(PROVIDERS.FAL)) throw new Error("fal.ai API key (FAL_KEY) is required.");
+  const headers = { Authorization: `Key ${FAL_KEY}`, "Content-Type": "application/json" };
+
+  const submitted = (await axios.post(`https://queue.fal.run/${model}`, input, { headers })).data;
+  const watchdog = startPollWatchdog(`fal ${model} request ${submitted.request_id}`,
+    () => submitted.cancel_url && axios.put(submitted.cancel_url, null, { headers }));
+  let status = submitted;
+  while (status.status !== "COMPLETED") {
+    if (status.status === "IN_QUEUE" && status.queue_position !== undefined) {
+      reportProgress(0, 100, `fal ${model}: queue position ${status.queue_position}`);
+    } else if (status.status === "IN_PROGRESS") {
+      reportProgress(50, 100, `fal ${model}: running`);
+    }
+    await new Promise(resolve => setTimeout(resolve, 1000));
+    await watchdog.check();
+    status = (await axios.get(submitted.status_url, { headers })).data;
+  }
+  reportProgress(100, 100, `fal ${model}: completed`);
+
+  // A failed run returns its error from the result endpoint.
+  let result;
+  try {
+    result = (await axios.get(submitted.response_url, { headers })).data;
+  } catch (error) {
+    const detail = error.response?.data?.detail;
+    throw new Error(`fal.ai ${model} failed: ${detail ? JSON.stringify(detail) : error.message}`);
+  }
+
+  const images = [];
+  // Most models return `images`; single-image ones such as the upscalers return `image`.
+  for (const image of result.images || [result.image].filter(Boolean)) {
+    const response = await axios.get(image.url, { responseType: "arraybuffer" });
+    images.push(Buffer.from(response.data));
+  }
+  if (images.length === 0) throw new Error(`No images in fal.ai ${model} response`);
+  return {
+    images,
+    metadata: {
+      model,
+      request_id: submitted.request_id,
+      seed: result.seed,
+      has_nsfw_concepts: result.has_nsfw_concepts,
+      timings: result.timings,
     },
-  },
-  {
-    name: "edit_image",
-    description: "Edit an existing image based on your prompt. Note: This tool ONLY alters the visual content of the image; it does NOT change the image format or dimensions.",
-    inputSchema: {
-      type: "object",
-      properties: {
-        image_path: { type: "string", description: "Path to the image file." },
-        prompt: { type: "string", description: "Instructions for editing." },
-        output_path: { type: "string", description: "Path where the generated image will be saved." },
-      },
-      required: ["image_path", "prompt"],
+  };
+}
+
+async function generateImageFal(prompt, options = {}) {
+  return await runFalModel(options.model || FAL_MODEL, {
+    prompt,
+    image_size: getFalImageSize(options),
+    seed: options.seed,
+    num_images: options.numberOfImages || 1,
+  });
+}
+
+async function editImageFal(base64Image, mimeType, prompt, options = {}) {
+  return await runFalModel(options.model || FAL_EDIT_MODEL, {
+    prompt,
+    image_url: `data:${mimeType};base64,${base64Image}`,
+    strength: options.denoisingStrength ?? 0.6,
+    seed: options.seed,
+    num_images: options.numberOfImages || 1,
+  });
+}
+
+async function upscaleImageFal(base64Image, mimeType, scale) {
+  return await runFalModel(FAL_UPSCALE_MODEL, { image_url: `data:${mimeType};base64,${base64Image}`, scale });
+}
+
+// --- Together AI Implementation ---
+// OpenAI-style images endpoint; edits use a FLUX Kontext model with the source image as image_url.
+
+async function postTogetherImages(body) {
+  if (!TOGETHER_API_KEY && !getMemberApiKey(PROVIDERS.TOGETHER)) throw new Error("Together AI API key (TOGETHER_API_KEY) is required.");
+  let response;
+  try {
+    response = await axios.post("https://api.together.xyz/v1/images/generations", { ...body, response_format: "b64_json" }, {
+      headers: { Authorization: `Bearer ${TOGETHER_API_KEY}`, "Content-Type": "application/json" },
+      maxBodyLength: Infinity,
+    });
+  } catch (error) {
+    if (!error.response) throw error;
+    throw new Error(`Together AI error ${error.response.status}: ${error.response.data?.error?.message || JSON.stringify(error.response.data)}`);
+  }
+  const images = (response.data.data || []).filter(d => d.b64_json).map(d => Buffer.from(d.b64_json, "base64"));
+  if (images.length === 0) throw new Error("No images in Together AI response");
+  return { images, metadata: { model: body.model, id: response.data.id } };
+}
+
+async function generateImageTogether(prompt, options = {}) {
+  const { width, height } = getDiffusionSize(options);
+  return await postTogetherImages({
+    model: options.model || TOGETHER_IMAGE_MODEL,
+    prompt,
+    negative_prompt: options.negativePrompt,
+    width: width ?? 1024,
+    height: height ?? 1024,
+    steps: options.steps,
+    seed: options.seed,
+    n: options.numberOfImages || 1,
+  });
+}
+
+async function editImageTogether(base64Image, mimeType, prompt, options = {}) {
+  const { width, height } = getDiffusionSize(options);
+  return await postTogetherImages({
+    model: options.model || TOGETHER_EDIT_MODEL,
+    prompt,
+    image_url: `data:${mimeType};base64,${base64Image}`,
+    width,
+    height,
+    steps: options.steps,
+    seed: options.seed,
+    n: options.numberOfImages || 1,
+  });
+}
+
+// --- Recraft Implementation ---
+// OpenAI-style images API. The vector styles (vector_illustration, icon) return SVG files, which are saved unchanged.
+
+const RECRAFT_API_URL = "https://external.api.recraft.ai/v1";
+const RECRAFT_VECTOR_STYLES = ["vector_illustration", "icon"];
+const RECRAFT_SIZES = {
+  "1:1": "1024x1024", "4:3": "1365x1024", "3:4": "1024x1365", "3:2": "1536x1024", "2:3": "1024x1536",
+  "16:9": "1820x1024", "9:16": "1024x1820", "2:1": "2048x1024", "1:2": "1024x2048", "5:4": "1280x1024", "4:5": "1024x1280",
+};
+
+function getRecraftSize(aspectRatio) {
+  return RECRAFT_SIZES[nearestAspectRatio(aspectRatio, Object.keys(RECRAFT_SIZES))];
+}
+
+async function postRecraft(endpoint, body) {
+  if (!RECRAFT_API_TOKEN && !getMemberApiKey(PROVIDERS.RECRAFT)) throw new Error("Recraft API token (RECRAFT_API_TOKEN) is required.");
+  let response;
+  try {
+    response = await axios.post(`${RECRAFT_API_URL}/images/${endpoint}`, body, {
+      headers: { Authorization: `Bearer ${RECRAFT_API_TOKEN}`, ...(body instanceof FormData ? {} : { "Content-Type": "application/json" }) },
+      maxBodyLength: Infinity,
+    });
+  } catch (error) {
+    if (!error.response) throw error;
+    const data = error.response.data;
+    throw new Error(`Recraft API error ${error.response.status}: ${data?.message || data?.error?.message || JSON.stringify(data)}`);
+  }
+  const items = response.data.data || [];
+  if (items.length === 0) throw new Error("No images in Recraft response");
+  const images = await Promise.all(items.map(async (item) => {
+    if (item.b64_json) return Buffer.from(item.b64_json, "base64");
+    const download = await axios.get(item.url, { responseType: "arraybuffer" });
+    return Buffer.from(download.data);
+  }));
+  return { images, imageIds: items.map(item => item.image_id).filter(Boolean) };
+}
+
+// `options.vector` switches to vector_illustration; metadata.mimeType tells callers the outputs are SVG.
+async function generateImageRecraft(prompt, options = {}) {
+  const style = options.vector && !RECRAFT_VECTOR_STYLES.includes(options.style) ? "vector_illustration" : options.style || RECRAFT_STYLE;
+  const model = options.model || RECRAFT_MODEL;
+  const { images, imageIds } = await postRecraft("generations", {
+    prompt,
+    model,
+    style,
+    substyle: options.substyle,
+    negative_prompt: options.negativePrompt,
+    size: options.size || getRecraftSize(options.aspectRatio),
+    n: options.numberOfImages || 1,
+  });
+  const vector = RECRAFT_VECTOR_STYLES.includes(style);
+  return { images, metadata: { model, style, substyle: options.substyle, image_ids: imageIds, mimeType: vector ? "image/svg+xml" : undefined } };
+}
+
+async function editImageRecraft(base64Image, mimeType, prompt, options = {}) {
+  const model = options.model || RECRAFT_MODEL;
+  const style = options.style || RECRAFT_STYLE;
+  const form = new FormData();
+  form.append("image", new Blob([Buffer.from(base64Image, "base64")], { type: mimeType }), `image.${(mimeType.split("/")[1] || "png").replace("jpeg", "jpg")}`);
+  form.append("prompt", prompt);
+  form.append("strength", String(options.denoisingStrength ?? 0.5));
+  form.append("style", style);
+  form.append("model", model);
+  if (options.substyle) form.append("substyle", options.substyle);
+  if (options.negativePrompt) form.append("negative_prompt", options.negativePrompt);
+  if (options.numberOfImages) form.append("n", String(options.numberOfImages));
+  const { images, imageIds } = await postRecraft("imageToImage", form);
+  return { images, metadata: { model, style, image_ids: imageIds, mimeType: RECRAFT_VECTOR_STYLES.includes(style) ? "image/svg+xml" : undefined } };
+}
+
+// --- Leonardo.ai Implementation ---
+// Generations are asynchronous: the POST returns a generation id, which is polled until it is COMPLETE or FAILED.
+// Source images for edits are uploaded as init images through a presigned S3 form first.
+
+const LEONARDO_API_URL = "https://cloud.leonardo.ai/api/rest/v1";
+
+function leonardoHeaders() {
+  if (!LEONARDO_API_KEY && !getMemberApiKey(PROVIDERS.LEONARDO)) throw new Error("Leonardo API key (LEONARDO_API_KEY) is required.");
+  return { Authorization: `Bearer ${LEONARDO_API_KEY}`, "Content-Type": "application/json", Accept: "application/json" };
+}
+
+function getLeonardoError(error) {
+  if (!error.response) return error;
+  const data = error.response.data;
+  return new Error(`Leonardo API error ${error.response.status}: ${data?.error || data?.message || JSON.stringify(data)}`);
+}
+
+async function uploadLeonardoInitImage(base64Image, mimeType) {
+  const extension = (mimeType.split("/")[1] || "png").replace("jpeg", "jpg");
+  let upload;
+  try {
+    upload = (await axios.post(`${LEONARDO_API_URL}/init-image`, { extension }, { headers: leonardoHeaders() })).data.uploadInitImage;
+  } catch (error) {
+    throw getLeonardoError(error);
+  }
+  const form = new FormData();
+  for (const [key, value] of Object.entries(JSON.parse(upload.fields))) form.append(key, value);
+  form.append("file", new Blob([Buffer.from(base64Image, "base64")], { type: mimeType }), `init.${extension}`);
+  await axios.post(upload.url, form, { maxBodyLength: Infinity });
+  return upload.id;
+}
+
+async function runLeonardoGeneration(body) {
+  const headers = leonardoHeaders();
+  let generationId;
+  try {
+    generationId = (await axios.post(`${LEONARDO_API_URL}/generations`, body, { headers })).data.sdGenerationJob.generationId;
+  } catch (error) {
+    throw getLeonardoError(error);
+  }
+
+  const watchdog = startPollWatchdog(`Leonardo generation ${generationId}`,
+    () => axios.delete(`${LEONARDO_API_URL}/generations/${generationId}`, { headers }));
+  let generation;
+  for (let polls = 0; ; polls++) {
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await watchdog.check();
+    generation = (await axios.get(`${LEONARDO_API_URL}/generations/${generationId}`, { headers })).data.generations_by_pk;
+    if (generation.status === "COMPLETE") break;
+    if (generation.status === "FAILED") throw new Error(`Leonardo generation ${generationId} failed.`);
+    // Leonardo doesn't report progress, so the poll count stands in for it.
+    reportProgress(Math.min(90, polls * 10), 100, `Leonardo generation ${generationId}: ${generation.status.toLowerCase()}`);
+  }
+  reportProgress(100, 100, `Leonardo generation ${generationId}: completed`);
+
+  const images = [];
+  for (const image of generation.generated_images || []) {
+    const response = await axios.get(image.url, { responseType: "arraybuffer" });
+    images.push(Buffer.from(response.data));
+  }
+  if (images.length === 0) throw new Error(`No images in Leonardo generation ${generationId}`);
+  return {
+    images,
+    metadata: {
+      model: body.modelId,
+      generation_id: generationId,
+      preset_style: body.presetStyle,
+      style_uuid: body.styleUUID,
+      seed: generation.seed,
+      image_ids: generation.generated_images.map(image => image.id),
     },
-  },
-  {
-    name: "remove_background",
-    description: "Remove the background from an image.",
-    inputSchema: {
-      type: "object",
-      properties: {
-        image_path: { type: "string", description: "Path to the image file." },
-        output_path: { type: "string", description: "Path where the transparent image will be saved." },
-      },
-      required: ["image_path"],
+  };
+}
+
+// Per-call leonardoModel / presetStyle / styleUuid override the LEONARDO_* defaults; leonardoInput adds any other API field.
+function buildLeonardoBody(prompt, options) {
+  const { width, height } = getDiffusionSize(options);
+  return {
+    prompt,
+    modelId: options.leonardoModel || options.model || LEONARDO_MODEL_ID,
+    presetStyle: options.presetStyle || LEONARDO_PRESET_STYLE,
+    styleUUID: options.styleUuid || LEONARDO_STYLE_UUID,
+    negative_prompt: options.negativePrompt,
+    width: width ?? 1024,
+    height: height ?? 1024,
+    num_images: options.numberOfImages || 1,
+    seed: options.seed,
+    guidance_scale: options.cfgScale,
+    num_inference_steps: options.steps,
+    ...options.leonardoInput,
+  };
+}
+
+async function generateImageLeonardo(prompt, options = {}) {
+  return await runLeonardoGeneration(buildLeonardoBody(prompt, options));
+}
+
+async function editImageLeonardo(base64Image, mimeType, prompt, options = {}) {
+  const initImageId = await uploadLeonardoInitImage(base64Image, mimeType);
+  return await runLeonardoGeneration({
+    ...buildLeonardoBody(prompt, options),
+    init_image_id: initImageId,
+    // Leonardo's init_strength is how much of the source to keep, the inverse of denoising strength.
+    init_strength: options.denoisingStrength !== undefined ? Math.round((1 - options.denoisingStrength) * 100) / 100 : 0.5,
+  });
+}
+
+// --- Amazon Bedrock Implementation ---
+// InvokeModel requests are signed with AWS Signature Version 4 using the standard AWS_* credential variables.
+// BEDROCK_IMAGE_MODEL selects Titan Image Generator ("amazon.titan-image-generator-v2:0") or a Stability model
+// ("stability.sd3-5-large-v1:0", "stability.stable-image-ultra-v1:1", "stability.stable-image-core-v1:1").
+
+function hmacSha256(key, data) {
+  return crypto.createHmac("sha256", key).update(data).digest();
+}
+
+// Returns the headers to send (x-amz-date, Authorization, ...) for a request; `headers` must include host.
+function signAwsRequest(method, url, headers, body, { region, service, accessKeyId, secretAccessKey, sessionToken }, amzDate = new Date().toISOString().replace(/[:-]|\.\d{3}/g, "")) {
+  const parsed = new URL(url);
+  const dateStamp = amzDate.slice(0, 8);
+  const payloadHash = crypto.createHash("sha256").update(body || "").digest("hex");
+  const signed = { ...headers, "x-amz-date": amzDate };
+  if (sessionToken) signed["x-amz-security-token"] = sessionToken;
+  const names = Object.keys(signed).map(name => name.toLowerCase()).sort();
+  const lowerCased = Object.fromEntries(Object.entries(signed).map(([name, value]) => [name.toLowerCase(), String(value).trim()]));
+
+  // Path segments are encoded once more on top of the encoding already in the URL (all services except S3).
+  const canonicalUri = parsed.pathname.split("/").map(segment => encodeURIComponent(segment)).join("/") || "/";
+  const canonicalQuery = [...parsed.searchParams].map(([k, v]) => `${encodeURIComponent(k)}=${encodeURIComponent(v)}`).sort().join("&");
+  const canonicalRequest = [
+    method,
+    canonicalUri,
+    canonicalQuery,
+    names.map(name => `${name}:${lowerCased[name]}\n`).join(""),
+    names.join(";"),
+    payloadHash,
+  ].join("\n");
+
+  const scope = `${dateStamp}/${region}/${service}/aws4_request`;
+  const stringToSign = ["AWS4-HMAC-SHA256", amzDate, scope, crypto.createHash("sha256").update(canonicalRequest).digest("hex")].join("\n");
+  const signingKey = hmacSha256(hmacSha256(hmacSha256(hmacSha256(`AWS4${secretAccessKey}`, dateStamp), region), service), "aws4_request");
+  const signature = crypto.createHmac("sha256", signingKey).update(stringToSign).digest("hex");
+
+  const { host, ...rest } = signed;
+  return {
+    ...rest,
+    Authorization: `AWS4-HMAC-SHA256 Credential=${accessKeyId}/${scope}, SignedHeaders=${names.join(";")}, Signature=${signature}`,
+  };
+}
+
+async function invokeBedrockModel(modelId, payload) {
+  const url = `https://bedrock-runtime.${AWS_REGION}.amazonaws.com/model/${encodeURIComponent(modelId)}/invoke`;
+  const body = JSON.stringify(payload);
+  const headers = signAwsRequest("POST", url, {
+    host: new URL(url).host,
+    "content-type": "application/json",
+    accept: "application/json",
+  }, body, {
+    region: AWS_REGION,
+    service: "bedrock",
+    accessKeyId: AWS_ACCESS_KEY_ID,
+    secretAccessKey: AWS_SECRET_ACCESS_KEY,
+    sessionToken: AWS_SESSION_TOKEN,
+  });
+  try {
+    const response = await axios.post(url, body, { headers, maxBodyLength: Infinity });
+    return response.data;
+  } catch (error) {
+    if (!error.response) throw error;
+    throw new Error(`Bedrock ${modelId} error ${error.response.status}: ${error.response.data?.message || JSON.stringify(error.response.data)}`);
+  }
+}
+
+// Titan accepts a fixed list of sizes; pick the one closest to the requested aspect ratio.
+const TITAN_IMAGE_SIZES = [[1024, 1024], [1152, 896], [1216, 832], [1344, 768], [1173, 640], [896, 1152], [832, 1216], [768, 1344], [640, 1173]];
+
+function getTitanImageSize(aspectRatio) {
+  const target = Math.log(aspectRatio ? parseAspectRatio(aspectRatio) : 1);
+  return TITAN_IMAGE_SIZES.reduce((best, size) => (Math.abs(Math.log(size[0] / size[1]) - target) < Math.abs(Math.log(best[0] / best[1]) - target) ? size : best));
+}
+
+function parseBedrockImages(data, modelId) {
+  if (data.error) throw new Error(`Bedrock ${modelId}: ${data.error}`);
+  const images = (data.images || []).map(img => Buffer.from(img, "base64"));
+  if (images.length === 0) throw new Error(`No images in Bedrock ${modelId} response`);
+  return { images, metadata: { model: modelId, seeds: data.seeds, finish_reasons: data.finish_reasons } };
+}
+
+async function generateImageBedrock(prompt, options = {}) {
+  const modelId = options.model || BEDROCK_IMAGE_MODEL;
+  if (modelId.startsWith("stability.")) {
+    return parseBedrockImages(await invokeBedrockModel(modelId, {
+      prompt,
+      negative_prompt: options.negativePrompt,
+      aspect_ratio: getStabilityAspectRatio(options.aspectRatio),
+      seed: options.seed,
+      output_format: "png",
+    }), modelId);
+  }
+  const [width, height] = getTitanImageSize(options.aspectRatio);
+  return parseBedrockImages(await invokeBedrockModel(modelId, {
+    taskType: "TEXT_IMAGE",
+    textToImageParams: { text: prompt, negativeText: options.negativePrompt },
+    imageGenerationConfig: { numberOfImages: options.numberOfImages || 1, width, height, seed: options.seed, cfgScale: options.cfgScale },
+  }), modelId);
+}
+
+// Titan edits are image variations, or inpainting when a mask is given; Stability models run image-to-image (SD3.5 only).
+async function editImageBedrock(base64Image, mimeType, prompt, options = {}) {
+  const modelId = options.model || BEDROCK_IMAGE_MODEL;
+  if (modelId.startsWith("stability.")) {
+    return parseBedrockImages(await invokeBedrockModel(modelId, {
+      prompt,
+      mode: "image-to-image",
+      image: base64Image,
+      strength: options.denoisingStrength ?? 0.6,
+      negative_prompt: options.negativePrompt,
+      output_format: "png",
+    }), modelId);
+  }
+  const imageGenerationConfig = { numberOfImages: options.numberOfImages || 1, seed: options.seed };
+  if (options.mask) {
+    // Titan inpaints the pure-black pixels of the mask.
+    const maskImage = await sharp(options.mask).greyscale().threshold(128).negate({ alpha: false }).png().toBuffer();
+    return parseBedrockImages(await invokeBedrockModel(modelId, {
+      taskType: "INPAINTING",
+      inPaintingParams: { text: prompt, image: base64Image, maskImage: maskImage.toString("base64") },
+      imageGenerationConfig,
+    }), modelId);
+  }
+  // Titan rejects a similarityStrength outside [0.2, 1.0], so denoising strengths above 0.8 are capped there.
+  const similarityStrength = Math.min(1, Math.max(0.2, 1 - (options.denoisingStrength ?? 0.4)));
+  return parseBedrockImages(await invokeBedrockModel(modelId, {
+    taskType: "IMAGE_VARIATION",
+    imageVariationParams: { text: prompt, images: [base64Image], similarityStrength },
+    imageGenerationConfig,
+  }), modelId);
+}
+
+// --- Vertex AI Imagen Implementation ---
+
+const IMAGEN_ASPECT_RATIOS = ["1:1", "3:4", "4:3", "9:16", "16:9"];
+
+function parseImagenImages(response, model) {
+  const generated = response.generatedImages || [];
+  const images = generated.filter(g => g.image?.imageBytes).map(g => Buffer.from(g.image.imageBytes, "base64"));
+  if (images.length === 0) {
+    const reasons = generated.map(g => g.raiFilteredReason).filter(Boolean);
+    throw new Error(reasons.length > 0 ? `Imagen blocked the request: ${reasons.join("; ")}` : "No images in Imagen response");
+  }
+  return {
+    images,
+    metadata: {
+      model,
+      enhanced_prompts: generated.map(g => g.enhancedPrompt).filter(Boolean),
+      rai_filtered_reasons: generated.map(g => g.raiFilteredReason).filter(Boolean),
     },
-  },
-  {
-    name: "convert_image_format",
-    description: "Convert an image to a different format (e.g., PNG, JPEG, WEBP, GIF, TIFF, AVIF).",
-    inputSchema: {
-        type: "object",
-        properties: {
-            source_path: { type: "string", description: "Path to the source image." },
-            output_path: { type: "string", description: "Path where the converted image will be saved. If not provided, will save in the same directory with new extension." },
-            format: { type: "string", description: "Target format (png, jpeg, jpg, webp, gif, tiff, avif)." }
-        },
-        required: ["source_path", "format"]
+  };
+}
+
+async function generateImageVertex(prompt, options = {}) {
+  if (!vertexClient) throw new Error("Vertex AI is not configured (set IMAGE_GENERATION_PROVIDER=vertex and GOOGLE_CLOUD_PROJECT).");
+  const config = applyGeminiHttpOptions({
+    numberOfImages: options.numberOfImages || 1,
+    aspectRatio: nearestAspectRatio(options.aspectRatio, IMAGEN_ASPECT_RATIOS),
+    negativePrompt: options.negativePrompt,
+    includeRaiReason: true,
+  }, PROVIDERS.VERTEX);
+  if (options.resolution === "2K") config.imageSize = "2K";
+
+  const model = options.model || VERTEX_IMAGEN_MODEL;
+  const response = await vertexClient.models.generateImages({ model, prompt, config });
+  return parseImagenImages(response, model);
+}
+
+// Mask-free edits use the Imagen capability model; with `options.mask` (white = edit) the masked area is inpainted.
+async function editImageVertex(base64Image, mimeType, prompt, options = {}) {
+  if (!vertexClient) throw new Error("Vertex AI is not configured (set IMAGE_GENERATION_PROVIDER=vertex and GOOGLE_CLOUD_PROJECT).");
+  const raw = new RawReferenceImage();
+  raw.referenceId = 1;
+  raw.referenceImage = { imageBytes: base64Image, mimeType };
+  const referenceImages = [raw];
+  if (options.mask) {
+    const mask = new MaskReferenceImage();
+    mask.referenceId = 2;
+    mask.referenceImage = { imageBytes: Buffer.from(options.mask).toString("base64"), mimeType: "image/png" };
+    mask.config = { maskMode: "MASK_MODE_USER_PROVIDED" };
+    referenceImages.push(mask);
+  }
+  const config = applyGeminiHttpOptions({
+    editMode: options.mask ? "EDIT_MODE_INPAINT_INSERTION" : "EDIT_MODE_DEFAULT",
+    numberOfImages: options.numberOfImages || 1,
+    negativePrompt: options.negativePrompt,
+    includeRaiReason: true,
+  }, PROVIDERS.VERTEX);
+
+  const model = options.model || VERTEX_IMAGEN_EDIT_MODEL;
+  const response = await vertexClient.models.editImage({ model, prompt, referenceImages, config });
+  return parseImagenImages(response, model);
+}
+
+// --- Automatic1111 / SD WebUI Implementation ---
+// Talks to a local WebUI started with --api (AUTOMATIC1111_AUTH="user:pass" when it also uses --api-auth).
+
+// Shared by the local diffusion backends. Explicit width/height win; an aspect ratio gives a ~1 megapixel size in multiples of 64; otherwise the WebUI defaults apply.
+function getDiffusionSize(options) {
+  if (options.width || options.height) return { width: options.width, height: options.height };
+  if (!options.aspectRatio) return {};
+  const ratio = parseAspectRatio(options.aspectRatio);
+  const round64 = (v) => Math.max(64, Math.round(v / 64) * 64);
+  return { width: round64(1024 * Math.sqrt(ratio)), height: round64(1024 / Math.sqrt(ratio)) };
+}
+
+async function postAutomatic1111(endpoint, payload) {
+  const headers = { "Content-Type": "application/json" };
+  if (AUTOMATIC1111_AUTH) headers.Authorization = `Basic ${Buffer.from(AUTOMATIC1111_AUTH).toString("base64")}`;
+  let response;
+  try {
+    response = await axios.post(`${AUTOMATIC1111_URL}/sdapi/v1/${endpoint}`, payload, { headers, maxBodyLength: Infinity });
+  } catch (error) {
+    if (!error.response) throw new Error(`Could not reach the WebUI at ${AUTOMATIC1111_URL} (is it running with --api?): ${error.message}`);
+    throw new Error(`WebUI ${endpoint} error ${error.response.status}: ${JSON.stringify(error.response.data)}`);
+  }
+
+  // extra-single-image answers with a single `image`.
+  const images = (response.data.images || [response.data.image].filter(Boolean)).map(img => Buffer.from(img.split(",").pop(), "base64"));
+  if (images.length === 0) throw new Error(`No images in WebUI ${endpoint} response`);
+  let info = {};
+  try {
+    info = JSON.parse(response.data.info || "{}");
+  } catch (err) {
+    // Older WebUI versions return plain text here.
+  }
+  return {
+    images,
+    metadata: {
+      model: info.sd_model_name,
+      sampler: info.sampler_name,
+      steps: info.steps,
+      cfg_scale: info.cfg_scale,
+      seed: info.seed,
+      width: info.width,
+      height: info.height,
+    },
+  };
+}
+
+function buildAutomatic1111Payload(prompt, options) {
+  return {
+    prompt,
+    negative_prompt: options.negativePrompt || "",
+    sampler_name: options.sampler || AUTOMATIC1111_SAMPLER,
+    steps: options.steps ?? AUTOMATIC1111_STEPS,
+    cfg_scale: options.cfgScale ?? AUTOMATIC1111_CFG_SCALE,
+    seed: options.seed ?? -1,
+    batch_size: options.numberOfImages || 1,
+    ...getDiffusionSize(options),
+    // A model override switches the checkpoint for this request only.
+    override_settings: options.model ? { sd_model_checkpoint: options.model } : undefined,
+  };
+}
+
+async function generateImageAutomatic1111(prompt, options = {}) {
+  return await postAutomatic1111("txt2img", buildAutomatic1111Payload(prompt, options));
+}
+
+async function editImageAutomatic1111(base64Image, mimeType, prompt, options = {}) {
+  const payload = {
+    ...buildAutomatic1111Payload(prompt, options),
+    init_images: [base64Image],
+    denoising_strength: options.denoisingStrength ?? 0.6,
+  };
+  if (options.mask) payload.mask = Buffer.from(options.mask).toString("base64");
+  return await postAutomatic1111("img2img", payload);
+}
+
+async function upscaleImageAutomatic1111(base64Image, mimeType, scale) {
+  const { images } = await postAutomatic1111("extra-single-image", {
+    image: base64Image,
+    upscaling_resize: scale,
+    upscaler_1: AUTOMATIC1111_UPSCALER,
+  });
+  return { images, metadata: { upscaler: AUTOMATIC1111_UPSCALER, scale } };
+}
+
+// --- ComfyUI Implementation ---
+// Workflows are exported from ComfyUI with "Save (API Format)". String values can contain placeholders: {{prompt}},
+// {{negative_prompt}}, {{seed}}, {{width}}, {{height}}, {{steps}}, {{cfg_scale}}, {{denoise}}, {{model}} (a checkpoint name
+// chosen by quality routing) and (edit workflows) {{image}}.
+// A value that is exactly one placeholder is replaced with the typed value, so "{{seed}}" becomes a number.
+
+function fillWorkflowPlaceholders(node, values) {
+  if (typeof node === "string") {
+    const whole = node.match(/^\{\{(\w+)\}\}$/);
+    if (whole && values[whole[1]] !== undefined) return values[whole[1]];
+    return node.replace(/\{\{(\w+)\}\}/g, (match, key) => (values[key] !== undefined ? String(values[key]) : match));
+  }
+  if (Array.isArray(node)) return node.map(item => fillWorkflowPlaceholders(item, values));
+  if (node && typeof node === "object") {
+    return Object.fromEntries(Object.entries(node).map(([key, value]) => [key, fillWorkflowPlaceholders(value, values)]));
+  }
+  return node;
+}
+
+function loadComfyWorkflow(workflowPath) {
+  try {
+    const workflow = JSON.parse(fs.readFileSync(workflowPath, "utf8"));
+    if (Object.values(workflow).some(node => !node || !node.class_type)) {
+      throw new Error("expected the API format (use 'Save (API Format)' in ComfyUI)");
+    }
+    return workflow;
+  } catch (err) {
+    throw new Error(`Invalid ComfyUI workflow ${workflowPath}: ${err.message}`);
+  }
+}
+
+async function uploadComfyImage(imageBuffer, mimeType) {
+  const form = new FormData();
+  const extension = (mimeType.split("/")[1] || "png").replace("jpeg", "jpg");
+  form.append("image", new Blob([imageBuffer], { type: mimeType }), `mcp-input-${Date.now()}.${extension}`);
+  form.append("overwrite", "true");
+  const response = await axios.post(`${COMFYUI_URL}/upload/image`, form);
+  return response.data.subfolder ? `${response.data.subfolder}/${response.data.name}` : response.data.name;
+}
+
+// Queues the workflow, polls /history until it finishes, then downloads every image from the output nodes.
+async function runComfyWorkflow(workflow) {
+  let queued;
+  try {
+    queued = await axios.post(`${COMFYUI_URL}/prompt`, { prompt: workflow, client_id: "mcp-image-generator" });
+  } catch (error) {
+    if (!error.response) throw new Error(`Could not reach ComfyUI at ${COMFYUI_URL}: ${error.message}`);
+    throw new Error(`ComfyUI rejected the workflow: ${JSON.stringify(error.response.data)}`);
+  }
+  const promptId = queued.data.prompt_id;
+  // Dropped from the queue if still waiting, interrupted if already running.
+  const watchdog = startPollWatchdog(`ComfyUI prompt ${promptId}`, async () => {
+    await axios.post(`${COMFYUI_URL}/queue`, { delete: [promptId] });
+    await axios.post(`${COMFYUI_URL}/interrupt`, { prompt_id: promptId });
+  });
+
+  let entry;
+  while (!entry) {
+    await new Promise(resolve => setTimeout(resolve, 1000));
+    await watchdog.check();
+    const history = await axios.get(`${COMFYUI_URL}/history/${promptId}`);
+    entry = history.data[promptId];
+  }
+  if (entry.status?.status_str === "error") {
+    const failure = (entry.status.messages || []).find(([type]) => type === "execution_error");
+    throw new Error(`ComfyUI execution failed: ${failure ? failure[1].exception_message : "unknown error"}`);
+  }
+
+  const images = [];
+  for (const output of Object.values(entry.outputs || {})) {
+    for (const image of output.images || []) {
+      if (image.type !== "output") continue;
+      const response = await axios.get(`${COMFYUI_URL}/view`, {
+        params: { filename: image.filename, subfolder: image.subfolder, type: image.type },
+        responseType: "arraybuffer",
+      });
+      images.push(Buffer.from(response.data));
+    }
+  }
+  if (images.length === 0) throw new Error("ComfyUI workflow produced no output images (does it end in a SaveImage node?)");
+  return { images, metadata: { prompt_id: promptId } };
+}
+
+function buildComfyValues(prompt, options) {
+  const { width, height } = getDiffusionSize(options);
+  return {
+    prompt,
+    negative_prompt: options.negativePrompt || "",
+    // ComfyUI skips nodes whose inputs didn't change, so a fresh seed is needed for a new image.
+    seed: options.seed ?? Math.floor(Math.random() * 2 ** 32),
+    width: width ?? 1024,
+    height: height ?? 1024,
+    steps: options.steps,
+    cfg_scale: options.cfgScale,
+    denoise: options.denoisingStrength ?? 0.6,
+    model: options.model,
+  };
+}
+
+async function generateImageComfyUI(prompt, options = {}) {
+  const values = buildComfyValues(prompt, options);
+  const result = await runComfyWorkflow(fillWorkflowPlaceholders(loadComfyWorkflow(COMFYUI_WORKFLOW), values));
+  return { images: result.images, metadata: { ...result.metadata, workflow: COMFYUI_WORKFLOW, seed: values.seed } };
+}
+
+async function editImageComfyUI(base64Image, mimeType, prompt, options = {}) {
+  if (!COMFYUI_EDIT_WORKFLOW) throw new Error("Editing with ComfyUI requires COMFYUI_EDIT_WORKFLOW (a workflow with an {{image}} placeholder).");
+  const values = buildComfyValues(prompt, options);
+  values.image = await uploadComfyImage(Buffer.from(base64Image, "base64"), mimeType);
+  const result = await runComfyWorkflow(fillWorkflowPlaceholders(loadComfyWorkflow(COMFYUI_EDIT_WORKFLOW), values));
+  return { images: result.images, metadata: { ...result.metadata, workflow: COMFYUI_EDIT_WORKFLOW, seed: values.seed } };
+}
+
+// --- Mock Implementation ---
+// Deterministic images with no network access or API key, for development and CI. The same prompt, seed and size always
+// give the same bytes: a two-color gradient derived from a hash of the request, with the prompt printed on it. With
+// MOCK_FIXTURES_DIR set, fixture images from that directory are returned instead, picked by the same hash.
+
+function getMockHash(kind, prompt, options, index) {
+  return sha256(JSON.stringify({ kind, prompt, seed: options.seed ?? 0, index }));
+}
+
+function pickMockFixture(hash) {
+  const files = fs.readdirSync(MOCK_FIXTURES_DIR)
+    .filter(f => /\.(png|jpe?g|webp|gif)$/i.test(f))
+    .sort();
+  if (files.length === 0) throw new Error(`MOCK_FIXTURES_DIR contains no images: ${MOCK_FIXTURES_DIR}`);
+  return path.join(MOCK_FIXTURES_DIR, files[parseInt(hash.slice(0, 8), 16) % files.length]);
+}
+
+async function renderMockImage(prompt, width, height, hash) {
+  const lines = (prompt.match(/.{1,40}(\s|$)/g) || [prompt]).slice(0, 6).map(l => l.trim());
+  const fontSize = Math.max(12, Math.round(Math.min(width, height) / 24));
+  const text = lines.map((line, i) => `<tspan x="50%" dy="${i === 0 ? -((lines.length - 1) * 0.6) : 1.2}em">${escapeXml(line)}</tspan>`).join("");
+  const svg = `<svg xmlns="http://www.w3.org/2000/svg" width="${width}" height="${height}">` +
+    `<defs><linearGradient id="g" x1="0" y1="0" x2="1" y2="1"><stop offset="0" stop-color="#${hash.slice(0, 6)}"/><stop offset="1" stop-color="#${hash.slice(6, 12)}"/></linearGradient></defs>` +
+    `<rect width="100%" height="100%" fill="url(#g)"/>` +
+    `<text x="50%" y="50%" text-anchor="middle" font-family="${SVG_FONT}" font-size="${fontSize}" fill="#ffffff" stroke="#000000" stroke-width="${fontSize / 12}" paint-order="stroke">${text}</text>` +
+    `<text x="8" y="${height - 8}" font-family="${SVG_FONT}" font-size="${Math.max(10, fontSize / 2)}" fill="#ffffff">mock ${hash.slice(0, 12)}</text>` +
+    `</svg>`;
+  return await sharp(Buffer.from(svg)).png().toBuffer();
+}
+
+async function generateImageMock(prompt, options = {}) {
+  if (MOCK_LATENCY_MS > 0) await new Promise(resolve => setTimeout(resolve, MOCK_LATENCY_MS));
+  const { width, height } = getDiffusionSize(options);
+  const images = [];
+  const hashes = [];
+  for (let index = 0; index < (options.numberOfImages || 1); index++) {
+    const hash = getMockHash("generate", prompt, options, index);
+    hashes.push(hash.slice(0, 12));
+    images.push(MOCK_FIXTURES_DIR ? fs.readFileSync(pickMockFixture(hash)) : await renderMockImage(prompt, width ?? 1024, height ?? 1024, hash));
+  }
+  return { images, metadata: { model: MOCK_FIXTURES_DIR ? "mock-fixtures" : "mock", seed: options.seed ?? 0, hashes } };
+}
+
+// Edits keep the source dimensions and rotate its hue by an amount derived from the prompt.
+async function editImageMock(base64Image, mimeType, prompt, options = {}) {
+  if (MOCK_LATENCY_MS > 0) await new Promise(resolve => setTimeout(resolve, MOCK_LATENCY_MS));
+  const hash = getMockHash("edit", prompt, options, 0);
+  const image = await sharp(Buffer.from(base64Image, "base64"))
+    .modulate({ hue: parseInt(hash.slice(0, 4), 16) % 360 })
+    .png()
+    .toBuffer();
+  return { images: [image], metadata: { model: "mock", seed: options.seed ?? 0, hashes: [hash.slice(0, 12)] } };
+}
+
+async function upscaleImageMock(base64Image, mimeType, scale) {
+  if (MOCK_LATENCY_MS > 0) await new Promise(resolve => setTimeout(resolve, MOCK_LATENCY_MS));
+  const source = sharp(Buffer.from(base64Image, "base64"));
+  const { width, height } = await source.metadata();
+  const image = await source.resize(width * scale, height * scale, { kernel: "nearest" }).png().toBuffer();
+  return { images: [image], metadata: { model: "mock", scale } };
+}
+
+// --- Provider Registry ---
+// One entry per backend: whether it is configured, what it supports, and its text-to-image and image-to-image functions
+// (both resolve to { images, metadata }; `edit` is absent when the backend can't edit), plus `upscale(base64Image,
+// mimeType, scale)` for backends with a super-resolution model. Dispatch, list_providers and
+// quality routing all read from here, so a new backend needs an implementation section, a PROVIDERS name and an entry.
+// Capabilities: edit, mask (inpainting with options.mask, a greyscale PNG with white = repaint), reference_images (options.extraImages on edits),
+// negative_prompt, seed, vector (SVG output) and upscale. `defaultModel` is the model used when a request doesn't name one.
+
+const PROVIDER_REGISTRY = {
+  [PROVIDERS.GEMINI]: {
+    configured: () => Boolean(GEMINI_API_KEY),
+    defaultModel: GEMINI_MODEL,
+    capabilities: ["edit", "reference_images"],
+    generate: async (prompt, options) => {
+      if (options.referenceImages && options.referenceImages.length > 0) {
+        const [base, ...extraImages] = options.referenceImages;
+        const guidedPrompt = `Create a new image in the visual style of the reference image(s); do not copy their content.\n\n${prompt}`;
+        return await editImageGemini(base.data, base.mimeType, guidedPrompt, { ...options, extraImages });
+      }
+      return await generateImageGemini(prompt, options);
+    },
+    edit: editImageGemini,
+  },
+  [PROVIDERS.REPLICATE]: {
+    configured: () => Boolean(REPLICATE_API_TOKEN),
+    defaultModel: REPLICATE_MODEL || "bytedance/sdxl-lightning-4step",
+    capabilities: ["edit", "upscale"],
+    generate: async (prompt, options) => {
+      if (options.model || REPLICATE_MODEL) return await generateImageReplicateModel(options.model || REPLICATE_MODEL, prompt, options);
+      const buf = await generateImageReplicate(prompt);
+      return { images: [buf], metadata: { model: "bytedance/sdxl-lightning-4step" } };
+    },
+    edit: async (base64Image, mimeType, prompt, options = {}) => {
+      // A model override runs that Replicate model with the image as its `image` input.
+      if (options.model) {
+        const output = await runReplicatePrediction(options.model, { prompt, image: `data:${mimeType};base64,${base64Image}`, ...options.replicateInput });
+        return { images: await downloadReplicateImages(output), metadata: { model: options.model } };
+      }
+      const buf = await editImageReplicate(base64Image, mimeType, prompt);
+      return { images: [buf], metadata: { model: "timothybrooks/instruct-pix2pix" } };
+    },
+    upscale: upscaleImageReplicate,
+  },
+  [PROVIDERS.HUGGINGFACE]: {
+    configured: () => Boolean(HUGGING_FACE_TOKEN),
+    defaultModel: HF_MODEL,
+    capabilities: ["negative_prompt", "seed"],
+    generate: async (prompt, options) => {
+      const buf = await generateImageHuggingFace(prompt, options);
+      return { images: [buf], metadata: { model: options.model || HF_ENDPOINT_URL || HF_MODEL } };
+    },
+  },
+  [PROVIDERS.OPENAI]: {
+    configured: () => Boolean(OPENAI_API_KEY),
+    defaultModel: OPENAI_IMAGE_MODEL,
+    // Only gpt-image-1 edits take reference images; DALL-E edits a single image.
+    get capabilities() {
+      return OPENAI_IMAGE_MODEL === "gpt-image-1" ? ["edit", "mask", "reference_images"] : ["edit", "mask"];
+    },
+    generate: (prompt, options) => generateImageOpenAI(prompt, { ...options, provider: PROVIDERS.OPENAI }),
+    edit: (base64Image, mimeType, prompt, options) => editImageOpenAI(base64Image, mimeType, prompt, { ...options, provider: PROVIDERS.OPENAI }),
+  },
+  [PROVIDERS.AZURE_OPENAI]: {
+    configured: () => Boolean((AZURE_OPENAI_API_KEY || AZURE_OPENAI_AD_AUTH) && AZURE_OPENAI_ENDPOINT && AZURE_OPENAI_DEPLOYMENT),
+    defaultModel: AZURE_OPENAI_DEPLOYMENT,
+    get capabilities() {
+      return AZURE_OPENAI_IMAGE_MODEL === "gpt-image-1" ? ["edit", "mask", "reference_images"] : ["edit", "mask"];
+    },
+    generate: (prompt, options) => generateImageOpenAI(prompt, { ...options, provider: PROVIDERS.AZURE_OPENAI }),
+    edit: (base64Image, mimeType, prompt, options) => editImageOpenAI(base64Image, mimeType, prompt, { ...options, provider: PROVIDERS.AZURE_OPENAI }),
+  },
+  [PROVIDERS.STABILITY]: {
+    configured: () => Boolean(STABILITY_API_KEY),
+    defaultModel: STABILITY_MODEL,
+    capabilities: ["edit", "negative_prompt", "seed", "upscale"],
+    generate: generateImageStability,
+    edit: editImageStability,
+    upscale: upscaleImageStability,
+  },
+  [PROVIDERS.FAL]: {
+    configured: () => Boolean(FAL_KEY),
+    defaultModel: FAL_MODEL,
+    capabilities: ["edit", "seed", "upscale"],
+    generate: generateImageFal,
+    edit: editImageFal,
+    upscale: upscaleImageFal,
+  },
+  [PROVIDERS.TOGETHER]: {
+    configured: () => Boolean(TOGETHER_API_KEY),
+    defaultModel: TOGETHER_IMAGE_MODEL,
+    capabilities: ["edit", "negative_prompt", "seed"],
+    generate: generateImageTogether,
+    edit: editImageTogether,
+  },
+  [PROVIDERS.RECRAFT]: {
+    configured: () => Boolean(RECRAFT_API_TOKEN),
+    defaultModel: RECRAFT_MODEL,
+    capabilities: ["edit", "negative_prompt", "vector"],
+    generate: generateImageRecraft,
+    edit: editImageRecraft,
+  },
+  [PROVIDERS.LEONARDO]: {
+    configured: () => Boolean(LEONARDO_API_KEY),
+    defaultModel: LEONARDO_MODEL_ID,
+    capabilities: ["edit", "negative_prompt", "seed"],
+    generate: generateImageLeonardo,
+    edit: editImageLeonardo,
+  },
+  [PROVIDERS.BEDROCK]: {
+    configured: () => Boolean(AWS_ACCESS_KEY_ID && AWS_SECRET_ACCESS_KEY),
+    defaultModel: BEDROCK_IMAGE_MODEL,
+    // Stability models on Bedrock run plain image-to-image; only Titan inpaints.
+    capabilities: BEDROCK_IMAGE_MODEL.startsWith("stability.") ? ["edit", "negative_prompt", "seed"] : ["edit", "mask", "negative_prompt", "seed"],
+    generate: generateImageBedrock,
+    edit: editImageBedrock,
+  },
+  [PROVIDERS.VERTEX]: {
+    configured: () => Boolean(vertexClient),
+    defaultModel: VERTEX_IMAGEN_MODEL,
+    capabilities: ["edit", "mask", "negative_prompt"],
+    generate: generateImageVertex,
+    edit: editImageVertex,
+  },
+  [PROVIDERS.AUTOMATIC1111]: {
+    configured: () => activeProvider === PROVIDERS.AUTOMATIC1111 || Boolean(process.env.AUTOMATIC1111_URL),
+    capabilities: ["edit", "mask", "negative_prompt", "seed", "upscale"],
+    generate: generateImageAutomatic1111,
+    edit: editImageAutomatic1111,
+    upscale: upscaleImageAutomatic1111,
+  },
+  [PROVIDERS.COMFYUI]: {
+    configured: () => Boolean(COMFYUI_WORKFLOW),
+    capabilities: COMFYUI_EDIT_WORKFLOW ? ["edit", "negative_prompt", "seed"] : ["negative_prompt", "seed"],
+    generate: generateImageComfyUI,
+    edit: COMFYUI_EDIT_WORKFLOW ? editImageComfyUI : undefined,
+  },
+  [PROVIDERS.MOCK]: {
+    // Listing it in PROVIDER_FALLBACKS or BALANCED_PROVIDERS opts in as well, e.g. to keep CI runs green when a real provider is down.
+    configured: () => activeProvider === PROVIDERS.MOCK || PROVIDER_FALLBACKS.includes(PROVIDERS.MOCK)
+      || balancedMembers.some(member => member.provider === PROVIDERS.MOCK),
+    capabilities: ["edit", "seed", "upscale"],
+    generate: generateImageMock,
+    edit: editImageMock,
+    upscale: upscaleImageMock,
+  },
+  [PROVIDERS.BALANCED]: {
+    configured: () => balancedMembers.length > 0,
+    // Whatever at least one member can do; each request only goes to members that support it.
+    get capabilities() {
+      return [...new Set(balancedMembers.flatMap(member => getProviderCapabilities(member.provider)))];
+    },
+    generate: (prompt, options) => runBalanced("generate", options, (entry, memberOptions) => entry.generate(prompt, memberOptions)),
+    edit: (base64Image, mimeType, prompt, options) =>
+      runBalanced("edit", options, (entry, memberOptions) => entry.edit(base64Image, mimeType, prompt, memberOptions)),
+    upscale: (base64Image, mimeType, scale) =>
+      runBalanced("upscale", {}, (entry) => entry.upscale(base64Image, mimeType, scale)),
+  },
+};
+
+function getProviderCapabilities(provider) {
+  return PROVIDER_REGISTRY[provider]?.capabilities || [];
+}
+
+// --- Reference Image Cache ---
+// Style-profile and character reference images go out with every request of a batch. Their base64 encodings are kept
+// in memory, keyed by path and dropped when the file's size or mtime changes, up to REFERENCE_CACHE_MB (least recently
+// used first out; 0 disables the cache).
+const referenceImageCache = new Map();
+let referenceImageCacheBytes = 0;
+
+function loadReferenceImage(filePath) {
+    const resolved = path.resolve(filePath);
+    const { mtimeMs, size } = fs.statSync(resolved);
+    const cached = referenceImageCache.get(resolved);
+    if (cached) {
+        referenceImageCache.delete(resolved);
+        if (cached.mtimeMs === mtimeMs && cached.size === size) {
+            referenceImageCache.set(resolved, cached);
+            return cached.image;
+        }
+        referenceImageCacheBytes -= cached.image.data.length;
+    }
+    const image = { mimeType: getMimeType(resolved), data: fs.readFileSync(resolved).toString("base64") };
+    const limit = REFERENCE_CACHE_MB * 1024 * 1024;
+    if (image.data.length <= limit) {
+        referenceImageCache.set(resolved, { mtimeMs, size, image });
+        referenceImageCacheBytes += image.data.length;
+        for (const [key, entry] of referenceImageCache) {
+            if (referenceImageCacheBytes <= limit) break;
+            referenceImageCache.delete(key);
+            referenceImageCacheBytes -= entry.image.data.length;
+        }
+    }
+    return image;
+}
+
+// --- Output Helpers ---
+
+function sha256(buffer) {
+    return crypto.createHash("sha256").update(buffer).digest("hex");
+}
+
+function getSidecarPath(filePath) {
+    return `${filePath}.json`;
+}
+
+function getMimeType(filePath) {
+    const mimeTypes = { ".png": "image/png", ".jpg": "image/jpeg", ".jpeg": "image/jpeg", ".gif": "image/gif", ".webp": "image/webp", ".avif": "image/avif", ".tiff": "image/tiff", ".svg": "image/svg+xml" };
+    return mimeTypes[path.extname(filePath).toLowerCase()] || "image/png";
+}
+
+// For images that only exist in memory; providers return PNG, JPEG, WebP, GIF or (Recraft vectors) SVG.
+function getBufferMimeType(buffer) {
+    if (buffer[0] === 0x89 && buffer.toString("ascii", 1, 4) === "PNG") return "image/png";
+    if (buffer[0] === 0xff && buffer[1] === 0xd8) return "image/jpeg";
+    if (buffer.toString("ascii", 0, 4) === "RIFF" && buffer.toString("ascii", 8, 12) === "WEBP") return "image/webp";
+    if (buffer.toString("ascii", 0, 4) === "GIF8") return "image/gif";
+    if (/^\s*</.test(buffer.toString("utf8", 0, 64))) return "image/svg+xml";
+    return "image/png";
+}
+
+// Writes a `<file>.json` sidecar describing how an output was produced, so downstream tools don't need to ask the server.
+function writeMetadataSidecar(filePath, buffer, metadata, index = 0) {
+    const sidecar = {
+        ...metadata,
+        created_at: new Date().toISOString(),
+        output: {
+            path: filePath,
+            index: index,
+            sha256: sha256(buffer),
+            size: buffer.length,
+        },
+    };
+    fs.writeFileSync(getSidecarPath(filePath), JSON.stringify(sidecar, null, 2) + "\n");
+}
+
+// Saves one or more buffers at `outputPath` (extra ones as `<name>_2.ext`, ...), writing sidecars when enabled.
+function saveImageBuffers(imageBuffers, outputPath, metadata, writeMetadata) {
+    const results = [];
+    const resolvedOutputPath = path.resolve(outputPath);
+    const dir = path.dirname(resolvedOutputPath);
+    const ext = path.extname(resolvedOutputPath);
+    const name = path.basename(resolvedOutputPath, ext);
+
+    if (!fs.existsSync(dir)) {
+        fs.mkdirSync(dir, { recursive: true });
+    }
+
+    imageBuffers.forEach((buf, index) => {
+        let filePath;
+        if (index === 0) {
+            filePath = resolvedOutputPath;
+        } else {
+            filePath = path.join(dir, `${name}_${index + 1}${ext}`);
+        }
+
+        fs.writeFileSync(filePath, buf);
+        if (writeMetadata ?? WRITE_METADATA_SIDECARS) {
+            writeMetadataSidecar(filePath, buf, metadata, index);
+        }
+        results.push(filePath);
+    });
+
+    return results;
+}
+
+// Reads tEXt, zTXt and iTXt chunks from a PNG buffer into a { keyword: text } map.
+function readPngTextChunks(buffer) {
+    const signature = Buffer.from([0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a]);
+    const chunks = {};
+    if (buffer.length < 8 || !buffer.subarray(0, 8).equals(signature)) return chunks;
+
+    let offset = 8;
+    while (offset + 8 <= buffer.length) {
+        const length = buffer.readUInt32BE(offset);
+        const type = buffer.toString("latin1", offset + 4, offset + 8);
+        const data = buffer.subarray(offset + 8, offset + 8 + length);
+        offset += 12 + length;
+
+        try {
+            if (type === "tEXt") {
+                const sep = data.indexOf(0);
+                chunks[data.toString("latin1", 0, sep)] = data.toString("latin1", sep + 1);
+            } else if (type === "zTXt") {
+                const sep = data.indexOf(0);
+                chunks[data.toString("latin1", 0, sep)] = zlib.inflateSync(data.subarray(sep + 2)).toString("latin1");
+            } else if (type === "iTXt") {
+                const sep = data.indexOf(0);
+                const keyword = data.toString("latin1", 0, sep);
+                const compressed = data[sep + 1] === 1;
+                const langEnd = data.indexOf(0, sep + 3);
+                const translatedEnd = data.indexOf(0, langEnd + 1);
+                const text = data.subarray(translatedEnd + 1);
+                chunks[keyword] = (compressed ? zlib.inflateSync(text) : text).toString("utf-8");
+            } else if (type === "IEND") {
+                break;
+            }
+        } catch (err) {
+            console.error(`Skipping unreadable PNG ${type} chunk:`, err.message);
+        }
+    }
+
+    return chunks;
+}
+
+// Parses the "parameters" text written by Automatic1111 / SD WebUI.
+function parseSdWebUiParameters(text) {
+    const lines = text.split("\n");
+    const settingsIndex = lines.findIndex(l => /^Steps: /.test(l));
+    const head = settingsIndex === -1 ? lines : lines.slice(0, settingsIndex);
+    const negativeIndex = head.findIndex(l => l.startsWith("Negative prompt:"));
+    const result = {
+        prompt: (negativeIndex === -1 ? head : head.slice(0, negativeIndex)).join("\n").trim(),
+    };
+    if (negativeIndex !== -1) {
+        result.negative_prompt = head.slice(negativeIndex).join("\n").replace(/^Negative prompt:\s*/, "").trim();
+    }
+    if (settingsIndex !== -1) {
+        for (const pair of lines.slice(settingsIndex).join(", ").split(/,\s*(?=[A-Za-z ]+: )/)) {
+            const [key, ...rest] = pair.split(": ");
+            if (key === "Seed") result.seed = Number(rest.join(": "));
+            else if (key === "Model") result.model = rest.join(": ").trim();
+            else if (key === "Steps") result.steps = Number(rest.join(": "));
+            else if (key === "Sampler") result.sampler = rest.join(": ").trim();
+            else if (key === "CFG scale") result.cfg_scale = Number(rest.join(": "));
+        }
+    }
+    return result;
+}
+
+// Looks for generation metadata next to (sidecar) or inside (PNG text chunks) an image. Returns null if none is found.
+function findGenerationMetadata(imagePath) {
+    const resolvedPath = path.resolve(imagePath);
+    const sidecarPath = getSidecarPath(resolvedPath);
+
+    if (fs.existsSync(sidecarPath)) {
+        let sidecar;
+        try {
+            sidecar = JSON.parse(fs.readFileSync(sidecarPath, "utf-8"));
+        } catch (error) {
+            return { source: "sidecar", sidecar_path: sidecarPath, error: `Unreadable sidecar: ${error.message}` };
+        }
+        return {
+            source: "sidecar",
+            sidecar_path: sidecarPath,
+            prompt: sidecar.request?.prompt,
+            model: sidecar.provider_metadata?.model,
+            provider: sidecar.provider,
+            seed: sidecar.request?.seed,
+            tool: sidecar.tool,
+            request: sidecar.request,
+            hash_matches: sidecar.output?.sha256 ? sidecar.output.sha256 === sha256(fs.readFileSync(resolvedPath)) : undefined,
+        };
+    }
+
+    if (path.extname(resolvedPath).toLowerCase() === ".png") {
+        const chunks = readPngTextChunks(fs.readFileSync(resolvedPath));
+        if (chunks.parameters) {
+            return { source: "png:parameters", ...parseSdWebUiParameters(chunks.parameters), raw: chunks };
+        }
+        if (Object.keys(chunks).length > 0) {
+            const prompt = chunks.prompt || chunks.Description || chunks.Comment;
+            return { source: "png:text", prompt: prompt, raw: chunks };
+        }
+    }
+
+    return null;
+}
+
+// Sidecars record the camelCase options the tool ran with; map them back to the names in the tool's schema.
+function toToolArguments(toolName, request) {
+    const properties = tools.find(t => t.name === toolName)?.inputSchema.properties || {};
+    const args = {};
+    for (const [key, value] of Object.entries(request)) {
+        const snakeKey = key.replace(/[A-Z]/g, c => `_${c.toLowerCase()}`);
+        args[key in properties || !(snakeKey in properties) ? key : snakeKey] = value;
+    }
+    return args;
+}
+
+async function readMetadata(imagePath) {
+    try {
+        const resolvedPath = path.resolve(imagePath);
+        if (!fs.existsSync(resolvedPath)) throw new Error(`Image file not found: ${resolvedPath}`);
+
+        const metadata = findGenerationMetadata(resolvedPath);
+        if (!metadata) {
+            return { success: true, found: false, message: "No generation metadata found (no sidecar or PNG text chunks)." };
+        }
+
+        const result = { success: true, found: true, metadata: metadata };
+        if (metadata.tool && metadata.request) {
+            result.regenerate = { tool: metadata.tool, arguments: toToolArguments(metadata.tool, metadata.request) };
+        } else if (metadata.prompt) {
+            result.regenerate = { tool: "generate_image_from_text", arguments: { prompt: metadata.prompt } };
+        }
+        return result;
+    } catch (error) {
+        console.error("Error reading metadata:", error.message);
+        throw error;
+    }
+}
+
+
+// --- Vision (Image Understanding) ---
+
+async function describeImage(imagePath, instruction, options = {}) {
+    const resolvedPath = path.resolve(imagePath);
+    if (!fs.existsSync(resolvedPath)) throw new Error(`Image file not found: ${resolvedPath}`);
+    const base64Image = fs.readFileSync(resolvedPath).toString("base64");
+    return await describeImageData(base64Image, getMimeType(resolvedPath), instruction, options);
+}
+
+function buildAltTextPrompt(maxLength = 150, context) {
+    return `Write alt text for this image for screen-reader users. Describe the essential content and purpose in one or two plain sentences, at most ${maxLength} characters. ` +
+        `Do not start with "Image of" or "Picture of", do not mention that it is AI-generated, and return only the alt text.` +
+        (context ? ` The image is used in this context: ${context}.` : "");
+}
+
+async function generateAltText(imagePath, maxLength = 150, context) {
+    const text = await describeImage(imagePath, buildAltTextPrompt(maxLength, context));
+    return text.replace(/^["']|["']$/g, "").trim();
+}
+
+// Adds `alt_text` to an existing sidecar, or creates a minimal one when `create` is set.
+function storeAltTextInSidecar(filePath, altText, create = false) {
+    const sidecarPath = getSidecarPath(filePath);
+    if (!fs.existsSync(sidecarPath) && !create) return false;
+    const sidecar = fs.existsSync(sidecarPath) ? JSON.parse(fs.readFileSync(sidecarPath, "utf-8")) : {};
+    sidecar.alt_text = altText;
+    fs.writeFileSync(sidecarPath, JSON.stringify(sidecar, null, 2) + "\n");
+    return true;
+}
+
+// Generates alt text for every output of a tool result, storing it in sidecars that were written for them.
+// The images are already saved, so a failed description is reported per image instead of failing the whole call.
+async function addAltTexts(result) {
+    result.alt_texts = {};
+    const errors = {};
+    for (const outputPath of result.output_paths || []) {
+        try {
+            const altText = await generateAltText(outputPath);
+            result.alt_texts[outputPath] = altText;
+            storeAltTextInSidecar(outputPath, altText);
+        } catch (error) {
+            console.error(`Alt text failed for ${outputPath}:`, error.message);
+            errors[outputPath] = error.message;
+        }
+    }
+    if (Object.keys(errors).length > 0) result.alt_text_errors = errors;
+    return result;
+}
+
+async function generateAltTextTool(imagePath, maxLength, context, writeMetadata) {
+    try {
+        const resolvedPath = path.resolve(imagePath);
+        const altText = await generateAltText(resolvedPath, maxLength, context);
+        const stored = storeAltTextInSidecar(resolvedPath, altText, writeMetadata ?? WRITE_METADATA_SIDECARS);
+        return {
+            success: true,
+            alt_text: altText,
+            sidecar_path: stored ? getSidecarPath(resolvedPath) : undefined,
+            message: "Alt text generated."
+        };
+    } catch (error) {
+        console.error("Error generating alt text:", error.message);
+        throw error;
+    }
+}
+
+const DETECTION_SCHEMA = {
+    type: Type.ARRAY,
+    items: {
+        type: Type.OBJECT,
+        properties: {
+            label: { type: Type.STRING },
+            box_2d: { type: Type.ARRAY, items: { type: Type.INTEGER } },
+        },
+        required: ["label", "box_2d"],
+    },
+};
+
+async function detectObjects(imagePath, labels, maxObjects = 25, annotatePath) {
+    try {
+        const resolvedPath = path.resolve(imagePath);
+        if (!fs.existsSync(resolvedPath)) throw new Error(`Image file not found: ${resolvedPath}`);
+        const { width, height } = await sharp(resolvedPath).metadata();
+
+        const target = labels && labels.length > 0 ? `every instance of: ${labels.join(", ")}` : "the prominent objects";
+        const instruction = `Detect ${target} in this image (at most ${maxObjects}). ` +
+            "For each, return a short label and box_2d as [ymin, xmin, ymax, xmax] normalized to 0-1000. Use distinct labels for distinct instances where helpful (e.g., 'person (left)').";
+        const text = await describeImage(resolvedPath, instruction, { responseSchema: DETECTION_SCHEMA });
+
+        let detections;
+        try {
+            detections = JSON.parse(text.replace(/^```(json)?/, "").replace(/```$/, ""));
+        } catch (err) {
+            throw new Error(`Vision model returned invalid JSON: ${text.slice(0, 200)}`);
+        }
+
+        // Gemini boxes are [ymin, xmin, ymax, xmax] on a 0-1000 grid.
+        const objects = detections
+            .filter(d => Array.isArray(d.box_2d) && d.box_2d.length === 4)
+            .slice(0, maxObjects)
+            .map(d => {
+                const [ymin, xmin, ymax, xmax] = d.box_2d.map(v => Math.max(0, Math.min(1000, v)) / 1000);
+                return {
+                    label: d.label,
+                    box: {
+                        x: Math.round(xmin * width),
+                        y: Math.round(ymin * height),
+                        width: Math.round((xmax - xmin) * width),
+                        height: Math.round((ymax - ymin) * height),
+                    },
+                    box_normalized: { x: +xmin.toFixed(4), y: +ymin.toFixed(4), width: +(xmax - xmin).toFixed(4), height: +(ymax - ymin).toFixed(4) },
+                };
+            });
+
+        const result = { success: true, image_width: width, image_height: height, objects: objects, message: `Detected ${objects.length} object(s).` };
+
+        if (annotatePath) {
+            const stroke = Math.max(2, Math.round(Math.min(width, height) / 250));
+            const fontSize = Math.max(12, Math.round(Math.min(width, height) / 40));
+            const shapes = objects.map((o, i) => {
+                const color = CHART_PALETTE[i % CHART_PALETTE.length];
+                return `<rect x="${o.box.x}" y="${o.box.y}" width="${o.box.width}" height="${o.box.height}" fill="none" stroke="${color}" stroke-width="${stroke}"/>` +
+                    `<text x="${o.box.x + stroke}" y="${Math.max(fontSize, o.box.y - stroke)}" font-family="${SVG_FONT}" font-size="${fontSize}" fill="${color}" stroke="#000" stroke-width="${stroke / 2}" paint-order="stroke">${escapeXml(o.label)}</text>`;
+            }).join("");
+            const overlay = `<svg xmlns="http://www.w3.org/2000/svg" width="${width}" height="${height}">${shapes}</svg>`;
+            const resolvedAnnotatePath = path.resolve(annotatePath);
+            fs.mkdirSync(path.dirname(resolvedAnnotatePath), { recursive: true });
+            await sharp(resolvedPath).composite([{ input: Buffer.from(overlay), top: 0, left: 0 }]).png().toFile(resolvedAnnotatePath);
+            result.output_path = resolvedAnnotatePath;
+        }
+
+        return result;
+    } catch (error) {
+        console.error("Error detecting objects:", error.message);
+        throw error;
+    }
+}
+
+
+// --- Main Tool Logic ---
+
+const tools = [
+  {
+    name: "generate_image_from_text",
+    description: "Generate an image from a text description. Note: When using Gemini, it only supports specific resolutions and PNG format.",
+    inputSchema: {
+      type: "object",
+      properties: {
+        prompt: { type: "string", description: "Detailed description of the image." },
+        output_path: { type: "string", description: "Path where the generated image will be saved." },
+        write_metadata: { type: "boolean", description: "Write a .json metadata sidecar next to each output. Defaults to WRITE_METADATA_SIDECARS." },
+        alt_text: { type: "boolean", description: "Also generate accessibility alt text for each output (stored in sidecars and returned as alt_texts)." },
+        style_profile: { type: "string", description: "Name of a saved style profile (see create_style_profile) to apply." },
+        safe_rewrite: { type: "string", enum: ["soften", "llm", "off"], description: "How to retry if the provider blocks the prompt on content-policy grounds. Defaults to SAFE_REWRITE_STRATEGY." },
+        quality: { type: "string", enum: ["draft", "standard", "best"], description: "Quality tier; the request goes to the cheapest model in MODEL_ROUTES meeting it, on any configured provider. Use 'draft' for iterations and batches." },
+        replicate_model: { type: "string", description: "Run this request on any Replicate model instead of the active provider: 'owner/name' or 'owner/name:version' (e.g., 'black-forest-labs/flux-schnell'). Requires REPLICATE_API_TOKEN." },
+        replicate_input: { type: "object", description: "Extra model-specific inputs for replicate_model (e.g., { \"num_inference_steps\": 4, \"output_format\": \"png\" })." },
+        http_headers: { type: "object", description: "Extra HTTP headers for the provider requests of this call (e.g., gateway routing keys)." },
+        http_query: { type: "object", description: "Extra query parameters for the provider requests of this call (not supported by Gemini)." },
+        max_upload_rate: { type: "number", description: "Upload bandwidth limit for this call in KB/s (0 = unlimited). Overrides MAX_UPLOAD_RATE. Not applied to Gemini and Vertex AI requests, which go through the Google SDK." },
+        max_download_rate: { type: "number", description: "Download bandwidth limit for this call in KB/s (0 = unlimited). Overrides MAX_DOWNLOAD_RATE. Not applied to Gemini and Vertex AI requests, which go through the Google SDK." },
+      },
+      required: ["prompt"],
+    },
+  },
+  {
+    name: "generate_image",
+    description: "Generate image(s) from a text description and return them directly as image content. Saving to disk is optional.",
+    inputSchema: {
+      type: "object",
+      properties: {
+        prompt: { type: "string", description: "Detailed description of the image." },
+        negative_prompt: { type: "string", description: "What the image should not contain (ignored by providers without negative prompts)." },
+        size: { type: "string", description: "Size in WIDTHxHEIGHT format, e.g., '1024x768'. Providers with fixed sizes use the closest aspect ratio." },
+        aspect_ratio: { type: "string", description: "Aspect ratio (e.g., '16:9') when size is not given." },
+        count: { type: "number", description: "Number of images to generate (default 1)." },
+        seed: { type: "number", description: "Seed for reproducible results, where the provider supports it." },
+        output_path: { type: "string", description: "Also save the images here (further images get _2, _3, ... suffixes)." },
+        write_metadata: { type: "boolean", description: "Write a .json metadata sidecar next to each saved output. Defaults to WRITE_METADATA_SIDECARS." },
+        style_profile: { type: "string", description: "Name of a saved style profile (see create_style_profile) to apply." },
+        safe_rewrite: { type: "string", enum: ["soften", "llm", "off"], description: "How to retry if the provider blocks the prompt on content-policy grounds. Defaults to SAFE_REWRITE_STRATEGY." },
+      },
+      required: ["prompt"],
+    },
+  },
+  {
+    name: "generate_svg_from_text",
+    description: "Generate an SVG vector image from a text description with Recraft V3 (the Recraft API when RECRAFT_API_TOKEN is set, otherwise the recraft-v3-svg model on Replicate).",
+    inputSchema: {
+      type: "object",
+      properties: {
+        prompt: { type: "string", description: "Detailed description of the SVG image." },
+        output_path: { type: "string", description: "Path where the generated SVG will be saved. Defaults to output.svg." },
+        size: { type: "string", description: "Size of the image in WIDTHxHEIGHT format, e.g., '1024x1024'." },
+        style: { type: "string", description: "Style parameter supported by Recraft (e.g., 'any')." },
+        aspect_ratio: { type: "string", description: "Aspect ratio parameter for Recraft, or 'Not set' to disable." },
+        write_metadata: { type: "boolean", description: "Write a .json metadata sidecar next to each output. Defaults to WRITE_METADATA_SIDECARS." },
+        return_content: { type: "boolean", description: "Also return the SVG itself as an image/svg+xml content block, for clients that display or embed it directly." },
+      },
+      required: ["prompt"],
+    },
+  },
+  {
+    name: "edit_image",
+    description: "Edit an existing image based on your prompt. Note: This tool ONLY alters the visual content of the image; it does NOT change the image format or dimensions.",
+    inputSchema: {
+      type: "object",
+      properties: {
+        image_path: { type: "string", description: "Path to the image file." },
+        prompt: { type: "string", description: "Instructions for editing." },
+        output_path: { type: "string", description: "Path where the generated image will be saved." },
+        write_metadata: { type: "boolean", description: "Write a .json metadata sidecar next to each output. Defaults to WRITE_METADATA_SIDECARS." },
+        alt_text: { type: "boolean", description: "Also generate accessibility alt text for each output (stored in sidecars and returned as alt_texts)." },
+        style_profile: { type: "string", description: "Name of a saved style profile (see create_style_profile) whose style the edit should follow." },
+        http_headers: { type: "object", description: "Extra HTTP headers for the provider requests of this call (e.g., gateway routing keys)." },
+        http_query: { type: "object", description: "Extra query parameters for the provider requests of this call (not supported by Gemini)." },
+        max_upload_rate: { type: "number", description: "Upload bandwidth limit for this call in KB/s (0 = unlimited). Overrides MAX_UPLOAD_RATE. Not applied to Gemini and Vertex AI requests, which go through the Google SDK." },
+        max_download_rate: { type: "number", description: "Download bandwidth limit for this call in KB/s (0 = unlimited). Overrides MAX_DOWNLOAD_RATE. Not applied to Gemini and Vertex AI requests, which go through the Google SDK." },
+        safe_rewrite: { type: "string", enum: ["soften", "llm", "off"], description: "How to retry if the provider blocks the edit on content-policy grounds. Defaults to SAFE_REWRITE_STRATEGY." },
+      },
+      required: ["image_path", "prompt"],
+    },
+  },
+  {
+    name: "remove_background",
+    description: "Remove the background from an image.",
+    inputSchema: {
+      type: "object",
+      properties: {
+        image_path: { type: "string", description: "Path to the image file." },
+        output_path: { type: "string", description: "Path where the transparent image will be saved." },
+        write_metadata: { type: "boolean", description: "Write a .json metadata sidecar next to each output. Defaults to WRITE_METADATA_SIDECARS." },
+      },
+      required: ["image_path"],
+    },
+  },
+  {
+    name: "convert_image_format",
+    description: "Convert an image to a different format (e.g., PNG, JPEG, WEBP, GIF, TIFF, AVIF).",
+    inputSchema: {
+        type: "object",
+        properties: {
+            source_path: { type: "string", description: "Path to the source image." },
+            output_path: { type: "string", description: "Path where the converted image will be saved. If not provided, will save in the same directory with new extension." },
+            format: { type: "string", description: "Target format (png, jpeg, jpg, webp, gif, tiff, avif)." }
+        },
+        required: ["source_path", "format"]
+    }
+  },
+  {
+    name: "resize_image",
+    description: "Resize an image to specific dimensions.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            source_path: { type: "string", description: "Path to the source image." },
+            output_path: { type: "string", description: "Path where the resized image will be saved." },
+            width: { type: "number", description: "Target width in pixels." },
+            height: { type: "number", description: "Target height in pixels. Optional if preserving aspect ratio." },
+            fit: { type: "string", description: "How the image should be resized to fit the dimensions (cover, contain, fill, inside, outside). Default is 'cover'." }
+        },
+        required: ["source_path", "width"]
+    }
+  },
+  {
+    name: "export_project",
+    description: "Export images into a git/LFS-friendly project folder: stable sequential file names, a metadata sidecar per image, a manifest.json with hashes and a .gitattributes routing images through Git LFS.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            image_paths: { type: "array", items: { type: "string" }, description: "Images to export, in order. Alternatively use source_dir." },
+            source_dir: { type: "string", description: "Directory whose images are exported in file-name order." },
+            output_dir: { type: "string", description: "Project directory to write into." },
+            name_prefix: { type: "string", description: "Prefix for the stable file names (e.g., 'chapter-01'). Default is 'page'." },
+            locale: { type: "string", description: "BCP 47 locale (e.g., 'ja-JP', 'ar-EG') for the human-readable numbers and dates added to the manifest. Default is OUTPUT_LOCALE; none when unset." }
+        },
+        required: ["output_dir"]
+    }
+  },
+  {
+    name: "export_web_images",
+    description: "Export images as responsive, size-optimized sets for web readers: several widths in AVIF and WebP plus a JPEG fallback, with a web-manifest.json describing every variant.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            image_paths: { type: "array", items: { type: "string" }, description: "Images to export, in order. Alternatively use source_dir." },
+            source_dir: { type: "string", description: "Directory whose images are exported in file-name order." },
+            output_dir: { type: "string", description: "Directory to write the image sets and manifest into." },
+            widths: { type: "array", items: { type: "number" }, description: "Target widths in pixels. Default is [480, 960, 1600]. Images are never enlarged." },
+            formats: { type: "array", items: { type: "string" }, description: "Formats to produce, best first (avif, webp, jpeg, png). Default is ['avif', 'webp', 'jpeg']; the last one is the fallback." },
+            quality: { type: "number", description: "Encoder quality (1-100). Default is 75." },
+            locale: { type: "string", description: "BCP 47 locale (e.g., 'ja-JP', 'ar-EG') for the human-readable sizes added to the manifest. Default is OUTPUT_LOCALE; none when unset." }
+        },
+        required: ["output_dir"]
+    }
+  },
+  {
+    name: "read_metadata",
+    description: "Recover generation parameters (prompt, model, seed, provider) from an image's .json sidecar or embedded PNG text chunks, including ready-to-use arguments for regenerating it.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            image_path: { type: "string", description: "Path to the image file." }
+        },
+        required: ["image_path"]
+    }
+  },
+  {
+    name: "get_image_info",
+    description: "Get metadata about an image (dimensions, format, etc.).",
+    inputSchema: {
+        type: "object",
+        properties: {
+            image_path: { type: "string", description: "Path to the image file." }
+        },
+        required: ["image_path"]
+    }
+  },
+  {
+    name: "smart_crop",
+    description: "Crop an image to one or more target aspect ratios (e.g., 1:1, 9:16, 16:9) around its most salient region, or extend it to the ratio with outpainting, so one image can be published across platforms.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            image_path: { type: "string", description: "Path to the image file." },
+            aspect_ratios: { type: "array", items: { type: "string" }, description: "Target aspect ratios as 'W:H'. Default is ['1:1', '9:16', '16:9']." },
+            mode: { type: "string", description: "'crop' (default) keeps the most salient region; 'extend' pads the image to the ratio and outpaints the new area with the image model." },
+            output_dir: { type: "string", description: "Directory for the results. Defaults to the source image's directory." }
+        },
+        required: ["image_path"]
+    }
+  },
+  {
+    name: "generate_avatar",
+    description: "Generate an avatar/profile image with face-centric framing, a consistent background and multi-size export (e.g., 512, 256, 128, 64 px).",
+    inputSchema: {
+        type: "object",
+        properties: {
+            subject: { type: "string", description: "Who or what the avatar depicts (e.g., 'a friendly robot barista', 'a woman with short red hair')." },
+            style: { type: "string", description: "Visual style (e.g., 'photorealistic', 'flat vector', '3D render', 'anime', 'pixel art'). Default is 'photorealistic'." },
+            background: { type: "string", description: "Background: 'solid' (default), 'gradient', 'transparent' or a free-form description." },
+            background_color: { type: "string", description: "Color for solid/gradient backgrounds (e.g., 'pastel blue', '#223344')." },
+            shape: { type: "string", description: "'square' (default) or 'circle' (transparent corners)." },
+            sizes: { type: "array", items: { type: "number" }, description: "Square sizes to export in pixels. Default is [512, 256, 128, 64]." },
+            output_dir: { type: "string", description: "Directory for the avatar files. Default is the current directory." },
+            name: { type: "string", description: "Base file name. Default is 'avatar'." }
+        },
+        required: ["subject"]
+    }
+  },
+  {
+    name: "render_chart",
+    description: "Render an exact bar, line or pie chart from JSON data locally (no AI model) and save it as PNG or SVG.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            type: { type: "string", description: "Chart type: 'bar', 'line' or 'pie'." },
+            labels: { type: "array", items: { type: "string" }, description: "Category labels (x-axis for bar/line, slices for pie)." },
+            series: {
+                type: "array",
+                description: "Data series. Pie charts use the first series only.",
+                items: {
+                    type: "object",
+                    properties: {
+                        name: { type: "string", description: "Series name shown in the legend." },
+                        values: { type: "array", items: { type: "number" }, description: "One value per label." },
+                        color: { type: "string", description: "Optional CSS color. For pie charts, the color of the first slice." },
+                        colors: { type: "array", items: { type: "string" }, description: "Optional CSS colors, one per slice (pie charts only)." }
+                    },
+                    required: ["values"]
+                }
+            },
+            title: { type: "string", description: "Optional chart title." },
+            x_label: { type: "string", description: "Optional x-axis title (bar/line)." },
+            y_label: { type: "string", description: "Optional y-axis title (bar/line)." },
+            width: { type: "number", description: "Width in pixels. Default is 800." },
+            height: { type: "number", description: "Height in pixels. Default is 500." },
+            format: { type: "string", description: "'png' (default) or 'svg'." },
+            output_path: { type: "string", description: "Path where the chart will be saved. Default is chart.png / chart.svg." }
+        },
+        required: ["type", "labels", "series"]
+    }
+  },
+  {
+    name: "render_diagram",
+    description: "Render a Mermaid or Graphviz (DOT) diagram from source to PNG or SVG using the locally installed renderer (mmdc / dot).",
+    inputSchema: {
+        type: "object",
+        properties: {
+            source: { type: "string", description: "Diagram source code." },
+            language: { type: "string", description: "'mermaid' or 'dot'. Detected from the source when omitted." },
+            format: { type: "string", description: "'png' (default) or 'svg'." },
+            theme: { type: "string", description: "Mermaid theme (default, dark, forest, neutral)." },
+            scale: { type: "number", description: "Scale factor for PNG output. Default is 2." },
+            output_path: { type: "string", description: "Path where the diagram will be saved. Default is diagram.png / diagram.svg." }
+        },
+        required: ["source"]
+    }
+  },
+  {
+    name: "render_math",
+    description: "Render a LaTeX math snippet to PNG or SVG using the local TeX installation (latex + dvisvgm).",
+    inputSchema: {
+        type: "object",
+        properties: {
+            latex: { type: "string", description: "LaTeX math, without surrounding $ (e.g., '\\int_0^1 x^2\\,dx = \\frac{1}{3}')." },
+            display: { type: "boolean", description: "Use display style (larger operators). Default is true." },
+            color: { type: "string", description: "Text color as a hex value (e.g., '#000000'). Default is black." },
+            scale: { type: "number", description: "Scale factor for PNG output. Default is 4." },
+            format: { type: "string", description: "'png' (default) or 'svg'." },
+            output_path: { type: "string", description: "Path where the formula will be saved. Default is formula.png / formula.svg." }
+        },
+        required: ["latex"]
+    }
+  },
+  {
+    name: "render_html",
+    description: "Rasterize HTML/CSS to a PNG at a given viewport using a local headless Chrome/Chromium (social cards, report snapshots).",
+    inputSchema: {
+        type: "object",
+        properties: {
+            html: { type: "string", description: "HTML markup. A full document or a fragment (wrapped in a basic document)." },
+            css: { type: "string", description: "Optional CSS injected into the document head." },
+            html_path: { type: "string", description: "Alternatively, path to an HTML file to render (relative assets resolve next to it)." },
+            width: { type: "number", description: "Viewport width in CSS pixels. Default is 1200." },
+            height: { type: "number", description: "Viewport height in CSS pixels. Default is 630." },
+            scale: { type: "number", description: "Device scale factor (2 for retina-quality output). Default is 1." },
+            output_path: { type: "string", description: "Path where the PNG will be saved. Default is render.png." }
+        }
+    }
+  },
+  {
+    name: "render_code",
+    description: "Render syntax-highlighted source code to a styled, carbon-like image (window frame, theme, optional line numbers) locally, without an AI model.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            code: { type: "string", description: "Source code to render." },
+            language: { type: "string", description: "Language for highlighting (javascript, typescript, python, rust, go, java, c, cpp, csharp, bash, sql, json, ...). Default is plain text." },
+            theme: { type: "string", description: "'dark' (default) or 'light'." },
+            title: { type: "string", description: "Optional title shown in the window bar (e.g., a file name)." },
+            line_numbers: { type: "boolean", description: "Show line numbers. Default is false." },
+            font_size: { type: "number", description: "Font size in pixels. Default is 16." },
+            background: { type: "string", description: "Outer background CSS color. Default is a theme-matching gradient; 'none' for no padding frame." },
+            scale: { type: "number", description: "Scale factor for PNG output. Default is 2." },
+            format: { type: "string", description: "'png' (default) or 'svg'." },
+            output_path: { type: "string", description: "Path where the image will be saved. Default is code.png / code.svg." }
+        },
+        required: ["code"]
+    }
+  },
+  {
+    name: "make_meme",
+    description: "Apply a meme/caption template locally to an image: classic top/bottom Impact text, or a padded caption bar above the image. Can generate the base image from a prompt first.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            image_path: { type: "string", description: "Path to the base image. Alternatively use prompt." },
+            prompt: { type: "string", description: "Generate the base image from this prompt with the active provider." },
+            template: { type: "string", description: "'top_bottom' (default, Impact text over the image) or 'caption' (text on a white bar above the image)." },
+            top_text: { type: "string", description: "Top text (top_bottom template)." },
+            bottom_text: { type: "string", description: "Bottom text (top_bottom template)." },
+            caption: { type: "string", description: "Caption text (caption template)." },
+            output_path: { type: "string", description: "Path where the meme will be saved. Default is meme.png." }
+        }
+    }
+  },
+  {
+    name: "placeholder",
+    description: "Create a deterministic placeholder image (solid, gradient or pattern background with a size label) locally, without any API call.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            width: { type: "number", description: "Width in pixels." },
+            height: { type: "number", description: "Height in pixels. Defaults to width." },
+            style: { type: "string", description: "'solid' (default), 'gradient', 'stripes', 'checker' or 'grid'." },
+            color: { type: "string", description: "Primary CSS color. Default is '#cccccc'." },
+            color2: { type: "string", description: "Secondary color for gradients and patterns. Default is '#999999'." },
+            label: { type: "string", description: "Label text. Defaults to 'WIDTHxHEIGHT'; use an empty string for no label." },
+            text_color: { type: "string", description: "Label color. Default is '#333333'." },
+            format: { type: "string", description: "'png' (default) or 'svg'." },
+            output_path: { type: "string", description: "Path where the placeholder will be saved. Default is placeholder.png / placeholder.svg." }
+        },
+        required: ["width"]
+    }
+  },
+  {
+    name: "simulate_color_blindness",
+    description: "Simulate how an image looks with protanopia, deuteranopia, tritanopia and achromatopsia, and report color pairs that become hard to tell apart plus low-contrast dominant colors.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            image_path: { type: "string", description: "Path to the image file." },
+            types: { type: "array", items: { type: "string" }, description: "Simulations to run. Default is ['protanopia', 'deuteranopia', 'tritanopia']; 'achromatopsia' is also available." },
+            output_dir: { type: "string", description: "Directory for the simulated images. Defaults to the source image's directory." }
+        },
+        required: ["image_path"]
+    }
+  },
+  {
+    name: "analyze_colors",
+    description: "Report an image's dominant colors with coverage percentages and, when a brand palette is supplied, each color's distance to the nearest brand color and the share of the image that is on-brand.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            image_path: { type: "string", description: "Path to the image file." },
+            count: { type: "number", description: "Number of dominant colors to extract. Default is 6." },
+            palette: { type: "array", items: { type: "string" }, description: "Brand palette as hex colors (e.g., ['#0a2540', '#635bff'])." },
+            tolerance: { type: "number", description: "Maximum color difference (CIE ΔE) for a color to count as on-brand. Default is 10." }
+        },
+        required: ["image_path"]
+    }
+  },
+  {
+    name: "find_duplicates",
+    description: "Scan a directory for near-duplicate images using perceptual hashing (pHash) and report clusters of visually similar files.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            directory: { type: "string", description: "Directory to scan." },
+            recursive: { type: "boolean", description: "Include subdirectories. Default is false." },
+            threshold: { type: "number", description: "Maximum Hamming distance (0-64) between hashes to treat images as duplicates. Default is 8; 0 finds only perceptually identical images." }
+        },
+        required: ["directory"]
+    }
+  },
+  {
+    name: "verify_outputs",
+    description: "Re-hash images against the records written by this server (export_project manifest.json and .json metadata sidecars) and report missing, modified or corrupted files.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            path: { type: "string", description: "Directory to verify, or a specific manifest.json." },
+            recursive: { type: "boolean", description: "Also verify sidecars in subdirectories. Default is false." }
+        },
+        required: ["path"]
+    }
+  },
+  {
+    name: "generate_alt_text",
+    description: "Generate concise accessibility alt text for an image with the vision model, optionally storing it in the image's .json metadata sidecar.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            image_path: { type: "string", description: "Path to the image file." },
+            max_length: { type: "number", description: "Maximum length in characters. Default is 150." },
+            context: { type: "string", description: "Optional context where the image is used (e.g., 'hero image of a pricing page')." },
+            write_metadata: { type: "boolean", description: "Store the alt text in the image's .json sidecar (created if missing). Defaults to WRITE_METADATA_SIDECARS; existing sidecars are always updated." }
+        },
+        required: ["image_path"]
+    }
+  },
+  {
+    name: "detect_objects",
+    description: "Detect objects in an image with the vision model and return labels with pixel bounding boxes, optionally drawing them onto an annotated copy.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            image_path: { type: "string", description: "Path to the image file." },
+            labels: { type: "array", items: { type: "string" }, description: "Only detect these kinds of objects (e.g., ['person', 'speech bubble']). Default is all prominent objects." },
+            max_objects: { type: "number", description: "Maximum number of objects to return. Default is 25." },
+            annotate_path: { type: "string", description: "Optional path for a copy of the image with the boxes drawn on it." }
+        },
+        required: ["image_path"]
+    }
+  },
+  {
+    name: "edit_region",
+    description: "Edit only a region of an image, given as a bounding box or polygon, with a prompt (e.g., 'fix the hand'). The mask is built internally and everything outside the region is kept pixel-identical.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            image_path: { type: "string", description: "Path to the image file." },
+            prompt: { type: "string", description: "What to change or paint inside the region." },
+            box: {
+                type: "object",
+                description: "Region as a pixel bounding box.",
+                properties: {
+                    x: { type: "number" }, y: { type: "number" }, width: { type: "number" }, height: { type: "number" }
+                },
+                required: ["x", "y", "width", "height"]
+            },
+            polygon: { type: "array", items: { type: "array", items: { type: "number" } }, description: "Region as a polygon of [x, y] pixel points (alternative to box)." },
+            feather: { type: "number", description: "Soft edge width in pixels for blending. Default is 8." },
+            output_path: { type: "string", description: "Path where the edited image will be saved. Default is output.png." }
+        },
+        required: ["image_path", "prompt"]
+    }
+  },
+  {
+    name: "inpaint",
+    description: "Repaint the masked area of an image from a prompt on a provider with native mask support (OpenAI, Azure OpenAI, Bedrock Titan, Vertex AI, Automatic1111). The mask is an image (white = repaint) or a rectangle; pixels outside it are kept.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            image_path: { type: "string", description: "Path to the base image." },
+            prompt: { type: "string", description: "What to paint into the masked area." },
+            mask_path: { type: "string", description: "Path to a mask image, white where the image should be repainted. Resized to the base image." },
+            box: {
+                type: "object",
+                description: "Rectangle to repaint in pixels, instead of mask_path.",
+                properties: {
+                    x: { type: "number" }, y: { type: "number" }, width: { type: "number" }, height: { type: "number" }
+                },
+                required: ["x", "y", "width", "height"]
+            },
+            invert_mask: { type: "boolean", description: "Repaint the black area of mask_path instead of the white one." },
+            feather: { type: "number", description: "Soft mask edge in pixels. Default is 0." },
+            negative_prompt: { type: "string", description: "What the repainted area should not contain, where supported." },
+            seed: { type: "number", description: "Seed for reproducible results, where supported." },
+            denoising_strength: { type: "number", description: "How much the masked area may change (0-1, Automatic1111 only)." },
+            output_path: { type: "string", description: "Path where the result will be saved. Default is output.png." },
+            write_metadata: { type: "boolean", description: "Write a .json metadata sidecar next to the output. Defaults to WRITE_METADATA_SIDECARS." }
+        },
+        required: ["image_path", "prompt"]
+    }
+  },
+  {
+    name: "outpaint",
+    description: "Extend an image beyond its borders by N pixels in a chosen direction (e.g., turn a portrait panel into a widescreen wallpaper). The canvas is padded and masked locally and the new area is filled by the provider's image editing; the original pixels are kept.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            image_path: { type: "string", description: "Path to the image file." },
+            direction: { type: "string", enum: ["left", "right", "top", "bottom", "horizontal", "vertical", "all"], description: "Where to extend. 'horizontal' (default) adds N pixels on both the left and the right." },
+            pixels: { type: "number", description: "Pixels to add on each chosen side." },
+            prompt: { type: "string", description: "Optional description of what the extended area should show." },
+            overlap: { type: "number", description: "Pixels of the original next to each new edge that may be repainted for a seamless join. Default is 16." },
+            feather: { type: "number", description: "Soft mask edge in pixels. Default is 8." },
+            denoising_strength: { type: "number", description: "How much the masked area may change (0-1, default 0.9; Automatic1111 only)." },
+            output_path: { type: "string", description: "Path where the result will be saved. Default is output.png." },
+            write_metadata: { type: "boolean", description: "Write a .json metadata sidecar next to the output. Defaults to WRITE_METADATA_SIDECARS." }
+        },
+        required: ["image_path", "pixels"]
+    }
+  },
+  {
+    name: "upscale",
+    description: "Enlarge an image 2x or 4x (e.g., low-resolution colorized comic pages before print). Uses a provider-side super-resolution model (Real-ESRGAN on Replicate, fal.ai or Automatic1111, Stability's fast upscaler) when one is configured, otherwise a local Lanczos resize.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            image_path: { type: "string", description: "Path to the image file." },
+            scale: { type: "number", enum: [2, 4], description: "Scale factor. Default is 2." },
+            local: { type: "boolean", description: "Resize locally (Lanczos) even if a provider upscaler is available. Default is false." },
+            output_path: { type: "string", description: "Path where the result will be saved. Default is <name>_<scale>x.png next to the source." },
+            write_metadata: { type: "boolean", description: "Write a .json metadata sidecar next to the output. Defaults to WRITE_METADATA_SIDECARS." }
+        },
+        required: ["image_path"]
+    }
+  },
+  {
+    name: "style_transfer",
+    description: "Restyle a content image after a style reference image (palette, brushwork, texture, medium) while keeping its composition and subjects. Both images are sent to the provider; needs one that accepts reference images (Gemini, OpenAI gpt-image-1, Azure OpenAI).",
+    inputSchema: {
+        type: "object",
+        properties: {
+            content_image_path: { type: "string", description: "Path to the image whose content is kept." },
+            style_image_path: { type: "string", description: "Path to the image whose style is applied." },
+            strength: { type: "string", enum: ["subtle", "balanced", "strong"], description: "How far to restyle the content image. Default is balanced." },
+            prompt: { type: "string", description: "Optional extra guidance (e.g., 'keep the text in the speech bubbles legible')." },
+            aspect_ratio: { type: "string", description: "Output aspect ratio where the provider supports it (e.g., '1:1', '16:9')." },
+            output_path: { type: "string", description: "Path where the result will be saved. Default is output.png." },
+            write_metadata: { type: "boolean", description: "Write a .json metadata sidecar next to the output. Defaults to WRITE_METADATA_SIDECARS." }
+        },
+        required: ["content_image_path", "style_image_path"]
+    }
+  },
+  {
+    name: "generate_with_pose",
+    description: "Generate a character in a specified pose from a pose reference: an OpenPose skeleton image or OpenPose keypoints JSON. Uses Gemini with the skeleton as a reference, or a ControlNet pose model on Replicate (REPLICATE_POSE_MODEL).",
+    inputSchema: {
+        type: "object",
+        properties: {
+            prompt: { type: "string", description: "Description of the character and scene." },
+            pose_image_path: { type: "string", description: "Path to an OpenPose skeleton image (or any pose reference image)." },
+            pose_keypoints: { type: "object", description: "OpenPose JSON ({ people: [{ pose_keypoints_2d: [x, y, c, ...] }], canvas_width, canvas_height }) in the 18-point COCO layout." },
+            skeleton_path: { type: "string", description: "Optional path to save the skeleton rendered from pose_keypoints." },
+            output_path: { type: "string", description: "Path where the generated image will be saved. Default is output.png." }
+        },
+        required: ["prompt"]
+    }
+  },
+  {
+    name: "generate_texture",
+    description: "Generate a seamlessly tileable texture or pattern. The result is made tileable with an offset-blend fixup and checked with a seam-continuity score; an optional 2x2 tiled preview can be written.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            prompt: { type: "string", description: "Texture description (e.g., 'mossy cobblestone', 'art deco wallpaper pattern')." },
+            size: { type: "number", description: "Square texture size in pixels. Default is 1024." },
+            seamless_fix: { type: "boolean", description: "Apply the offset-blend fixup so edges wrap. Default is true." },
+            preview_path: { type: "string", description: "Optional path for a 2x2 tiled preview." },
+            output_path: { type: "string", description: "Path where the texture will be saved. Default is texture.png." }
+        },
+        required: ["prompt"]
+    }
+  },
+  {
+    name: "generate_pbr_textures",
+    description: "Produce a matching PBR texture set (albedo, height, normal, roughness) from a prompt or a base image. The albedo is generated as a seamless texture; the other maps are derived locally.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            prompt: { type: "string", description: "Material description (e.g., 'weathered red brick wall'). Ignored when image_path is given." },
+            image_path: { type: "string", description: "Optional existing albedo/base image to derive the maps from." },
+            size: { type: "number", description: "Square map size in pixels. Default is 1024." },
+            normal_strength: { type: "number", description: "Normal map intensity. Default is 2." },
+            normal_convention: { type: "string", description: "'opengl' (default, green up) or 'directx' (green down)." },
+            output_dir: { type: "string", description: "Directory for the maps. Default is the current directory." },
+            name: { type: "string", description: "Base file name. Default is 'material'." }
+        }
+    }
+  },
+  {
+    name: "generate_storyboard",
+    description: "Generate a storyboard from a scene-by-scene script: one frame per beat with consistent characters, composed into a numbered storyboard sheet plus individual frames.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            beats: {
+                type: "array",
+                description: "Script beats in order. Each item is a description string or { description, caption }.",
+                items: {}
+            },
+            characters: { type: "string", description: "Character/setting descriptions repeated in every frame prompt (e.g., 'Mara: tall woman, green raincoat; Bo: small grey dog')." },
+            reference_image_paths: { type: "array", items: { type: "string" }, description: "Optional character reference images. Without them, the first frame becomes the reference for the rest (providers that take reference images, such as Gemini)." },
+            style: { type: "string", description: "Drawing style. Default is 'rough storyboard sketch, pencil and grey marker'." },
+            style_profile: { type: "string", description: "Name of a saved style profile (see create_style_profile) applied to every frame." },
+            aspect_ratio: { type: "string", description: "Frame aspect ratio. Default is '16:9'." },
+            columns: { type: "number", description: "Frames per row on the sheet. Default is 3." },
+            output_dir: { type: "string", description: "Directory for the frames and sheet. Default is the current directory." },
+            name: { type: "string", description: "Base file name. Default is 'storyboard'." }
+        },
+        required: ["beats"]
+    }
+  },
+  {
+    name: "generate_comic_strip",
+    description: "Generate a finished multi-panel comic strip from panel descriptions and dialogue: each panel is generated with consistent characters, dialogue is typeset into speech bubbles or caption boxes, and the panels are assembled into one strip.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            panels: {
+                type: "array",
+                description: "Panels in reading order: { description, dialogue: [{ speaker, text, type: 'speech' | 'caption' }] }. Dialogue items may also be plain strings.",
+                items: {}
+            },
+            characters: { type: "string", description: "Character descriptions repeated in every panel prompt." },
+            reference_image_paths: { type: "array", items: { type: "string" }, description: "Optional character reference images (providers that take reference images, such as Gemini)." },
+            style: { type: "string", description: "Art style. Default is 'clean western comic art, bold ink lines, flat colors'." },
+            style_profile: { type: "string", description: "Name of a saved style profile (see create_style_profile) applied to every panel." },
+            title: { type: "string", description: "Optional title drawn above the strip." },
+            columns: { type: "number", description: "Panels per row. Default is the number of panels (single row) up to 4." },
+            panel_width: { type: "number", description: "Panel width in pixels. Default is 600." },
+            output_path: { type: "string", description: "Path for the assembled strip. Default is comic.png." }
+        },
+        required: ["panels"]
+    }
+  },
+  {
+    name: "generate_cover",
+    description: "Generate cover art (book, manga volume, album, etc.) with clean title typography rendered locally: title, subtitle, author and volume number, with font selection.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            prompt: { type: "string", description: "Description of the cover artwork. Ignored when image_path is given." },
+            image_path: { type: "string", description: "Optional existing artwork to typeset instead of generating one." },
+            title: { type: "string", description: "Cover title." },
+            subtitle: { type: "string", description: "Optional subtitle." },
+            author: { type: "string", description: "Optional author/artist name." },
+            volume: { type: "string", description: "Optional volume number or label (e.g., '3' or 'Book Two')." },
+            font_family: { type: "string", description: "Title font family (must be installed). Default is a serif stack." },
+            body_font_family: { type: "string", description: "Font family for subtitle, author and volume. Default is the sans-serif stack." },
+            title_position: { type: "string", description: "'top' (default) or 'bottom'. The author goes at the opposite end." },
+            text_color: { type: "string", description: "Text color. Default is white with a dark outline." },
+            aspect_ratio: { type: "string", description: "Cover aspect ratio. Default is '2:3'." },
+            width: { type: "number", description: "Output width in pixels. Default is 1600." },
+            output_path: { type: "string", description: "Path where the cover will be saved. Default is cover.png." }
+        },
+        required: ["title"]
+    }
+  },
+  {
+    name: "describe_as_prompt",
+    description: "Reverse-engineer a generation-ready prompt from an image: subject, medium/style, composition, lighting, lens/camera terms and palette, so you can generate 'more in this style'. Requires GEMINI_API_KEY or VISION_PROVIDER=ollama.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            image_path: { type: "string", description: "Path to the image to analyze." },
+            prompt_format: { type: "string", description: "'natural' (default, descriptive sentences) or 'tags' (comma-separated Stable Diffusion style keywords)." },
+            new_subject: { type: "string", description: "Optional subject to substitute, producing a prompt for a different subject in the same style." },
+            include_subject: { type: "boolean", description: "Include the original subject in the prompt. Default is true; false gives a style-only prompt." }
+        },
+        required: ["image_path"]
+    }
+  },
+  {
+    name: "create_style_profile",
+    description: "Analyze a set of reference images and save a named style profile (text descriptor, palette and a reference grid) that later generations can use via style_profile. Requires GEMINI_API_KEY or VISION_PROVIDER=ollama.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            name: { type: "string", description: "Profile name (letters, digits, '-' and '_')." },
+            image_paths: { type: "array", items: { type: "string" }, description: "Reference images sharing the style (up to 9 are used)." },
+            notes: { type: "string", description: "Optional extra guidance appended to the descriptor (e.g., 'always thick outlines')." }
+        },
+        required: ["name", "image_paths"]
+    }
+  },
+  {
+    name: "list_style_profiles",
+    description: "List saved style profiles.",
+    inputSchema: { type: "object", properties: {} }
+  },
+  {
+    name: "create_experiment",
+    description: "Define an A/B experiment: named variants of a prompt and/or tool parameters to compare on the same items. Saved in EXPERIMENTS_DIR.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            name: { type: "string", description: "Experiment name (letters, digits, '-' and '_')." },
+            description: { type: "string", description: "What is being tested." },
+            variants: {
+                type: "array",
+                description: "At least two variants.",
+                items: {
+                    type: "object",
+                    properties: {
+                        name: { type: "string", description: "Variant name (e.g., 'A', 'warm-palette')." },
+                        prompt: { type: "string", description: "Prompt template; {subject} is replaced by the item's subject. For image items this is the edit instruction." },
+                        params: { type: "object", description: "Extra generate_image_from_text / edit_image arguments for this variant (e.g., { \"quality\": \"best\", \"style_profile\": \"ink\" })." }
+                    },
+                    required: ["name", "prompt"]
+                }
+            }
+        },
+        required: ["name", "variants"]
+    }
+  },
+  {
+    name: "run_experiment",
+    description: "Generate (text subjects) or edit (image paths, e.g. pages) items with an experiment's variants and record each output as a trial.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            name: { type: "string", description: "Experiment name." },
+            subjects: { type: "array", items: { type: "string" }, description: "Text subjects, each generated with generate_image_from_text." },
+            image_paths: { type: "array", items: { type: "string" }, description: "Images (e.g., pages to colorize), each edited with edit_image." },
+            assignment: { type: "string", enum: ["all", "alternate"], description: "'all' (default) runs every item with every variant; 'alternate' assigns items to variants in turn." },
+            output_dir: { type: "string", description: "Directory for the outputs. Defaults to EXPERIMENTS_DIR/<name>." }
+        },
+        required: ["name"]
+    }
+  },
+  {
+    name: "score_experiment",
+    description: "Record a review score for one output of an experiment.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            name: { type: "string", description: "Experiment name." },
+            output_path: { type: "string", description: "Output file returned by run_experiment." },
+            score: { type: "number", description: "Review score (any scale, higher is better; e.g., 1-5)." },
+            notes: { type: "string", description: "Optional reviewer notes." }
+        },
+        required: ["name", "output_path", "score"]
+    }
+  },
+  {
+    name: "experiment_report",
+    description: "Compare an experiment's variants: trials, mean/min/max review scores, head-to-head wins per item, and the leading variant.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            name: { type: "string", description: "Experiment name." }
+        },
+        required: ["name"]
+    }
+  },
+  {
+    name: "contact_sheet",
+    description: "Tile several candidate images into one labeled grid image for quick review in chat clients that only display a single image well.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            image_paths: { type: "array", items: { type: "string" }, description: "Images to include, in order." },
+            source_dir: { type: "string", description: "Alternatively, include every image in this directory." },
+            labels: { type: "array", items: { type: "string" }, description: "Optional labels, one per image. Default is the number and file name." },
+            columns: { type: "number", description: "Images per row. Default is a near-square grid." },
+            cell_size: { type: "number", description: "Maximum width/height of each thumbnail in pixels. Default is 320." },
+            background: { type: "string", description: "Sheet background color. Default is '#1e1e1e'." },
+            locale: { type: "string", description: "BCP 47 locale (e.g., 'ar-EG') used to format the numbers in the default labels. Default is OUTPUT_LOCALE." },
+            output_path: { type: "string", description: "Path for the sheet. Default is contact_sheet.png." }
+        }
+    }
+  },
+  {
+    name: "chapter_summary_page",
+    description: "Create a recap/summary image for a chapter (a directory of page images): key panels are chosen with vision analysis and arranged as a collage with a title and recap text. Requires GEMINI_API_KEY or VISION_PROVIDER=ollama.",
+    inputSchema: {
+        type: "object",
+        properties: {
+            source_dir: { type: "string", description: "Directory containing the chapter's pages (sorted by file name)." },
+            title: { type: "string", description: "Heading for the page (e.g., 'Previously, in Chapter 12')." },
+            summary: { type: "string", description: "Optional recap text. When omitted, it is written by the vision model." },
+            panel_count: { type: "number", description: "Number of key panels to include. Default is 6." },
+            width: { type: "number", description: "Output width in pixels. Default is 1600." },
+            output_path: { type: "string", description: "Path for the summary image. Default is chapter_summary.png." }
+        },
+        required: ["source_dir"]
+    }
+  },
+  {
+    name: "server_info",
+    description: "Report the server configuration: version, active provider and models, optional features, and the detected CPU/GPU acceleration with the local implementation settings chosen from it.",
+    inputSchema: { type: "object", properties: {} }
+  },
+  {
+    name: "list_providers",
+    description: "List the image providers configured on this server, which one is active, what each supports (edit, mask, reference images, ...), and their latest availability and latency probe results.",
+    inputSchema: {
+      type: "object",
+      properties: {
+        probe: { type: "boolean", description: "Probe every configured provider now instead of returning the last results." },
+      },
+    },
+  }
+];
+
+// Provider-specific parameters for generate_image_from_text and edit_image. Every configured provider's parameters are
+// advertised, since a call can pick any of them with `provider`; the active provider goes last so its descriptions win
+// where names overlap, and only it extends the tool descriptions.
+const PROVIDER_SCHEMA_PARAMS = {
+    [PROVIDERS.GEMINI]: (genTool, editTool, active) => {
+        // Enhance generate_image_from_text with Gemini params
+        if (active) genTool.description += " Supports advanced parameters like aspectRatio and resolution.";
+        genTool.inputSchema.properties.aspectRatio = { 
+            type: "string", 
+            description: "Aspect ratio of the image (e.g., '1:1', '3:4', '4:3', '9:16', '16:9')." 
+        };
+        genTool.inputSchema.properties.resolution = { 
+            type: "string", 
+            description: "Resolution/Size of the image (e.g., '1K', '2K', '4K')." 
+        };
+        genTool.inputSchema.properties.numberOfImages = { 
+            type: "number", 
+            description: "Number of images to generate." 
+        };
+
+        // Enhance edit_image with Gemini params
+        editTool.inputSchema.properties.aspectRatio = { 
+            type: "string", 
+            description: "Aspect ratio." 
+        };
+        editTool.inputSchema.properties.resolution = { 
+            type: "string", 
+            description: "Resolution/Size." 
+        };
+        editTool.inputSchema.properties.numberOfImages = { 
+            type: "number", 
+            description: "Number of images." 
+        };
+    },
+    [PROVIDERS.AUTOMATIC1111]: (genTool, editTool, active) => {
+        // Sampling parameters for the local WebUI; unset values fall back to AUTOMATIC1111_* env vars, then WebUI defaults.
+        const samplingParams = {
+            negative_prompt: { type: "string", description: "What the image should not contain." },
+            sampler: { type: "string", description: "Sampler name as shown in the WebUI (e.g., 'DPM++ 2M', 'Euler a')." },
+            steps: { type: "number", description: "Number of sampling steps." },
+            cfg_scale: { type: "number", description: "Classifier-free guidance scale (e.g., 7)." },
+            seed: { type: "number", description: "Seed; -1 for random." },
+            width: { type: "number", description: "Output width in pixels (multiple of 8)." },
+            height: { type: "number", description: "Output height in pixels (multiple of 8)." },
+            aspectRatio: { type: "string", description: "Aspect ratio (e.g., '16:9') used when width/height are not given." },
+            numberOfImages: { type: "number", description: "Number of images (batch size)." },
+        };
+        if (active) genTool.description += " Runs on the local Automatic1111 WebUI and accepts sampler, steps and cfg_scale.";
+        Object.assign(genTool.inputSchema.properties, samplingParams);
+        Object.assign(editTool.inputSchema.properties, samplingParams, {
+            denoising_strength: { type: "number", description: "How much the image may change (0-1, default 0.6)." },
+        });
+    },
+    [PROVIDERS.COMFYUI]: (genTool, editTool, active) => {
+        // Values for the workflow placeholders; only those the workflow uses have an effect.
+        const workflowParams = {
+            negative_prompt: { type: "string", description: "Fills {{negative_prompt}}." },
+            seed: { type: "number", description: "Fills {{seed}}. Random when omitted." },
+            width: { type: "number", description: "Fills {{width}} (default 1024)." },
+            height: { type: "number", description: "Fills {{height}} (default 1024)." },
+            aspectRatio: { type: "string", description: "Aspect ratio (e.g., '16:9') used for {{width}}/{{height}} when they are not given." },
+            steps: { type: "number", description: "Fills {{steps}}." },
+            cfg_scale: { type: "number", description: "Fills {{cfg_scale}}." },
+        };
+        if (active) genTool.description += " Runs the configured ComfyUI workflow.";
+        Object.assign(genTool.inputSchema.properties, workflowParams);
+        Object.assign(editTool.inputSchema.properties, workflowParams, {
+            denoising_strength: { type: "number", description: "Fills {{denoise}} (0-1, default 0.6)." },
+        });
+    },
+    [PROVIDERS.MOCK]: (genTool, editTool, active) => {
+        if (active) genTool.description += " Mock provider: returns deterministic placeholder images without network access.";
+        Object.assign(genTool.inputSchema.properties, {
+            aspectRatio: { type: "string", description: "Aspect ratio (e.g., '16:9') used when width/height are not given." },
+            width: { type: "number", description: "Output width in pixels (default 1024)." },
+            height: { type: "number", description: "Output height in pixels (default 1024)." },
+            seed: { type: "number", description: "Changes the output deterministically." },
+            numberOfImages: { type: "number", description: "Number of images to generate." },
+        });
+        editTool.inputSchema.properties.seed = { type: "number", description: "Changes the output deterministically." };
+    },
+    [PROVIDERS.FAL]: (genTool, editTool, active) => {
+        const fluxParams = {
+            seed: { type: "number", description: "Seed for reproducible results." },
+            numberOfImages: { type: "number", description: "Number of images to generate." },
+        };
+        if (active) genTool.description += ` Runs ${FAL_MODEL} on fal.ai.`;
+        Object.assign(genTool.inputSchema.properties, fluxParams, {
+            aspectRatio: { type: "string", description: "Aspect ratio ('1:1', '4:3', '16:9', '3:4', '9:16'; others use the closest)." },
+            width: { type: "number", description: "Exact output width in pixels (use with height instead of aspectRatio)." },
+            height: { type: "number", description: "Exact output height in pixels." },
+        });
+        // Image-to-image keeps the size of the source image.
+        Object.assign(editTool.inputSchema.properties, fluxParams, {
+            denoising_strength: { type: "number", description: "How much the image may change (0-1, default 0.6)." },
+        });
+    },
+    [PROVIDERS.TOGETHER]: (genTool, editTool, active) => {
+        if (active) genTool.description += ` Runs ${TOGETHER_IMAGE_MODEL} on Together AI.`;
+        Object.assign(genTool.inputSchema.properties, {
+            aspectRatio: { type: "string", description: "Aspect ratio (e.g., '16:9') used when width/height are not given." },
+            width: { type: "number", description: "Output width in pixels (default 1024)." },
+            height: { type: "number", description: "Output height in pixels (default 1024)." },
+            steps: { type: "number", description: "Number of inference steps (FLUX.1-schnell: 1-4)." },
+            negative_prompt: { type: "string", description: "What the image should not contain (SDXL models)." },
+            seed: { type: "number", description: "Seed for reproducible results." },
+            numberOfImages: { type: "number", description: "Number of images to generate." },
+        });
+        Object.assign(editTool.inputSchema.properties, {
+            steps: { type: "number", description: "Number of inference steps." },
+            seed: { type: "number", description: "Seed for reproducible results." },
+        });
+    },
+    [PROVIDERS.RECRAFT]: (genTool, editTool, active) => {
+        const styleParams = {
+            style: { type: "string", enum: ["realistic_image", "digital_illustration", "vector_illustration", "icon"], description: `Recraft style (default ${RECRAFT_STYLE}). vector_illustration and icon produce SVG.` },
+            substyle: { type: "string", description: "Recraft substyle of the chosen style (e.g., 'hand_drawn', 'line_art', 'pixel_art')." },
+            negative_prompt: { type: "string", description: "What the image should not contain." },
+            numberOfImages: { type: "number", description: "Number of images to generate." },
+        };
+        if (active) genTool.description += ` Runs ${RECRAFT_MODEL} on Recraft; with vector: true the output is an SVG file.`;
+        Object.assign(genTool.inputSchema.properties, styleParams, {
+            aspectRatio: { type: "string", description: "Aspect ratio (e.g., '1:1', '16:9', '2:3'); the closest supported size is used." },
+            vector: { type: "boolean", description: "Generate a vector illustration and save it as SVG (the output extension becomes .svg)." },
+        });
+        Object.assign(editTool.inputSchema.properties, styleParams, {
+            denoising_strength: { type: "number", description: "How much the image may change (0-1, default 0.5)." },
+        });
+    },
+    [PROVIDERS.LEONARDO]: (genTool, editTool, active) => {
+        const leonardoParams = {
+            leonardo_model: { type: "string", description: `Leonardo model id (platform or custom model UUID). Defaults to LEONARDO_MODEL_ID (${LEONARDO_MODEL_ID}).` },
+            preset_style: { type: "string", description: "Leonardo preset style (e.g., 'DYNAMIC', 'CINEMATIC', 'ILLUSTRATION', 'ANIME', 'PHOTOGRAPHY', 'NONE')." },
+            style_uuid: { type: "string", description: "Leonardo style UUID (Phoenix and Flux models)." },
+            leonardo_input: { type: "object", description: "Extra generation fields passed through to the Leonardo API (e.g., { \"alchemy\": true, \"contrast\": 3.5 })." },
+            negative_prompt: { type: "string", description: "What the image should not contain." },
+            width: { type: "number", description: "Output width in pixels (multiple of 8, default 1024)." },
+            height: { type: "number", description: "Output height in pixels (multiple of 8, default 1024)." },
+            aspectRatio: { type: "string", description: "Aspect ratio (e.g., '16:9') used when width/height are not given." },
+            seed: { type: "number", description: "Seed for reproducible results." },
+            numberOfImages: { type: "number", description: "Number of images to generate (1-8)." },
+        };
+        if (active) genTool.description += " Runs on Leonardo.ai with model and style selection.";
+        Object.assign(genTool.inputSchema.properties, leonardoParams, {
+            steps: { type: "number", description: "Number of inference steps." },
+            cfg_scale: { type: "number", description: "Guidance scale." },
+        });
+        Object.assign(editTool.inputSchema.properties, leonardoParams, {
+            denoising_strength: { type: "number", description: "How much the image may change (0-1, default 0.5)." },
+        });
+    },
+    [PROVIDERS.HUGGINGFACE]: (genTool, editTool, active) => {
+        Object.assign(genTool.inputSchema.properties, {
+            negative_prompt: { type: "string", description: "What the image should not contain." },
+            width: { type: "number", description: "Output width in pixels." },
+            height: { type: "number", description: "Output height in pixels." },
+            steps: { type: "number", description: "Number of inference steps." },
+            cfg_scale: { type: "number", description: "Guidance scale." },
+            seed: { type: "number", description: "Seed for reproducible results." },
+        });
+    },
+    [PROVIDERS.BEDROCK]: (genTool, editTool, active) => {
+        if (active) genTool.description += ` Runs ${BEDROCK_IMAGE_MODEL} on Amazon Bedrock.`;
+        Object.assign(genTool.inputSchema.properties, {
+            aspectRatio: { type: "string", description: "Aspect ratio (e.g., '1:1', '16:9', '2:3'); the closest supported size is used." },
+            negative_prompt: { type: "string", description: "What the image should not contain." },
+            seed: { type: "number", description: "Seed for reproducible results." },
+            numberOfImages: { type: "number", description: "Number of images (Titan only)." },
+        });
+        Object.assign(editTool.inputSchema.properties, {
+            negative_prompt: { type: "string", description: "What the image should not contain." },
+            denoising_strength: { type: "number", description: "How much the image may change (0-1)." },
+        });
+    },
+};
+
+for (const provider of [
+    ...Object.keys(PROVIDER_SCHEMA_PARAMS).filter(p => p !== activeProvider && p !== PROVIDERS.MOCK && PROVIDER_REGISTRY[p].configured()),
+    activeProvider,
+]) {
+    PROVIDER_SCHEMA_PARAMS[provider]?.(tools.find(t => t.name === "generate_image_from_text"), tools.find(t => t.name === "edit_image"), provider === activeProvider);
+}
+
+if (ENABLE_DESKTOP_TOOLS) {
+    tools.push({
+        name: "capture_and_generate",
+        description: "Capture a region of the desktop and redraw it with the image model (e.g., turn a rough UI mockup into a polished design).",
+        inputSchema: {
+            type: "object",
+            properties: {
+                prompt: { type: "string", description: "Instructions for redrawing the capture. Defaults to a clean, polished UI redraw." },
+                x: { type: "number", description: "Left edge of the region in screen pixels. Omit x/y/width/height to capture the whole screen." },
+                y: { type: "number", description: "Top edge of the region in screen pixels." },
+                width: { type: "number", description: "Width of the region in screen pixels." },
+                height: { type: "number", description: "Height of the region in screen pixels." },
+                capture_path: { type: "string", description: "Optional path where the raw screen capture will be kept." },
+                output_path: { type: "string", description: "Path where the generated image will be saved." },
+            },
+        },
+    });
+}
+
+// Tools whose provider requests can be sent to another provider and model for one call (see callToolWithHooks).
+// `upscale` takes just `provider`, since the upscalers are fixed per provider.
+const MODEL_OVERRIDE_TOOLS = ["generate_image_from_text", "generate_image", "generate_avatar", "generate_texture", "generate_pbr_textures", "generate_storyboard", "generate_comic_strip", "generate_cover", "make_meme", "edit_image", "edit_region", "inpaint", "outpaint", "style_transfer", "generate_with_pose", "smart_crop", "capture_and_generate"];
+const PROVIDER_OVERRIDE_TOOLS = [...MODEL_OVERRIDE_TOOLS, "upscale"];
+
+for (const tool of tools.filter(t => PROVIDER_OVERRIDE_TOOLS.includes(t.name))) {
+    tool.inputSchema.properties.provider = {
+        type: "string",
+        enum: Object.values(PROVIDERS),
+        description: `Provider for this call instead of the active one (${activeProvider}). Must be configured; see list_providers.`,
+    };
+    if (MODEL_OVERRIDE_TOOLS.includes(tool.name)) {
+        tool.inputSchema.properties.model = {
+            type: "string",
+            description: "Model for this call (e.g., 'gemini-2.0-flash-exp', 'dall-e-3'), on `provider` or the active provider. Must be the provider's default, in ALLOWED_MODELS or in MODEL_ROUTES; list_providers shows the allowed models.",
+        };
+    }
+}
+
+// Uses Recraft's own API when RECRAFT_API_TOKEN is set, otherwise the recraft-v3-svg model on Replicate.
+async function generateSvgFromTextRecraft(prompt, outputPath = "output.svg", options = {}) {
+  try {
+    let svgBuffer;
+    let providerMetadata;
+    if (RECRAFT_API_TOKEN) {
+      const result = await generateImageRecraft(prompt, {
+        style: "vector_illustration",
+        substyle: options.style && options.style !== "any" ? options.style : undefined,
+        size: options.size,
+        aspectRatio: options.aspect_ratio && options.aspect_ratio !== "Not set" ? options.aspect_ratio : undefined,
+      });
+      svgBuffer = result.images[0];
+      providerMetadata = result.metadata;
+    } else {
+      svgBuffer = await generateSvgRecraft(prompt, options);
+      providerMetadata = { model: "recraft-ai/recraft-v3-svg" };
+    }
+
+    const [resolvedOutputPath] = saveImageBuffers([svgBuffer], outputPath || "output.svg", {
+      tool: "generate_svg_from_text",
+      request: { prompt, ...options },
+      provider: RECRAFT_API_TOKEN ? PROVIDERS.RECRAFT : PROVIDERS.REPLICATE,
+      provider_metadata: providerMetadata,
+    }, options.writeMetadata);
+
+    const result = {
+      success: true,
+      output_path: resolvedOutputPath,
+      message: `SVG generated successfully using ${RECRAFT_API_TOKEN ? "Recraft" : "Recraft V3 SVG on Replicate"}.`,
+    };
+    if (options.returnContent) {
+      result.contentBlocks = [{ type: "image", data: svgBuffer.toString("base64"), mimeType: "image/svg+xml" }];
+    }
+    return result;
+  } catch (error) {
+    console.error("Error generating SVG:", error.response ? error.response.data : error.message);
+    throw error;
+  }
+}
+
+// Runs any Replicate-hosted text-to-image model. Common options map to the usual input names; `options.replicateInput` adds or overrides model-specific inputs.
+async function generateImageReplicateModel(model, prompt, options = {}) {
+  const input = { prompt };
+  if (options.aspectRatio) input.aspect_ratio = options.aspectRatio;
+  if (options.numberOfImages) input.num_outputs = options.numberOfImages;
+  Object.assign(input, options.replicateInput);
+  const output = await runReplicatePrediction(model, input);
+  return { images: await downloadReplicateImages(output), metadata: { model } };
+}
+
+// Provider that will serve a generation request: a routed provider (options.provider) or a per-request Replicate model
+// takes precedence over the nano-banana-pro mode and the active provider.
+function getGenerationProvider(options = {}) {
+  if (options.provider) return options.provider;
+  if (options.replicateModel || IMAGE_GENERATION_MODE === MODES.NANO_BANANA_PRO) return PROVIDERS.REPLICATE;
+  return activeProvider;
+}
+
+// --- Request Coalescing ---
+// Identical provider requests that are in flight at the same time share one provider call; every caller gets the same
+// images and saves them to its own output path. The key covers the provider, the request and per-call HTTP extras.
+// Only seeded requests are shared: two unseeded requests for the same prompt are expected to give different images.
+const inFlightProviderCalls = new Map();
+
+function getProviderRequestKey(kind, payload) {
+  const { writeMetadata, safeRewrite, styleProfile, ...options } = payload.options || {};
+  const store = requestHttpContext.getStore() || {};
+  return sha256(JSON.stringify({
+    kind,
+    provider: kind === "generate" ? getGenerationProvider(options) : options.provider || activeProvider,
+    mode: IMAGE_GENERATION_MODE,
+    ...payload,
+    options,
+    http: { headers: store.headers, query: store.query },
+  }));
+}
+
+function coalesceProviderCall(kind, payload, run) {
+  if (!COALESCE_REQUESTS || payload.options?.seed == null) return run();
+  const key = getProviderRequestKey(kind, payload);
+  const pending = inFlightProviderCalls.get(key);
+  if (pending) {
+    console.error(`[Coalesce] Joining identical in-flight ${kind} request ${key.slice(0, 12)}`);
+    return pending;
+  }
+  const promise = run().finally(() => inFlightProviderCalls.delete(key));
+  inFlightProviderCalls.set(key, promise);
+  return promise;
+}
+
+// --- Provider Fallback ---
+// PROVIDER_FALLBACKS lists providers to try, in order, when the first one fails with a quota, rate-limit, server,
+// network or safety error. Fallbacks that aren't configured, can't do the operation or were last probed as
+// unavailable are skipped. The result names the provider that produced the image and the failures before it.
+
+const FALLBACK_ERROR_PATTERN = /\b(429|50[0-4])\b|quota|rate.?limit|RESOURCE_EXHAUSTED|UNAVAILABLE|overloaded|timed? ?out|ECONNREFUSED|ECONNRESET|ETIMEDOUT|ENOTFOUND|socket hang up/i;
+
+for (const provider of PROVIDER_FALLBACKS) {
+  if (!Object.values(PROVIDERS).includes(provider)) console.error(`Ignoring unknown provider in PROVIDER_FALLBACKS: ${provider}`);
+}
+
+function isFallbackError(error) {
+  const status = error.response?.status ?? error.status;
+  if (status === 429 || status >= 500) return true;
+  return isContentPolicyError(error) || FALLBACK_ERROR_PATTERN.test(error.message);
+}
+
+function getFallbackChain(kind, first, options) {
+  const fallbacks = PROVIDER_FALLBACKS.filter(provider => {
+    const entry = PROVIDER_REGISTRY[provider];
+    if (!entry || provider === first || !entry.configured()) return false;
+    if (kind === "edit" && (!entry.edit || (options.mask && !entry.capabilities.includes("mask")))) return false;
+    if (options.requireReferenceImages && !entry.capabilities.includes("reference_images")) return false;
+    return providerHealth.get(provider)?.available !== false;
+  });
+  return [first, ...new Set(fallbacks)];
+}
+
+// `run` gets the options for each attempt; fallbacks drop the model choices that only apply to the first provider.
+async function runWithFallback(kind, first, options, run) {
+  const chain = getFallbackChain(kind, first, options);
+  const failures = [];
+  for (const [index, provider] of chain.entries()) {
+    const attemptOptions = index === 0 ? options : { ...options, provider, model: undefined, replicateModel: undefined };
+    for (let resubmits = 0; ; resubmits++) {
+      try {
+        const result = await run(attemptOptions);
+        const allFailures = [...failures, ...(result.fallbacks || [])];
+        return { ...result, provider: result.provider || provider, fallbacks: allFailures.length > 0 ? allFailures : undefined };
+      } catch (error) {
+        if (error instanceof PollTimeoutError && resubmits < POLL_RETRIES) {
+          console.error(`[Watchdog] Resubmitting ${kind} on ${provider} (${resubmits + 1}/${POLL_RETRIES})`);
+          continue;
+        }
+        if (index === chain.length - 1 || !isFallbackError(error)) throw error;
+        console.error(`[Fallback] ${kind} on ${provider} failed (${error.message}); trying ${chain[index + 1]}`);
+        failures.push({ provider, error: error.message });
+        break;
+      }
+    }
+  }
+}
+
+// --- Load Balancing ---
+// IMAGE_GENERATION_PROVIDER=balanced spreads requests over the members in BALANCED_PROVIDERS, given either as
+// "openai:3,fal:1" (provider[:weight]) or as a JSON array of {"provider","weight","api_key","name"} objects; listing a
+// provider several times with different api_key values rotates between its keys. Members are picked by smooth weighted
+// round-robin, and a member that fails with a fallback error (see above) passes the request on to the next one.
+
+// Headers that replace the provider's configured key for one member; the key is sent only to that provider's API.
+// Gemini goes through its SDK, so a Gemini member's key gets a client of its own instead (getGeminiClient).
+const BALANCED_AUTH_HEADERS = {
+  [PROVIDERS.REPLICATE]: key => ({ Authorization: `Bearer ${key}` }),
+  [PROVIDERS.HUGGINGFACE]: key => ({ Authorization: `Bearer ${key}` }),
+  [PROVIDERS.OPENAI]: key => ({ Authorization: `Bearer ${key}` }),
+  [PROVIDERS.AZURE_OPENAI]: key => ({ "api-key": key }),
+  [PROVIDERS.STABILITY]: key => ({ Authorization: `Bearer ${key}` }),
+  [PROVIDERS.FAL]: key => ({ Authorization: `Key ${key}` }),
+  [PROVIDERS.TOGETHER]: key => ({ Authorization: `Bearer ${key}` }),
+  [PROVIDERS.RECRAFT]: key => ({ Authorization: `Bearer ${key}` }),
+  [PROVIDERS.LEONARDO]: key => ({ Authorization: `Bearer ${key}` }),
+};
+
+function parseBalancedMembers() {
+  const entries = BALANCED_PROVIDERS.startsWith("[")
+    ? parseJsonEnv("BALANCED_PROVIDERS")
+    : BALANCED_PROVIDERS.split(",").map(s => s.trim()).filter(Boolean).map(item => {
+      const [provider, weight] = item.split(":").map(s => s.trim());
+      return { provider, weight: weight === undefined ? 1 : Number(weight) };
+    });
+  if (!Array.isArray(entries)) return [];
+  return entries.flatMap((entry, index) => {
+    const weight = entry.weight ?? 1;
+    if (!PROVIDER_REGISTRY[entry.provider] || entry.provider === PROVIDERS.BALANCED) {
+      console.error(`Ignoring unknown provider in BALANCED_PROVIDERS: ${entry.provider}`);
+      return [];
+    }
+    if (!(weight > 0)) {
+      console.error(`Ignoring BALANCED_PROVIDERS entry for ${entry.provider}: weight must be a positive number`);
+      return [];
+    }
+    if (entry.api_key && !BALANCED_AUTH_HEADERS[entry.provider] && entry.provider !== PROVIDERS.GEMINI) {
+      console.error(`Ignoring BALANCED_PROVIDERS entry for ${entry.provider}: api_key is not supported for this provider`);
+      return [];
+    }
+    return [{
+      name: entry.name || `${entry.provider}#${index + 1}`,
+      provider: entry.provider,
+      weight,
+      apiKey: entry.api_key,
+      headers: entry.api_key && BALANCED_AUTH_HEADERS[entry.provider] ? BALANCED_AUTH_HEADERS[entry.provider](entry.api_key) : undefined,
+      current: 0,
+    }];
+  });
+}
+
+const balancedMembers = parseBalancedMembers();
+
+// The key of the balanced member serving the current call, which stands in for the provider's configured key.
+function getMemberApiKey(provider) {
+  return requestHttpContext.getStore()?.memberApiKeys?.[provider];
+}
+
+// A member with its own api_key counts as configured without the global key (Azure still needs its endpoint and deployment).
+function isBalancedMemberConfigured(member) {
+  if (!member.apiKey) return PROVIDER_REGISTRY[member.provider].configured();
+  return member.provider !== PROVIDERS.AZURE_OPENAI || Boolean(AZURE_OPENAI_ENDPOINT && AZURE_OPENAI_DEPLOYMENT);
+}
+
+function isBalancedCandidate(kind, member, options) {
+  const entry = PROVIDER_REGISTRY[member.provider];
+  if (!isBalancedMemberConfigured(member) || providerHealth.get(member.provider)?.available === false) return false;
+  if (kind === "upscale") return Boolean(entry.upscale);
+  if (options.requireReferenceImages && !entry.capabilities.includes("reference_images")) return false;
+  return kind !== "edit" || (Boolean(entry.edit) && (!options.mask || entry.capabilities.includes("mask")));
+}
+
+// Smooth weighted round-robin: over any run of picks each member gets its share of the total weight, evenly interleaved.
+function pickBalancedMember(candidates) {
+  const total = candidates.reduce((sum, member) => sum + member.weight, 0);
+  let best;
+  for (const member of candidates) {
+    member.current += member.weight;
+    if (!best || member.current > best.current) best = member;
+  }
+  best.current -= total;
+  return best;
+}
+
+// `run` gets the member's registry entry and the options for it; the result names the provider and member that served it.
+async function runBalanced(kind, options, run) {
+  const tried = new Set();
+  const failures = [];
+  let lastError;
+  for (;;) {
+    const candidates = balancedMembers.filter(member => !tried.has(member) && isBalancedCandidate(kind, member, options));
+    if (candidates.length === 0) {
+      const action = { generate: "generate images", edit: "edit images", upscale: "upscale images" }[kind];
+      throw lastError || new Error(`No BALANCED_PROVIDERS member can ${action}${options.mask ? " with a mask" : ""}.`);
+    }
+    const member = pickBalancedMember(candidates);
+    tried.add(member);
+    const store = requestHttpContext.getStore() || {};
+    const memberStore = member.apiKey ? {
+      ...store,
+      providerHeaders: member.headers ? { ...store.providerHeaders, [member.provider]: member.headers } : store.providerHeaders,
+      memberApiKeys: { ...store.memberApiKeys, [member.provider]: member.apiKey },
+    } : store;
+    try {
+      const result = await requestHttpContext.run(memberStore, () => run(PROVIDER_REGISTRY[member.provider], { ...options, provider: member.provider }));
+      return {
+        ...result,
+        metadata: { ...result.metadata, balanced_member: member.name },
+        provider: member.provider,
+        fallbacks: failures.length > 0 ? failures : undefined,
+      };
+    } catch (error) {
+      if (!isFallbackError(error)) throw error;
+      console.error(`[Balance] ${kind} on ${member.name} failed (${error.message}); trying another member`);
+      failures.push({ provider: member.name, error: error.message });
+      lastError = error;
+    }
+  }
+}
+
+// Runs text-to-image generation on the active provider (or its fallbacks) without saving anything.
+async function runProviderGenerate(prompt, options = {}) {
+  options = applyProviderOverride(options);
+  return await runWithFallback("generate", getGenerationProvider(options), options,
+    (attemptOptions) => coalesceProviderCall("generate", { prompt, options: attemptOptions }, () => callProviderGenerate(prompt, attemptOptions)));
+}
+
+// `options.provider` / `options.model` (set by quality routing) select a provider other than the active one and a model other than its default.
+async function callProviderGenerate(prompt, options = {}) {
+  if (options.replicateModel) {
+    return await generateImageReplicateModel(options.replicateModel, prompt, options);
+  }
+  if (IMAGE_GENERATION_MODE === MODES.NANO_BANANA_PRO && !options.provider) {
+    const buf = await generateImageNanoBanana(prompt, options);
+    return { images: [buf], metadata: { model: "google/nano-banana-pro" } };
+  }
+  const provider = options.provider || activeProvider;
+  const entry = PROVIDER_REGISTRY[provider];
+  if (!entry) throw new Error(`Unknown image provider: ${provider}`);
+  return await entry.generate(prompt, options);
+}
+
+// --- Content Policy Retries ---
+// A request blocked by the provider's safety filters is retried with a rewritten prompt. SAFE_REWRITE_STRATEGY (or the
+// safe_rewrite tool argument) is "soften" (add non-graphic instructions), "llm" (have the Gemini text model rewrite the
+// prompt, more conservatively on each attempt) or "off". Requests that stay blocked are logged to CONTENT_POLICY_QUARANTINE_FILE.
+
+const CONTENT_POLICY_PATTERN = /SAFETY|PROHIBITED_CONTENT|BLOCKLIST|SPII|content[_ -]?polic|moderation|nsfw|blocked/i;
+
+function isContentPolicyError(error) {
+  const data = error.response?.data;
+  const detail = data && !(data instanceof ArrayBuffer) && !Buffer.isBuffer(data) ? JSON.stringify(data) : "";
+  return CONTENT_POLICY_PATTERN.test(`${error.message} ${detail}`);
+}
+
+async function rewritePromptForSafety(prompt, strategy, attempt) {
+  if (strategy === "llm" && geminiClient) {
+    const response = await geminiClient.models.generateContent({
+      model: GEMINI_VISION_MODEL,
+      contents: [{
+        role: "user",
+        parts: [{
+          text: `An image generator's safety filter rejected the prompt below${attempt > 1 ? ` ${attempt} times; be considerably more conservative this time` : ""}. ` +
+            "Rewrite it so it complies with typical content policies while keeping the subject, composition, setting and art style. " +
+            "Replace gore, explicit violence and nudity with implied, off-screen or stylized alternatives, and drop names of real people. " +
+            `Reply with the rewritten prompt only.\n\nPrompt:\n${prompt}`,
+        }],
+      }],
+      config: applyGeminiHttpOptions({}),
+    });
+    const text = (response.text || "").trim();
+    if (text) return text;
+  }
+  return "Depict the following in a non-graphic, non-explicit way suitable for a general audience: imply violence or injury rather than showing it, " +
+    `keep characters fully clothed, and avoid real public figures.\n\n${prompt}`;
+}
+
+function quarantineBlockedRequest(entry) {
+  if (!CONTENT_POLICY_QUARANTINE_FILE) return;
+  try {
+    fs.mkdirSync(path.dirname(path.resolve(CONTENT_POLICY_QUARANTINE_FILE)), { recursive: true });
+    fs.appendFileSync(CONTENT_POLICY_QUARANTINE_FILE, JSON.stringify({ time: new Date().toISOString(), ...entry }) + "\n");
+  } catch (err) {
+    console.error("Failed to write quarantine entry:", err.message);
+  }
+}
+
+// Calls run(prompt) and, on content-policy failures, retries with rewritten prompts. Returns run()'s result plus
+// `safeRewrite` ({ strategy, original_prompt, prompt, blocked_attempts }) when a rewrite was needed.
+async function runWithSafeRewrite(toolName, prompt, strategy, run) {
+  try {
+    return await run(prompt);
+  } catch (error) {
+    if (strategy === "off" || !isContentPolicyError(error)) throw error;
+    const blockedAttempts = [{ prompt, error: error.message }];
+    // The fixed soften wording gives the same prompt every time, so it gets one attempt.
+    const maxAttempts = strategy === "llm" ? SAFE_REWRITE_ATTEMPTS : Math.min(1, SAFE_REWRITE_ATTEMPTS);
+    let current = prompt;
+    for (let attempt = 1; attempt <= maxAttempts; attempt++) {
+      current = await rewritePromptForSafety(current, strategy, attempt);
+      console.error(`[SafeRewrite] ${toolName}: retrying blocked request (attempt ${attempt}/${maxAttempts}, ${strategy})`);
+      try {
+        const result = await run(current);
+        return { ...result, safeRewrite: { strategy, original_prompt: prompt, prompt: current, blocked_attempts: blockedAttempts } };
+      } catch (err) {
+        if (!isContentPolicyError(err)) throw err;
+        blockedAttempts.push({ prompt: current, error: err.message });
+      }
+    }
+    quarantineBlockedRequest({ tool: toolName, prompt, strategy, attempts: blockedAttempts });
+    throw new Error(`Irrecoverable content-policy block after ${blockedAttempts.length} attempt(s): ${blockedAttempts[blockedAttempts.length - 1].error}`);
+  }
+}
+
+// Vector outputs (Recraft vector styles) are SVG documents, so they are saved under .svg whatever extension was asked for.
+function getOutputPathForMimeType(outputPath, mimeType) {
+  if (mimeType !== "image/svg+xml" || path.extname(outputPath).toLowerCase() === ".svg") return outputPath;
+  return `${outputPath.slice(0, outputPath.length - path.extname(outputPath).length)}.svg`;
+}
+
+async function generateImageFromText(prompt, outputPath = "output.png", options = {}) {
+  try {
+    // The sidecar keeps the prompt as given, so regenerating from it applies the style profile only once.
+    const requestPrompt = prompt;
+    ({ prompt, options } = withStyleProfile(prompt, options));
+    let route;
+    if (options.quality && !options.replicateModel && !getProviderOverride()) {
+      route = routeByQuality(options.quality);
+      options = { ...options, provider: route.provider, model: route.model };
+    }
+    const { images: imageBuffers, metadata: providerMetadata, safeRewrite, provider, fallbacks } = await runWithSafeRewrite(
+      "generate_image_from_text", prompt, options.safeRewrite || SAFE_REWRITE_STRATEGY, (p) => runProviderGenerate(p, options));
+    outputPath = getOutputPathForMimeType(outputPath, providerMetadata?.mimeType);
+
+    const results = saveImageBuffers(imageBuffers, outputPath, {
+      tool: "generate_image_from_text",
+      request: { prompt: requestPrompt, ...options, referenceImages: undefined },
+      provider,
+      provider_metadata: providerMetadata,
+      safe_rewrite: safeRewrite,
+      route,
+      fallbacks,
+    }, options.writeMetadata);
+    
+    const result = {
+      success: true,
+      output_paths: results,
+      provider,
+      message: `Image(s) generated successfully using ${
+        fallbacks ? `${provider} (fallback after ${fallbacks.map(f => f.provider).join(", ")} failed)`
+          : route ? [route.provider, route.model].filter(Boolean).join(" / ")
+          : options.replicateModel || (IMAGE_GENERATION_MODE === MODES.NANO_BANANA_PRO ? "nano-banana-pro" : provider)
+      }`,
+    };
+    if (route) result.route = route;
+    if (fallbacks) result.fallbacks = fallbacks;
+    if (safeRewrite) result.safe_rewrite = safeRewrite;
+    return result;
+  } catch (error) {
+    console.error("Error generating image:", error.response ? error.response.data : error.message);
+    throw error;
+  }
+}
+
+function parseImageSize(size) {
+  const match = /^\s*(\d+)\s*[xX×]\s*(\d+)\s*$/.exec(size || "");
+  if (!match) throw new Error(`Invalid size: ${size}. Use WIDTHxHEIGHT, e.g. '1024x768'.`);
+  const width = parseInt(match[1], 10);
+  const height = parseInt(match[2], 10);
+  const gcd = (a, b) => (b === 0 ? a : gcd(b, a % b));
+  const divisor = gcd(width, height);
+  return { width, height, aspectRatio: `${width / divisor}:${height / divisor}` };
+}
+
+// Text-to-image for clients that show images inline: the images are returned as MCP image content blocks.
+async function generateImage(args) {
+  try {
+    const options = {
+      negativePrompt: args.negative_prompt,
+      numberOfImages: args.count,
+      seed: args.seed,
+      aspectRatio: args.aspect_ratio,
+      ...(args.size ? parseImageSize(args.size) : {}),
+    };
+    if (args.size && args.aspect_ratio) options.aspectRatio = args.aspect_ratio;
+    const { prompt, options: styledOptions } = withStyleProfile(args.prompt, { ...options, styleProfile: args.style_profile });
+    const { images, metadata: providerMetadata, safeRewrite, provider, fallbacks } = await runWithSafeRewrite(
+      "generate_image", prompt, args.safe_rewrite || SAFE_REWRITE_STRATEGY, (p) => runProviderGenerate(p, styledOptions));
+
+    const result = {
+      success: true,
+      provider,
+      image_count: images.length,
+      message: `${images.length} image(s) generated using ${provider}${fallbacks ? ` (fallback after ${fallbacks.map(f => f.provider).join(", ")} failed)` : ""}`,
+    };
+    if (args.output_path) {
+      result.output_paths = saveImageBuffers(images, args.output_path, {
+        tool: "generate_image",
+        request: args,
+        provider,
+        provider_metadata: providerMetadata,
+        safe_rewrite: safeRewrite,
+        fallbacks,
+      }, args.write_metadata);
+    }
+    if (fallbacks) result.fallbacks = fallbacks;
+    if (safeRewrite) result.safe_rewrite = safeRewrite;
+    result.contentBlocks = images.map(buf => ({ type: "image", data: buf.toString("base64"), mimeType: getBufferMimeType(buf) }));
+    return result;
+  } catch (error) {
+    console.error("Error generating image:", error.response ? error.response.data : error.message);
+    throw error;
+  }
+}
+
+const PROMPT_ANALYSIS_SCHEMA = {
+    type: Type.OBJECT,
+    properties: {
+        subject: { type: Type.STRING },
+        medium: { type: Type.STRING },
+        style: { type: Type.STRING },
+        composition: { type: Type.STRING },
+        lighting: { type: Type.STRING },
+        camera: { type: Type.STRING },
+        color_palette: { type: Type.STRING },
+        mood: { type: Type.STRING },
+        negative_prompt: { type: Type.STRING },
+    },
+    required: ["subject", "medium", "style", "composition", "lighting", "camera", "color_palette", "mood"],
+};
+
+function buildPromptFromAnalysis(analysis, format = "natural", subject) {
+    const parts = [subject, analysis.medium, analysis.style, analysis.composition, analysis.lighting, analysis.camera, analysis.color_palette, analysis.mood]
+        .map(p => (p || "").trim().replace(/\.$/, ""))
+        .filter(Boolean);
+    if (format === "tags") {
+        return parts.flatMap(p => p.split(/\s*[,;]\s*/)).filter(Boolean).map(t => t.toLowerCase()).join(", ");
+    }
+    return parts.map(p => p.charAt(0).toUpperCase() + p.slice(1)).join(". ") + ".";
+}
+
+async function describeAsPrompt(args) {
+    try {
+        const instruction = "Analyze this image as a prompt engineer would, so it can be recreated with a text-to-image model. " +
+            "Describe: subject (what is depicted, concise), medium (photo, oil painting, 3D render, ...), style (movement, artist-like traits, rendering technique), " +
+            "composition (framing, shot type, angle, placement), lighting (type, direction, quality, time of day), camera (lens, focal length, depth of field, film/sensor look; for non-photos, the equivalent viewpoint terms), " +
+            "color_palette, mood, and a negative_prompt of artifacts to avoid. Use short, concrete generation keywords rather than prose.";
+        const text = await describeImage(args.image_path, instruction, { responseSchema: PROMPT_ANALYSIS_SCHEMA });
+
+        let analysis;
+        try {
+            analysis = JSON.parse(text.replace(/^```(json)?/, "").replace(/```$/, ""));
+        } catch (err) {
+            throw new Error(`Vision model returned invalid JSON: ${text.slice(0, 200)}`);
+        }
+
+        const subject = args.new_subject || (args.include_subject === false ? "" : analysis.subject);
+        const format = args.prompt_format === "tags" ? "tags" : "natural";
+        return {
+            success: true,
+            prompt: buildPromptFromAnalysis(analysis, format, subject),
+            negative_prompt: analysis.negative_prompt,
+            analysis: analysis,
+        };
+    } catch (error) {
+        console.error("Error describing image as prompt:", error.message);
+        throw error;
+    }
+}
+
+// --- Style Profiles ---
+
+const STYLE_PROFILE_SCHEMA = {
+    type: Type.OBJECT,
+    properties: {
+        descriptor: { type: Type.STRING },
+        medium: { type: Type.STRING },
+        line_and_texture: { type: Type.STRING },
+        lighting: { type: Type.STRING },
+        palette_description: { type: Type.STRING },
+        negative_prompt: { type: Type.STRING },
+    },
+    required: ["descriptor", "medium", "line_and_texture", "lighting", "palette_description"],
+};
+
+function getStyleProfilePath(name) {
+    if (!/^[\w-]+$/.test(name || "")) throw new Error(`Invalid style profile name: ${name}. Use letters, digits, '-' and '_'.`);
+    return path.join(STYLE_PROFILES_DIR, `${name}.json`);
+}
+
+function loadStyleProfile(name) {
+    const profilePath = getStyleProfilePath(name);
+    if (!fs.existsSync(profilePath)) throw new Error(`Style profile not found: ${name}`);
+    return JSON.parse(fs.readFileSync(profilePath, "utf8"));
+}
+
+function applyStyleProfile(prompt, profile) {
+    const palette = (profile.palette || []).map(c => c.hex).join(", ");
+    return [
+        prompt,
+        `Style: ${profile.descriptor}`,
+        profile.notes ? `Notes: ${profile.notes}` : "",
+        palette ? `Color palette: ${palette}.` : "",
+        profile.negative_prompt ? `Avoid: ${profile.negative_prompt}` : "",
+    ].filter(Boolean).join("\n");
+}
+
+// The prompt and options for a request that uses `options.styleProfile`: the profile's text goes into the prompt and
+// its reference grid joins any other reference images.
+function withStyleProfile(prompt, options) {
+    if (!options.styleProfile) return { prompt, options };
+    const profile = loadStyleProfile(options.styleProfile);
+    if (!profile.reference_image || !fs.existsSync(profile.reference_image)) return { prompt: applyStyleProfile(prompt, profile), options };
+    return {
+        prompt: applyStyleProfile(prompt, profile),
+        options: { ...options, referenceImages: [...(options.referenceImages || []), loadReferenceImage(profile.reference_image)] },
+    };
+}
+
+async function createStyleProfile(args) {
+    try {
+        const profilePath = getStyleProfilePath(args.name);
+        const imagePaths = (args.image_paths || []).slice(0, 9).map(p => path.resolve(p));
+        if (imagePaths.length === 0) throw new Error("image_paths must contain at least one image.");
+        for (const p of imagePaths) {
+            if (!fs.existsSync(p)) throw new Error(`Image file not found: ${p}`);
+        }
+
+        // One grid image lets the vision model see the whole set at once and doubles as a visual reference for generation.
+        const cell = 384;
+        const columns = Math.ceil(Math.sqrt(imagePaths.length));
+        const rows = Math.ceil(imagePaths.length / columns);
+        const tiles = await Promise.all(imagePaths.map(async (p, i) => ({
+            input: await sharp(p).resize(cell, cell, { fit: "cover" }).removeAlpha().png().toBuffer(),
+            left: (i % columns) * cell,
+            top: Math.floor(i / columns) * cell,
+        })));
+        const grid = await sharp({ create: { width: columns * cell, height: rows * cell, channels: 3, background: "#ffffff" } })
+            .composite(tiles)
+            .png()
+            .toBuffer();
+
+        const instruction = `This grid shows ${imagePaths.length} reference image(s) that share a visual style. Ignore their subjects and describe only the shared style so it can be reproduced with a text-to-image model: ` +
+            "descriptor (one dense prompt-ready sentence), medium, line_and_texture, lighting, palette_description, and a negative_prompt of things that would break the style.";
+        const text = await describeImageData(grid.toString("base64"), "image/png", instruction, { responseSchema: STYLE_PROFILE_SCHEMA });
+        let analysis;
+        try {
+            analysis = JSON.parse(text.replace(/^```(json)?/, "").replace(/```$/, ""));
+        } catch (err) {
+            throw new Error(`Vision model returned invalid JSON: ${text.slice(0, 200)}`);
+        }
+
+        fs.mkdirSync(STYLE_PROFILES_DIR, { recursive: true });
+        const referencePath = path.join(STYLE_PROFILES_DIR, `${args.name}.png`);
+        fs.writeFileSync(referencePath, grid);
+
+        const profile = {
+            name: args.name,
+            created_at: new Date().toISOString(),
+            ...analysis,
+            notes: args.notes,
+            palette: await extractDominantColors(grid, 6),
+            reference_image: referencePath,
+            source_images: imagePaths,
+        };
+        fs.writeFileSync(profilePath, JSON.stringify(profile, null, 2));
+
+        return { success: true, profile_path: profilePath, profile: profile, message: `Style profile "${args.name}" saved.` };
+    } catch (error) {
+        console.error("Error creating style profile:", error.message);
+        throw error;
+    }
+}
+
+function listStyleProfiles() {
+    if (!fs.existsSync(STYLE_PROFILES_DIR)) return { success: true, profiles: [] };
+    const profiles = fs.readdirSync(STYLE_PROFILES_DIR)
+        .filter(f => f.endsWith(".json"))
+        .map(f => {
+            try {
+                const profile = JSON.parse(fs.readFileSync(path.join(STYLE_PROFILES_DIR, f), "utf8"));
+                return { name: profile.name, descriptor: profile.descriptor, palette: (profile.palette || []).map(c => c.hex), created_at: profile.created_at };
+            } catch (err) {
+                return null;
+            }
+        })
+        .filter(Boolean);
+    return { success: true, profiles: profiles };
+}
+
+// --- Experiments ---
+// An experiment is a set of named variants (prompt template + tool parameters) saved as JSON in EXPERIMENTS_DIR.
+// run_experiment generates each item with its assigned variant(s) and records a trial per output; review scores are
+// added with score_experiment, and experiment_report compares the variants.
+
+function getExperimentPath(name) {
+    if (!/^[\w-]+$/.test(name || "")) throw new Error(`Invalid experiment name: ${name}. Use letters, digits, '-' and '_'.`);
+    return path.join(EXPERIMENTS_DIR, `${name}.json`);
+}
+
+function loadExperiment(name) {
+    const experimentPath = getExperimentPath(name);
+    if (!fs.existsSync(experimentPath)) throw new Error(`Experiment not found: ${name}`);
+    return JSON.parse(fs.readFileSync(experimentPath, "utf8"));
+}
+
+function saveExperiment(experiment) {
+    fs.mkdirSync(EXPERIMENTS_DIR, { recursive: true });
+    fs.writeFileSync(getExperimentPath(experiment.name), JSON.stringify(experiment, null, 2));
+}
+
+function createExperiment(args) {
+    const experimentPath = getExperimentPath(args.name);
+    if (fs.existsSync(experimentPath)) throw new Error(`Experiment already exists: ${args.name}`);
+    const variants = args.variants || [];
+    if (variants.length < 2) throw new Error("An experiment needs at least two variants.");
+    const names = new Set();
+    for (const variant of variants) {
+        if (!variant.name || names.has(variant.name)) throw new Error(`Variant names must be present and unique (got '${variant.name}').`);
+        if (!variant.prompt) throw new Error(`Variant '${variant.name}' has no prompt.`);
+        names.add(variant.name);
+    }
+    const experiment = {
+        name: args.name,
+        description: args.description,
+        created_at: new Date().toISOString(),
+        variants: variants.map(v => ({ name: v.name, prompt: v.prompt, params: v.params || {} })),
+        trials: [],
+    };
+    saveExperiment(experiment);
+    return { success: true, experiment_path: experimentPath, experiment, message: `Experiment "${args.name}" created with ${variants.length} variants.` };
+}
+
+// `{subject}` in a variant prompt is replaced by the item's subject; without it the subject is appended.
+function buildVariantPrompt(variant, subject) {
+    if (!subject) return variant.prompt;
+    return variant.prompt.includes("{subject}") ? variant.prompt.split("{subject}").join(subject) : `${subject}\n\n${variant.prompt}`;
+}
+
+// Items are text subjects (generate_image_from_text) or image paths (edit_image, e.g. pages to colorize).
+// With assignment "all" every item is run with every variant; "alternate" assigns item i to variant i mod n.
+async function runExperiment(args) {
+    const experiment = loadExperiment(args.name);
+    const items = args.image_paths
+        // The index keeps labels unique when images from different folders share a file name.
+        ? args.image_paths.map((p, i) => ({ image_path: path.resolve(p), label: `${path.basename(p, path.extname(p))}-${i + 1}` }))
+        : (args.subjects || []).map((subject, i) => ({ subject, label: `item${i + 1}` }));
+    if (items.length === 0) throw new Error("Provide subjects or image_paths.");
+    const outputDir = path.resolve(args.output_dir || path.join(EXPERIMENTS_DIR, experiment.name));
+    fs.mkdirSync(outputDir, { recursive: true });
+
+    const trials = [];
+    for (const [index, item] of items.entries()) {
+        const variants = args.assignment === "alternate" ? [experiment.variants[index % experiment.variants.length]] : experiment.variants;
+        for (const variant of variants) {
+            const outputPath = path.join(outputDir, `${item.label}-${variant.name}.png`);
+            const trial = { id: crypto.randomUUID(), item: item.image_path || item.subject, variant: variant.name, created_at: new Date().toISOString() };
+            try {
+                // Trials go through the hooks and post-processing like any other call, so they are comparable with production output.
+                const result = await (item.image_path
+                    ? callToolWithHooks("edit_image", { ...variant.params, image_path: item.image_path, prompt: buildVariantPrompt(variant), output_path: outputPath })
+                    : callToolWithHooks("generate_image_from_text", { ...variant.params, prompt: buildVariantPrompt(variant, item.subject), output_path: outputPath }));
+                trial.output_paths = result.output_paths;
+            } catch (error) {
+                trial.error = error.message;
+            }
+            trials.push(trial);
+            // Saved after every trial so an interrupted run keeps what it produced.
+            experiment.trials.push(trial);
+            saveExperiment(experiment);
+        }
+    }
+    const failed = trials.filter(t => t.error).length;
+    return {
+        success: failed < trials.length,
+        trials,
+        message: `Ran ${trials.length} trial(s) of "${experiment.name}"${failed ? `, ${failed} failed` : ""}. Score the outputs with score_experiment.`,
+    };
+}
+
+function scoreExperiment(args) {
+    const experiment = loadExperiment(args.name);
+    const outputPath = path.resolve(args.output_path);
+    const trial = experiment.trials.find(t => (t.output_paths || []).includes(outputPath));
+    if (!trial) throw new Error(`No trial of "${experiment.name}" produced ${outputPath}.`);
+    if (typeof args.score !== "number") throw new Error("score must be a number.");
+    trial.scores = { ...trial.scores, [outputPath]: args.score };
+    if (args.notes) trial.notes = args.notes;
+    trial.scored_at = new Date().toISOString();
+    saveExperiment(experiment);
+    return { success: true, trial, message: `Scored ${path.basename(outputPath)} (${trial.variant}): ${args.score}.` };
+}
+
+function experimentReport(args) {
+    const experiment = loadExperiment(args.name);
+    const scoreOf = trial => {
+        const scores = Object.values(trial.scores || {});
+        return scores.length > 0 ? scores.reduce((a, b) => a + b, 0) / scores.length : null;
+    };
+
+    const variants = experiment.variants.map(variant => {
+        const trials = experiment.trials.filter(t => t.variant === variant.name);
+        const scores = trials.map(scoreOf).filter(s => s !== null);
+        return {
+            variant: variant.name,
+            trials: trials.length,
+            failed: trials.filter(t => t.error).length,
+            scored: scores.length,
+            mean_score: scores.length > 0 ? Math.round((scores.reduce((a, b) => a + b, 0) / scores.length) * 100) / 100 : null,
+            min_score: scores.length > 0 ? Math.min(...scores) : null,
+            max_score: scores.length > 0 ? Math.max(...scores) : null,
+            wins: 0,
+        };
+    });
+
+    // Head-to-head: for items scored under several variants, the variant with the best score wins that item.
+    const byItem = new Map();
+    for (const trial of experiment.trials) {
+        const score = scoreOf(trial);
+        if (score === null) continue;
+        const best = byItem.get(trial.item) || {};
+        if (!(best[trial.variant] >= score)) best[trial.variant] = score;
+        byItem.set(trial.item, best);
+    }
+    let comparedItems = 0;
+    for (const scores of byItem.values()) {
+        const entries = Object.entries(scores);
+        if (entries.length < 2) continue;
+        comparedItems++;
+        const top = Math.max(...entries.map(([, s]) => s));
+        const winners = entries.filter(([, s]) => s === top);
+        if (winners.length === 1) variants.find(v => v.variant === winners[0][0]).wins++;
+    }
+
+    const ranked = variants.filter(v => v.mean_score !== null).sort((a, b) => b.mean_score - a.mean_score || b.wins - a.wins);
+    const winner = ranked.length > 0 && (ranked.length === 1 || ranked[0].mean_score > ranked[1].mean_score || ranked[0].wins > ranked[1].wins) ? ranked[0].variant : null;
+    return {
+        success: true,
+        experiment: experiment.name,
+        variants,
+        compared_items: comparedItems,
+        winner,
+        unscored: experiment.trials.filter(t => !t.error && !t.scores).length,
+        message: winner ? `Variant "${winner}" leads with a mean score of ${ranked[0].mean_score}.` : "No winner yet: score more outputs with score_experiment.",
+    };
+}
+
+// Runs an edit on the active provider (or its fallbacks) without saving anything. `options.extraImages` ([{ mimeType, data }]) are sent as additional references where supported;
+// with `options.requireReferenceImages`, fallbacks that would drop them are skipped.
+async function runProviderEdit(base64Image, mimeType, prompt, options = {}) {
+    options = applyProviderOverride(options);
+    const image = sha256(Buffer.from(base64Image, "base64"));
+    return await runWithFallback("edit", options.provider || activeProvider, options, (attemptOptions) =>
+        coalesceProviderCall("edit", { image, mimeType, prompt, options: attemptOptions }, () => callProviderEdit(base64Image, mimeType, prompt, attemptOptions)));
+}
+
+async function callProviderEdit(base64Image, mimeType, prompt, options = {}) {
+    const provider = options.provider || activeProvider;
+    const entry = PROVIDER_REGISTRY[provider];
+    if (!entry.edit) throw new Error(`Image editing is not supported by the ${provider} provider.`);
+    return await entry.edit(base64Image, mimeType, prompt, options);
+}
+
+async function editImage(imagePath, outputPath = "output.png", prompt, options = {}) {
+    try {
+        const resolvedPath = path.resolve(imagePath);
+        if (!fs.existsSync(resolvedPath)) throw new Error(`Image file not found: ${resolvedPath}`);
+        
+        const imageBuffer = fs.readFileSync(resolvedPath);
+        const base64Image = imageBuffer.toString("base64");
+        const ext = path.extname(resolvedPath).toLowerCase();
+        const mimeTypes = { ".png": "image/png", ".jpg": "image/jpeg", ".jpeg": "image/jpeg", ".gif": "image/gif", ".webp": "image/webp" };
+        const mimeType = mimeTypes[ext] || "image/png";
+        // Looked up first so an unreadable source never costs a provider call.
+        const sourceMetadata = findGenerationMetadata(resolvedPath);
+        // Only the profile's text applies to edits; the image being edited already fixes the composition.
+        const styledPrompt = options.styleProfile ? applyStyleProfile(prompt, loadStyleProfile(options.styleProfile)) : prompt;
+
+        const { images: imageBuffers, metadata: providerMetadata, safeRewrite, provider, fallbacks } = await runWithSafeRewrite(
+            "edit_image", styledPrompt, options.safeRewrite || SAFE_REWRITE_STRATEGY, (p) => runProviderEdit(base64Image, mimeType, p, options));
+        outputPath = getOutputPathForMimeType(outputPath, providerMetadata?.mimeType);
+
+        const results = saveImageBuffers(imageBuffers, outputPath, {
+            tool: "edit_image",
+            request: { image_path: resolvedPath, prompt, ...options },
+            provider,
+            provider_metadata: providerMetadata,
+            source: { path: resolvedPath, sha256: sha256(imageBuffer), generation: sourceMetadata || undefined },
+            safe_rewrite: safeRewrite,
+            fallbacks,
+        }, options.writeMetadata);
+
+        const result = {
+            success: true,
+            output_paths: results,
+            provider,
+            message: `Image(s) edited successfully using ${provider}${fallbacks ? ` (fallback after ${fallbacks.map(f => f.provider).join(", ")} failed)` : ""}`,
+        };
+        if (fallbacks) {
+            result.fallbacks = fallbacks;
+        }
+        if (sourceMetadata) {
+            result.source_metadata = sourceMetadata;
+        }
+        if (safeRewrite) {
+            result.safe_rewrite = safeRewrite;
+        }
+        return result;
+    } catch (error) {
+        console.error("Error editing image:", error.response ? error.response.data : error.message);
+        throw error;
+    }
+}
+
+// --- Masked Editing ---
+
+// Builds a single-channel mask (white = editable) from a box or polygon.
+async function buildRegionMask(width, height, { box, polygon }, feather = 8) {
+    let shape;
+    if (box) {
+        shape = `<rect x="${box.x}" y="${box.y}" width="${box.width}" height="${box.height}" fill="#fff"/>`;
+    } else if (Array.isArray(polygon) && polygon.length >= 3) {
+        shape = `<polygon points="${polygon.map(([x, y]) => `${x},${y}`).join(" ")}" fill="#fff"/>`;
+    } else {
+        throw new Error("Provide a box ({ x, y, width, height }) or a polygon with at least 3 points.");
+    }
+    const svg = `<svg xmlns="http://www.w3.org/2000/svg" width="${width}" height="${height}"><rect width="100%" height="100%" fill="#000"/>${shape}</svg>`;
+    let mask = sharp(Buffer.from(svg)).greyscale();
+    if (feather > 0) mask = mask.blur(Math.max(0.3, feather / 2));
+    return await mask.toColourspace("b-w").png().toBuffer();
+}
+
+// Blends `edited` into `original` through `mask` (white = take edited), resizing the edit to the original size first.
+async function compositeWithMask(originalBuffer, editedBuffer, maskBuffer) {
+    const { width, height } = await sharp(originalBuffer).metadata();
+    const editedRgb = await sharp(editedBuffer).resize(width, height, { fit: "fill" }).removeAlpha().png().toBuffer();
+    const editedWithAlpha = await sharp(editedRgb).joinChannel(maskBuffer).png().toBuffer();
+    return await sharp(originalBuffer).composite([{ input: editedWithAlpha, top: 0, left: 0 }]).png().toBuffer();
+}
+
+// Edits the masked area and composites the result back so unmasked pixels stay untouched. Providers with native mask
+// support get the mask itself, those that take reference images (Gemini) get it as an extra image, and the rest only get
+// the prompt plus `regionHint` (a text description of the area); the composite does the masking either way.
+async function editWithMask(imageBuffer, mimeType, maskBuffer, prompt, options = {}) {
+    const { regionHint, ...editOptions } = options;
+    const provider = editOptions.provider || getProviderOverride()?.provider || activeProvider;
+    const capabilities = getProviderCapabilities(provider);
+    let guidedPrompt = prompt;
+    if (capabilities.includes("mask")) {
+        editOptions.mask = maskBuffer;
+    } else if (capabilities.includes("reference_images")) {
+        guidedPrompt = `${prompt}\n\nOnly change the area that is white in the attached mask image; keep everything else exactly as it is.`;
+        editOptions.extraImages = [{ mimeType: "image/png", data: maskBuffer.toString("base64") }];
+        editOptions.requireReferenceImages = true;
+    } else if (regionHint) {
+        guidedPrompt = `${prompt}\n\nOnly change ${regionHint}; keep everything else exactly as it is.`;
+    }
+    const { images, metadata, provider: usedProvider, fallbacks } = await runProviderEdit(imageBuffer.toString("base64"), mimeType, guidedPrompt, {
+        ...editOptions,
+        provider,
+        numberOfImages: 1,
+    });
+    return { image: await compositeWithMask(imageBuffer, images[0], maskBuffer), metadata, provider: usedProvider, fallbacks };
+}
+
+function describeRegion({ box, polygon }, width, height) {
+    if (box) return `the ${box.width}x${box.height} pixel rectangle whose top-left corner is at x=${box.x}, y=${box.y} in this ${width}x${height} image`;
+    return `the polygon with corners ${polygon.map(([x, y]) => `(${x}, ${y})`).join(", ")} (pixel coordinates in this ${width}x${height} image)`;
+}
+
+async function editRegion(args) {
+    try {
+        const resolvedPath = path.resolve(args.image_path);
+        if (!fs.existsSync(resolvedPath)) throw new Error(`Image file not found: ${resolvedPath}`);
+
+        const imageBuffer = fs.readFileSync(resolvedPath);
+        const { width, height } = await sharp(imageBuffer).metadata();
+        const mask = await buildRegionMask(width, height, { box: args.box, polygon: args.polygon }, args.feather ?? 8);
+        const { image, metadata, provider, fallbacks } = await editWithMask(imageBuffer, getMimeType(resolvedPath), mask, args.prompt, {
+            regionHint: describeRegion(args, width, height),
+        });
+
+        const [outputPath] = saveImageBuffers([image], args.output_path || "output.png", {
+            tool: "edit_region",
+            request: { image_path: resolvedPath, prompt: args.prompt, box: args.box, polygon: args.polygon, feather: args.feather },
+            provider,
+            provider_metadata: metadata,
+            source: { path: resolvedPath, sha256: sha256(imageBuffer) },
+            fallbacks,
+        });
+
+        const result = {
+            success: true,
+            output_paths: [outputPath],
+            provider,
+            message: `Region edited successfully using ${provider}${fallbacks ? ` (fallback after ${fallbacks.map(f => f.provider).join(", ")} failed)` : ""}`,
+        };
+        if (fallbacks) result.fallbacks = fallbacks;
+        return result;
+    } catch (error) {
+        console.error("Error editing region:", error.message);
+        throw error;
+    }
+}
+
+// Inpainting runs on a provider with native mask support: the requested one, else the active one, else the first
+// configured provider that can inpaint.
+function getInpaintProvider(requested) {
+    const supportsMask = (provider) => getProviderCapabilities(provider).includes("mask");
+    const maskProviders = Object.values(PROVIDERS).filter(supportsMask);
+    const unsupported = (provider) => new Error(`The ${provider} provider does not support inpainting with a mask. Providers that do: ${maskProviders.join(", ")}.`);
+    if (requested) {
+        if (!supportsMask(requested)) throw unsupported(requested);
+        return requested;
+    }
+    if (supportsMask(activeProvider)) return activeProvider;
+    const configured = getConfiguredProviders().find(supportsMask);
+    if (!configured) throw unsupported(activeProvider);
+    return configured;
+}
+
+async function loadInpaintMask(args, width, height) {
+    if (args.mask_path) {
+        const resolvedMask = path.resolve(args.mask_path);
+        if (!fs.existsSync(resolvedMask)) throw new Error(`Mask file not found: ${resolvedMask}`);
+        let mask = sharp(resolvedMask).flatten({ background: "#000000" }).resize(width, height, { fit: "fill" }).greyscale();
+        if (args.invert_mask) mask = mask.negate({ alpha: false });
+        if (args.feather > 0) mask = mask.blur(Math.max(0.3, args.feather / 2));
+        return await mask.toColourspace("b-w").png().toBuffer();
+    }
+    if (args.box) return await buildRegionMask(width, height, { box: args.box }, args.feather ?? 0);
+    throw new Error("Provide mask_path (white = area to repaint) or box ({ x, y, width, height }).");
+}
+
+async function inpaint(args) {
+    try {
+        const resolvedPath = path.resolve(args.image_path);
+        if (!fs.existsSync(resolvedPath)) throw new Error(`Image file not found: ${resolvedPath}`);
+
+        const imageBuffer = fs.readFileSync(resolvedPath);
+        const { width, height } = await sharp(imageBuffer).metadata();
+        const mask = await loadInpaintMask(args, width, height);
+        const provider = getInpaintProvider(getProviderOverride()?.provider);
+        const { images, metadata, provider: usedProvider, fallbacks } = await runProviderEdit(imageBuffer.toString("base64"), getMimeType(resolvedPath), args.prompt, {
+            provider,
+            mask,
+            numberOfImages: 1,
+            negativePrompt: args.negative_prompt,
+            seed: args.seed,
+            denoisingStrength: args.denoising_strength,
+        });
+        // Providers may return a resized or slightly shifted image; blending keeps unmasked pixels identical.
+        const image = await compositeWithMask(imageBuffer, images[0], mask);
+
+        const [outputPath] = saveImageBuffers([image], args.output_path || "output.png", {
+            tool: "inpaint",
+            request: { ...args, image_path: resolvedPath },
+            provider: usedProvider,
+            provider_metadata: metadata,
+            source: { path: resolvedPath, sha256: sha256(imageBuffer) },
+            fallbacks,
+        }, args.write_metadata);
+
+        const result = {
+            success: true,
+            output_paths: [outputPath],
+            provider: usedProvider,
+            message: `Inpainted successfully using ${usedProvider}${fallbacks ? ` (fallback after ${fallbacks.map(f => f.provider).join(", ")} failed)` : ""}`,
+        };
+        if (fallbacks) result.fallbacks = fallbacks;
+        return result;
+    } catch (error) {
+        console.error("Error inpainting:", error.message);
+        throw error;
+    }
+}
+
+// --- Outpainting ---
+
+const OUTPAINT_SIDES = {
+    left: ["left"], right: ["right"], top: ["top"], bottom: ["bottom"],
+    horizontal: ["left", "right"], vertical: ["top", "bottom"], all: ["left", "right", "top", "bottom"],
+};
+
+// White over the added border, reaching `overlap` pixels into the original so the seam is repainted as well.
+async function buildOutpaintMask(width, height, pad, overlap, feather) {
+    const rects = [];
+    if (pad.left) rects.push({ x: 0, y: 0, width: pad.left + overlap, height });
+    if (pad.right) rects.push({ x: width - pad.right - overlap, y: 0, width: pad.right + overlap, height });
+    if (pad.top) rects.push({ x: 0, y: 0, width, height: pad.top + overlap });
+    if (pad.bottom) rects.push({ x: 0, y: height - pad.bottom - overlap, width, height: pad.bottom + overlap });
+    const shapes = rects.map(r => `<rect x="${r.x}" y="${r.y}" width="${r.width}" height="${r.height}" fill="#fff"/>`).join("");
+    const svg = `<svg xmlns="http://www.w3.org/2000/svg" width="${width}" height="${height}"><rect width="100%" height="100%" fill="#000"/>${shapes}</svg>`;
+    let mask = sharp(Buffer.from(svg)).greyscale();
+    if (feather > 0) mask = mask.blur(Math.max(0.3, feather / 2));
+    return await mask.toColourspace("b-w").png().toBuffer();
+}
+
+// Extends the canvas and lets the provider fill the new area. Providers with native masks inpaint a mirrored border;
+// the others get a white border with the outpainting prompt (as in smart_crop's extend mode) and the mask as guidance.
+async function outpaint(args) {
+    try {
+        const resolvedPath = path.resolve(args.image_path);
+        if (!fs.existsSync(resolvedPath)) throw new Error(`Image file not found: ${resolvedPath}`);
+        const sides = OUTPAINT_SIDES[args.direction || "horizontal"];
+        if (!sides) throw new Error(`Unsupported direction: ${args.direction}. Use ${Object.keys(OUTPAINT_SIDES).join(", ")}.`);
+        const pixels = Math.round(args.pixels);
+        if (!(pixels > 0)) throw new Error("pixels must be a positive number.");
+
+        const imageBuffer = fs.readFileSync(resolvedPath);
+        const { width, height } = await sharp(imageBuffer).metadata();
+        const pad = { left: 0, right: 0, top: 0, bottom: 0 };
+        for (const side of sides) pad[side] = pixels;
+        const canvasWidth = width + pad.left + pad.right;
+        const canvasHeight = height + pad.top + pad.bottom;
+
+        const provider = getProviderOverride()?.provider || activeProvider;
+        const native = getProviderCapabilities(provider).includes("mask");
+        const padded = await sharp(imageBuffer)
+            .extend({ ...pad, ...(native ? { extendWith: "mirror" } : { background: { r: 255, g: 255, b: 255, alpha: 1 } }) })
+            .png()
+            .toBuffer();
+        const overlap = Math.min(args.overlap ?? 16, Math.floor(Math.min(width, height) / 4));
+        const mask = await buildOutpaintMask(canvasWidth, canvasHeight, pad, overlap, args.feather ?? 8);
+
+        let image;
+        let metadata;
+        let usedProvider = provider;
+        if (native) {
+            const prompt = args.prompt || "Continue the scene naturally beyond the original edges, matching its style, lighting and perspective.";
+            let images;
+            ({ images, metadata, provider: usedProvider } = await runProviderEdit(padded.toString("base64"), "image/png", prompt, {
+                mask,
+                numberOfImages: 1,
+                denoisingStrength: args.denoising_strength ?? 0.9,
+            }));
+            image = await compositeWithMask(padded, images[0], mask);
+        } else {
+            const prompt = args.prompt ? `${OUTPAINT_PROMPT}\n\nThe extended scene: ${args.prompt}` : OUTPAINT_PROMPT;
+            ({ image, metadata, provider: usedProvider } = await editWithMask(padded, "image/png", mask, prompt, { provider }));
+        }
+
+        const [outputPath] = saveImageBuffers([image], args.output_path || "output.png", {
+            tool: "outpaint",
+            request: { ...args, image_path: resolvedPath },
+            provider: usedProvider,
+            provider_metadata: metadata,
+            source: { path: resolvedPath, sha256: sha256(imageBuffer) },
+        }, args.write_metadata);
+
+        return {
+            success: true,
+            output_paths: [outputPath],
+            width: canvasWidth,
+            height: canvasHeight,
+            provider: usedProvider,
+            message: `Extended ${width}x${height} to ${canvasWidth}x${canvasHeight} using ${usedProvider}${native ? " (masked inpainting)" : ""}`,
+        };
+    } catch (error) {
+        console.error("Error outpainting image:", error.message);
+        throw error;
+    }
+}
+
+// --- Upscaling ---
+// Provider upscalers are used when the requested, active or any configured provider has one; results are resized to
+// exactly `scale` times the source, since some upscalers (Stability) only work at a fixed factor.
+
+const UPSCALE_SCALES = [2, 4];
+
+function getUpscaleProvider(requested) {
+    const supportsUpscale = (provider) => getProviderCapabilities(provider).includes("upscale");
+    if (requested) {
+        if (!supportsUpscale(requested)) {
+            throw new Error(`The ${requested} provider has no upscaler. Providers that do: ${Object.values(PROVIDERS).filter(supportsUpscale).join(", ")}.`);
+        }
+        return requested;
+    }
+    if (supportsUpscale(activeProvider)) return activeProvider;
+    return getConfiguredProviders().find(supportsUpscale) || null;
+}
+
+async function upscale(args) {
+    try {
+        const resolvedPath = path.resolve(args.image_path);
+        if (!fs.existsSync(resolvedPath)) throw new Error(`Image file not found: ${resolvedPath}`);
+        const scale = args.scale ?? 2;
+        if (!UPSCALE_SCALES.includes(scale)) throw new Error(`Unsupported scale: ${scale}. Use ${UPSCALE_SCALES.join(" or ")}.`);
+
+        const imageBuffer = fs.readFileSync(resolvedPath);
+        const { width, height } = await sharp(imageBuffer).metadata();
+        const targetWidth = width * scale;
+        const targetHeight = height * scale;
+
+        const provider = args.local ? null : getUpscaleProvider(getProviderOverride()?.provider);
+        let image;
+        let metadata;
+        if (provider) {
+            let images;
+            ({ images, metadata } = await PROVIDER_REGISTRY[provider].upscale(imageBuffer.toString("base64"), getBufferMimeType(imageBuffer), scale));
+            image = images[0];
+            const result = await sharp(image).metadata();
+            if (result.width !== targetWidth || result.height !== targetHeight) {
+                image = await sharp(image).resize(targetWidth, targetHeight, { fit: "fill", kernel: "lanczos3" }).png().toBuffer();
+            }
+        } else {
+            image = await sharp(imageBuffer).resize(targetWidth, targetHeight, { fit: "fill", kernel: "lanczos3" }).png().toBuffer();
+            metadata = { method: "lanczos3" };
+        }
+
+        const usedProvider = provider || "local";
+        const ext = path.extname(resolvedPath);
+        const outputPath = args.output_path || path.join(path.dirname(resolvedPath), `${path.basename(resolvedPath, ext)}_${scale}x.png`);
+        const [resolvedOutputPath] = saveImageBuffers([image], outputPath, {
+            tool: "upscale",
+            request: { ...args, image_path: resolvedPath },
+            provider: usedProvider,
+            provider_metadata: metadata,
+            source: { path: resolvedPath, sha256: sha256(imageBuffer) },
+        }, args.write_metadata);
+
+        return {
+            success: true,
+            output_paths: [resolvedOutputPath],
+            width: targetWidth,
+            height: targetHeight,
+            provider: usedProvider,
+            message: `Upscaled ${width}x${height} to ${targetWidth}x${targetHeight} using ${provider ? `the ${provider} upscaler` : "a local Lanczos resize"}`,
+        };
+    } catch (error) {
+        console.error("Error upscaling image:", error.message);
+        throw error;
+    }
+}
+
+// --- Style Transfer ---
+// The content image is edited with the style reference sent as a second image part, so only providers that accept
+// reference images on edits (see the registry capabilities) can do it.
+
+const STYLE_TRANSFER_STRENGTHS = {
+    subtle: "Apply the style lightly: keep the content image's own look recognisable and borrow mainly the palette and texture.",
+    balanced: "Apply the style clearly while keeping every subject and detail of the content image recognisable.",
+    strong: "Fully restyle the content image so it looks as if it had been made by the same artist, in the same medium, as the style reference.",
+};
+
+function getStyleTransferProvider(requested) {
+    const supportsReferences = (provider) => getProviderCapabilities(provider).includes("reference_images");
+    const referenceProviders = Object.values(PROVIDERS).filter(supportsReferences);
+    const unsupported = (provider) => new Error(`The ${provider} provider cannot take a style reference image. Providers that can: ${referenceProviders.join(", ")}.`);
+    if (requested) {
+        if (!supportsReferences(requested)) throw unsupported(requested);
+        return requested;
+    }
+    if (supportsReferences(activeProvider)) return activeProvider;
+    const configured = getConfiguredProviders().find(supportsReferences);
+    if (!configured) throw unsupported(activeProvider);
+    return configured;
+}
+
+async function styleTransfer(args) {
+    try {
+        const contentPath = path.resolve(args.content_image_path);
+        if (!fs.existsSync(contentPath)) throw new Error(`Content image not found: ${contentPath}`);
+        const stylePath = path.resolve(args.style_image_path);
+        if (!fs.existsSync(stylePath)) throw new Error(`Style image not found: ${stylePath}`);
+        const strength = args.strength || "balanced";
+        if (!STYLE_TRANSFER_STRENGTHS[strength]) throw new Error(`Unsupported strength: ${strength}. Use ${Object.keys(STYLE_TRANSFER_STRENGTHS).join(", ")}.`);
+
+        const contentBuffer = fs.readFileSync(contentPath);
+        const styleImage = loadReferenceImage(stylePath);
+        const prompt = [
+            "Redraw the first image in the artistic style of the second image: its color palette, brushwork or line quality, texture, lighting and medium.",
+            "Keep the first image's content, composition, subjects and poses; do not copy any objects or characters from the second image.",
+            STYLE_TRANSFER_STRENGTHS[strength],
+            args.prompt ? `Additional guidance: ${args.prompt}` : "",
+        ].filter(Boolean).join("\n");
+
+        const { images, metadata, provider: usedProvider, fallbacks } = await runProviderEdit(contentBuffer.toString("base64"), getMimeType(contentPath), prompt, {
+            provider: getStyleTransferProvider(getProviderOverride()?.provider),
+            model: getProviderOverride()?.model,
+            numberOfImages: 1,
+            extraImages: [styleImage],
+            requireReferenceImages: true,
+            aspectRatio: args.aspect_ratio,
+        });
+
+        const [outputPath] = saveImageBuffers(images, args.output_path || "output.png", {
+            tool: "style_transfer",
+            request: { ...args, content_image_path: contentPath, style_image_path: stylePath },
+            provider: usedProvider,
+            provider_metadata: metadata,
+            source: { path: contentPath, sha256: sha256(contentBuffer) },
+            style_reference: { path: stylePath, sha256: sha256(Buffer.from(styleImage.data, "base64")) },
+            fallbacks,
+        }, args.write_metadata);
+
+        const result = {
+            success: true,
+            output_paths: [outputPath],
+            provider: usedProvider,
+            message: `Style transferred successfully using ${usedProvider}${fallbacks ? ` (fallback after ${fallbacks.map(f => f.provider).join(", ")} failed)` : ""}`,
+        };
+        if (fallbacks) result.fallbacks = fallbacks;
+        return result;
+    } catch (error) {
+        console.error("Error transferring style:", error.message);
+        throw error;
+    }
+}
+
+// --- Pose Conditioning ---
+
+// OpenPose COCO-18 limbs and the conventional OpenPose color per keypoint.
+const OPENPOSE_LIMBS = [[1, 2], [1, 5], [2, 3], [3, 4], [5, 6], [6, 7], [1, 8], [8, 9], [9, 10], [1, 11], [11, 12], [12, 13], [1, 0], [0, 14], [14, 16], [0, 15], [15, 17]];
+const OPENPOSE_COLORS = ["#ff0000", "#ff5500", "#ffaa00", "#ffff00", "#aaff00", "#55ff00", "#00ff00", "#00ff55", "#00ffaa", "#00ffff", "#00aaff", "#0055ff", "#0000ff", "#5500ff", "#aa00ff", "#ff00ff", "#ff00aa", "#ff0055"];
+
+// Renders OpenPose keypoints JSON as a standard skeleton image (colored limbs on black).
+async function renderOpenPoseSkeleton(keypoints) {
+    const people = keypoints.people || [keypoints];
+    let width = keypoints.canvas_width;
+    let height = keypoints.canvas_height;
+    const poses = people.map(p => {
+        const flat = p.pose_keypoints_2d || p.keypoints || [];
+        const points = [];
+        for (let i = 0; i + 1 < flat.length && points.length < 18; i += 3) {
+            points.push({ x: flat[i], y: flat[i + 1], c: flat[i + 2] ?? 1 });
+        }
+        return points;
+    });
+    if (poses.every(p => p.length === 0)) throw new Error("pose_keypoints contains no pose_keypoints_2d data.");
+
+    if (!width || !height) {
+        const all = poses.flat().filter(p => p.c > 0);
+        width = width || Math.ceil(Math.max(...all.map(p => p.x)) * 1.1);
+        height = height || Math.ceil(Math.max(...all.map(p => p.y)) * 1.1);
+    }
+
+    const stroke = Math.max(4, Math.round(Math.min(width, height) / 100));
+    const shapes = [];
+    for (const points of poses) {
+        OPENPOSE_LIMBS.forEach(([a, b], i) => {
+            const p = points[a], q = points[b];
+            if (p && q && p.c > 0 && q.c > 0) {
+                shapes.push(`<line x1="${p.x}" y1="${p.y}" x2="${q.x}" y2="${q.y}" stroke="${OPENPOSE_COLORS[i]}" stroke-width="${stroke}" stroke-linecap="round" opacity="0.7"/>`);
+            }
+        });
+        points.forEach((p, i) => {
+            if (p.c > 0) shapes.push(`<circle cx="${p.x}" cy="${p.y}" r="${stroke * 0.8}" fill="${OPENPOSE_COLORS[i]}"/>`);
+        });
+    }
+    const svg = `<svg xmlns="http://www.w3.org/2000/svg" width="${width}" height="${height}"><rect width="100%" height="100%" fill="#000"/>${shapes.join("")}</svg>`;
+    return await sharp(Buffer.from(svg)).png().toBuffer();
+}
+
+async function generateWithPose(args) {
+    try {
+        let poseBuffer;
+        if (args.pose_keypoints) {
+            poseBuffer = await renderOpenPoseSkeleton(args.pose_keypoints);
+            if (args.skeleton_path) {
+                const resolvedSkeletonPath = path.resolve(args.skeleton_path);
+                fs.mkdirSync(path.dirname(resolvedSkeletonPath), { recursive: true });
+                fs.writeFileSync(resolvedSkeletonPath, poseBuffer);
+            }
+        } else if (args.pose_image_path) {
+            const resolvedPosePath = path.resolve(args.pose_image_path);
+            if (!fs.existsSync(resolvedPosePath)) throw new Error(`Pose image not found: ${resolvedPosePath}`);
+            poseBuffer = await sharp(resolvedPosePath).png().toBuffer();
+        } else {
+            throw new Error("Provide pose_image_path or pose_keypoints.");
+        }
+
+        let images, metadata;
+        const override = getProviderOverride();
+        const requested = override?.provider;
+        let provider = requested || activeProvider;
+        const poseModel = override?.model || REPLICATE_POSE_MODEL;
+        if (provider !== PROVIDERS.GEMINI && poseModel && REPLICATE_API_TOKEN && (!requested || requested === PROVIDERS.REPLICATE)) {
+            const output = await runReplicatePrediction(poseModel, {
+                image: `data:image/png;base64,${poseBuffer.toString("base64")}`,
+                prompt: args.prompt,
+            });
+            images = await downloadReplicateImages(output);
+            metadata = { model: poseModel };
+            provider = PROVIDERS.REPLICATE;
+        } else if (provider === PROVIDERS.GEMINI) {
+            const posePrompt = `${args.prompt}\n\nDraw the character in exactly the body pose shown by this OpenPose stick-figure skeleton (same limb angles, framing and proportions). Do not draw the skeleton itself.`;
+            ({ images, metadata } = await editImageGemini(poseBuffer.toString("base64"), "image/png", posePrompt, { numberOfImages: 1, model: override?.model }));
+        } else {
+            throw new Error("Pose conditioning requires Gemini, or a ControlNet pose model on Replicate configured via REPLICATE_POSE_MODEL.");
+        }
+
+        const results = saveImageBuffers(images, args.output_path || "output.png", {
+            tool: "generate_with_pose",
+            request: { prompt: args.prompt, pose_image_path: args.pose_image_path, pose_keypoints: args.pose_keypoints ? true : undefined },
+            provider,
+            provider_metadata: metadata,
+        });
+
+        return {
+            success: true,
+            output_paths: results,
+            skeleton_path: args.skeleton_path ? path.resolve(args.skeleton_path) : undefined,
+            message: `Pose-conditioned image generated using ${metadata.model}`,
+        };
+    } catch (error) {
+        console.error("Error generating with pose:", error.response ? error.response.data : error.message);
+        throw error;
+    }
+}
+
+async function removeBackground(imagePath, outputPath, writeMetadata) {
+    try {
+        const resolvedPath = path.resolve(imagePath);
+        if (!fs.existsSync(resolvedPath)) throw new Error(`Image file not found: ${resolvedPath}`);
+        
+        const imageBuffer = fs.readFileSync(resolvedPath);
+        const base64Image = imageBuffer.toString("base64");
+        const ext = path.extname(resolvedPath).toLowerCase();
+        const mimeTypes = { ".png": "image/png", ".jpg": "image/jpeg", ".jpeg": "image/jpeg", ".gif": "image/gif", ".webp": "image/webp" };
+        const mimeType = mimeTypes[ext] || "image/png";
+
+        let imageBufferResult = null;
+        let usedProvider = "";
+
+        // Priority: Replicate > Hugging Face
+        if (REPLICATE_API_TOKEN) {
+            try {
+                console.error("[System] Attempting background removal with Replicate...");
+                imageBufferResult = await removeBackgroundReplicate(base64Image, mimeType);
+                usedProvider = "Replicate";
+            } catch (err) {
+                console.error("Replicate background removal failed, falling back if possible:", err.message);
+            }
+        }
+        
+        if (!imageBufferResult) {
+            // Fallback to Hugging Face (Space doesn't necessarily need token, or we can use the Space implementation)
+             console.error("[System] Attempting background removal with Hugging Face Space...");
+             imageBufferResult = await removeBackgroundHuggingFace(imageBuffer);
+             usedProvider = "Hugging Face";
+        }
+
+        if (!imageBufferResult) {
+            throw new Error("Background removal failed with all available providers.");
+        }
+
+        // Determine output path
+        if (!outputPath) {
+             const dir = path.dirname(resolvedPath);
+             const name = path.basename(resolvedPath, ext);
+             outputPath = path.join(dir, `${name}_nobg.png`);
+        }
+
+        const [resolvedOutputPath] = saveImageBuffers([imageBufferResult], outputPath, {
+            tool: "remove_background",
+            request: { image_path: resolvedPath },
+            provider: usedProvider,
+            source: { path: resolvedPath, sha256: sha256(imageBuffer) },
+        }, writeMetadata);
+
+        return {
+            success: true,
+            output_paths: [resolvedOutputPath],
+            message: `Background removed successfully using ${usedProvider}`,
+        };
+
+    } catch (error) {
+        console.error("Error removing background:", error.message);
+        throw error;
+    }
+}
+
+// --- New Tools Implementation (Sharp) ---
+
+async function convertImage(sourcePath, outputPath, format) {
+    try {
+        const resolvedSourcePath = path.resolve(sourcePath);
+        if (!fs.existsSync(resolvedSourcePath)) throw new Error(`Source image not found: ${resolvedSourcePath}`);
+
+        // Normalize format
+        format = format.toLowerCase().replace('.', '');
+        if (format === 'jpg') format = 'jpeg';
+        
+        const validFormats = ['png', 'jpeg', 'webp', 'gif', 'tiff', 'avif', 'heif'];
+        if (!validFormats.includes(format)) throw new Error(`Unsupported format: ${format}`);
+
+        // Determine output path if not provided
+        if (!outputPath) {
+            const dir = path.dirname(resolvedSourcePath);
+            const name = path.basename(resolvedSourcePath, path.extname(resolvedSourcePath));
+            outputPath = path.join(dir, `${name}.${format}`);
+        }
+        
+        const resolvedOutputPath = path.resolve(outputPath);
+        const dirOutput = path.dirname(resolvedOutputPath);
+        if (!fs.existsSync(dirOutput)) {
+            fs.mkdirSync(dirOutput, { recursive: true });
+        }
+
+        await sharp(resolvedSourcePath)
+            .toFormat(format)
+            .toFile(resolvedOutputPath);
+
+        return {
+            success: true,
+            output_path: resolvedOutputPath,
+            message: `Image converted to ${format} successfully.`
+        };
+
+    } catch (error) {
+        console.error("Error converting image:", error.message);
+        throw error;
+    }
+}
+
+async function resizeImage(sourcePath, outputPath, width, height, fit = 'cover') {
+    try {
+        const resolvedSourcePath = path.resolve(sourcePath);
+        if (!fs.existsSync(resolvedSourcePath)) throw new Error(`Source image not found: ${resolvedSourcePath}`);
+
+        if (!outputPath) {
+             const dir = path.dirname(resolvedSourcePath);
+             const name = path.basename(resolvedSourcePath, path.extname(resolvedSourcePath));
+             outputPath = path.join(dir, `${name}_resized${path.extname(resolvedSourcePath)}`);
+        }
+
+        const resolvedOutputPath = path.resolve(outputPath);
+        const dirOutput = path.dirname(resolvedOutputPath);
+        if (!fs.existsSync(dirOutput)) {
+            fs.mkdirSync(dirOutput, { recursive: true });
+        }
+
+        await sharp(resolvedSourcePath)
+            .resize({
+                width: width,
+                height: height,
+                fit: fit
+            })
+            .toFile(resolvedOutputPath);
+
+        return {
+            success: true,
+            output_path: resolvedOutputPath,
+            message: `Image resized to ${width}x${height || 'auto'} successfully.`
+        };
+
+    } catch (error) {
+        console.error("Error resizing image:", error.message);
+        throw error;
+    }
+}
+
+async function getImageInfo(imagePath) {
+    try {
+        const resolvedPath = path.resolve(imagePath);
+        if (!fs.existsSync(resolvedPath)) throw new Error(`Image file not found: ${resolvedPath}`);
+
+        const metadata = await sharp(resolvedPath).metadata();
+        
+        return {
+            success: true,
+            info: {
+                format: metadata.format,
+                width: metadata.width,
+                height: metadata.height,
+                channels: metadata.channels,
+                size: fs.statSync(resolvedPath).size
+            }
+        };
+
+    } catch (error) {
+        console.error("Error getting image info:", error.message);
+        throw error;
+    }
+}
+
+// --- Local Rendering (SVG) ---
+
+const CHART_PALETTE = ["#4e79a7", "#f28e2b", "#e15759", "#76b7b2", "#59a14f", "#edc948", "#b07aa1", "#ff9da7", "#9c755f", "#bab0ac"];
+const SVG_FONT = "DejaVu Sans, Arial, Helvetica, sans-serif";
+
+function escapeXml(text) {
+    return String(text).replace(/&/g, "&amp;").replace(/</g, "&lt;").replace(/>/g, "&gt;").replace(/"/g, "&quot;").replace(/'/g, "&apos;");
+}
+
+// Writes an SVG document either as-is (format 'svg') or rasterized to PNG at `scale` times its nominal size.
+async function saveRenderedSvg(svg, outputPath, format = "png", scale = 1) {
+    format = (format || "png").toLowerCase();
+    if (format !== "png" && format !== "svg") throw new Error(`Unsupported output format: ${format}. Use 'png' or 'svg'.`);
+
+    const resolvedOutputPath = path.resolve(outputPath);
+    fs.mkdirSync(path.dirname(resolvedOutputPath), { recursive: true });
+    if (format === "svg") {
+        fs.writeFileSync(resolvedOutputPath, svg);
+    } else {
+        await sharp(Buffer.from(svg), { density: 72 * scale }).png().toFile(resolvedOutputPath);
+    }
+    return resolvedOutputPath;
+}
+
+// Round axis ticks covering [min, max] with roughly `count` steps of 1, 2, 2.5 or 5 x 10^n.
+function niceTicks(min, max, count = 5) {
+    if (min === max) {
+        max = min === 0 ? 1 : min + Math.abs(min);
+    }
+    const rawStep = (max - min) / count;
+    const magnitude = Math.pow(10, Math.floor(Math.log10(rawStep)));
+    const step = [1, 2, 2.5, 5, 10].map(m => m * magnitude).find(s => s >= rawStep);
+    const start = Math.floor(min / step) * step;
+    const ticks = [];
+    for (let v = start; v <= max + step * 1e-9; v += step) {
+        ticks.push(Number(v.toFixed(10)));
+    }
+    if (ticks[ticks.length - 1] < max) ticks.push(Number((ticks[ticks.length - 1] + step).toFixed(10)));
+    return ticks;
+}
+
+function formatTick(value) {
+    const abs = Math.abs(value);
+    if (abs >= 1e9) return `${+(value / 1e9).toFixed(2)}B`;
+    if (abs >= 1e6) return `${+(value / 1e6).toFixed(2)}M`;
+    if (abs >= 1e4) return `${+(value / 1e3).toFixed(1)}k`;
+    return String(+value.toFixed(4));
+}
+
+function buildChartSvg({ type, labels, series, title, x_label, y_label, width = 800, height = 500 }) {
+    if (!["bar", "line", "pie"].includes(type)) throw new Error(`Unsupported chart type: ${type}. Use 'bar', 'line' or 'pie'.`);
+    if (!Array.isArray(labels) || labels.length === 0) throw new Error("labels must be a non-empty array.");
+    if (!Array.isArray(series) || series.length === 0) throw new Error("series must be a non-empty array.");
+    series.forEach((s, i) => {
+        if (!Array.isArray(s.values) || s.values.length !== labels.length) {
+            throw new Error(`Series ${s.name || i + 1} must have exactly one value per label (${labels.length}).`);
+        }
+    });
+
+    // Colors are user input interpolated into attributes, so they are escaped like any other text.
+    const colors = series.map((s, i) => escapeXml(String(s.color || CHART_PALETTE[i % CHART_PALETTE.length])));
+    const parts = [];
+    const top = title ? 50 : 20;
+    const showLegend = type === "pie" || series.length > 1;
+
+    if (title) {
+        parts.push(`<text x="${width / 2}" y="32" text-anchor="middle" font-size="20" font-weight="bold">${escapeXml(title)}</text>`);
+    }
+
+    if (type === "pie") {
+        const values = series[0].values.map(v => Math.max(0, v));
+        const total = values.reduce((a, b) => a + b, 0);
+        if (total <= 0) throw new Error("Pie chart values must sum to a positive number.");
+        const legendWidth = 220;
+        const radius = Math.min(width - legendWidth - 40, height - top - 20) / 2;
+        const cx = 20 + radius;
+        const cy = top + (height - top) / 2;
+        let angle = -Math.PI / 2;
+        // Slices take the series' `colors`, then its `color` for the first slice, then the default palette.
+        const sliceColors = series[0].color ? [series[0].color, ...CHART_PALETTE.filter(c => c !== series[0].color)] : CHART_PALETTE;
+        values.forEach((v, i) => {
+            const color = escapeXml(String(series[0].colors?.[i] || sliceColors[i % sliceColors.length]));
+            const sweep = (v / total) * Math.PI * 2;
+            if (sweep >= Math.PI * 2 - 1e-9) {
+                parts.push(`<circle cx="${cx}" cy="${cy}" r="${radius}" fill="${color}"/>`);
+            } else if (sweep > 0) {
+                const x1 = cx + radius * Math.cos(angle), y1 = cy + radius * Math.sin(angle);
+                const x2 = cx + radius * Math.cos(angle + sweep), y2 = cy + radius * Math.sin(angle + sweep);
+                parts.push(`<path d="M${cx},${cy} L${x1.toFixed(2)},${y1.toFixed(2)} A${radius},${radius} 0 ${sweep > Math.PI ? 1 : 0} 1 ${x2.toFixed(2)},${y2.toFixed(2)} Z" fill="${color}" stroke="#fff" stroke-width="1.5"/>`);
+            }
+            angle += sweep;
+            const ly = top + 10 + i * 24;
+            parts.push(`<rect x="${width - legendWidth}" y="${ly}" width="14" height="14" fill="${color}"/>`);
+            parts.push(`<text x="${width - legendWidth + 22}" y="${ly + 12}" font-size="13">${escapeXml(labels[i])} (${(v / total * 100).toFixed(1)}%)</text>`);
+        });
+    } else {
+        const legendHeight = showLegend ? 30 : 0;
+        const left = 70 + (y_label ? 20 : 0);
+        const right = 20;
+        const bottom = 50 + (x_label ? 20 : 0) + legendHeight;
+        const plotWidth = width - left - right;
+        const plotHeight = height - top - bottom;
+        const all = series.flatMap(s => s.values);
+        const ticks = niceTicks(Math.min(0, ...all), Math.max(0, ...all));
+        const yMin = ticks[0], yMax = ticks[ticks.length - 1];
+        const y = v => top + plotHeight - ((v - yMin) / (yMax - yMin)) * plotHeight;
+        const band = plotWidth / labels.length;
+
+        for (const t of ticks) {
+            parts.push(`<line x1="${left}" y1="${y(t).toFixed(2)}" x2="${left + plotWidth}" y2="${y(t).toFixed(2)}" stroke="#e0e0e0"/>`);
+            parts.push(`<text x="${left - 8}" y="${(y(t) + 4).toFixed(2)}" text-anchor="end" font-size="12">${formatTick(t)}</text>`);
+        }
+        labels.forEach((label, i) => {
+            parts.push(`<text x="${(left + band * (i + 0.5)).toFixed(2)}" y="${top + plotHeight + 20}" text-anchor="middle" font-size="12">${escapeXml(label)}</text>`);
+        });
+
+        if (type === "bar") {
+            const groupWidth = band * 0.8;
+            const barWidth = groupWidth / series.length;
+            series.forEach((s, si) => {
+                s.values.forEach((v, i) => {
+                    const x = left + band * i + (band - groupWidth) / 2 + barWidth * si;
+                    const y0 = y(Math.max(0, v)), y1 = y(Math.min(0, v));
+                    parts.push(`<rect x="${x.toFixed(2)}" y="${y0.toFixed(2)}" width="${Math.max(1, barWidth - 2).toFixed(2)}" height="${(y1 - y0).toFixed(2)}" fill="${colors[si]}"/>`);
+                });
+            });
+        } else {
+            series.forEach((s, si) => {
+                const points = s.values.map((v, i) => `${(left + band * (i + 0.5)).toFixed(2)},${y(v).toFixed(2)}`);
+                parts.push(`<polyline points="${points.join(" ")}" fill="none" stroke="${colors[si]}" stroke-width="2.5"/>`);
+                points.forEach(p => {
+                    const [px, py] = p.split(",");
+                    parts.push(`<circle cx="${px}" cy="${py}" r="3.5" fill="${colors[si]}"/>`);
+                });
+            });
+        }
+
+        parts.push(`<line x1="${left}" y1="${y(0).toFixed(2)}" x2="${left + plotWidth}" y2="${y(0).toFixed(2)}" stroke="#333"/>`);
+        parts.push(`<line x1="${left}" y1="${top}" x2="${left}" y2="${top + plotHeight}" stroke="#333"/>`);
+        if (x_label) {
+            parts.push(`<text x="${left + plotWidth / 2}" y="${top + plotHeight + 45}" text-anchor="middle" font-size="14">${escapeXml(x_label)}</text>`);
+        }
+        if (y_label) {
+            parts.push(`<text transform="translate(18 ${top + plotHeight / 2}) rotate(-90)" text-anchor="middle" font-size="14">${escapeXml(y_label)}</text>`);
+        }
+        if (showLegend) {
+            let lx = left;
+            const ly = height - legendHeight + 5;
+            series.forEach((s, si) => {
+                const name = s.name || `Series ${si + 1}`;
+                parts.push(`<rect x="${lx}" y="${ly}" width="14" height="14" fill="${colors[si]}"/>`);
+                parts.push(`<text x="${lx + 20}" y="${ly + 12}" font-size="13">${escapeXml(name)}</text>`);
+                lx += 40 + name.length * 8;
+            });
+        }
+    }
+
+    return `<svg xmlns="http://www.w3.org/2000/svg" width="${width}" height="${height}" viewBox="0 0 ${width} ${height}" font-family="${SVG_FONT}" fill="#222">` +
+        `<rect width="100%" height="100%" fill="#fff"/>${parts.join("")}</svg>`;
+}
+
+async function renderChart(args) {
+    try {
+        const format = (args.format || "png").toLowerCase();
+        const svg = buildChartSvg(args);
+        const outputPath = await saveRenderedSvg(svg, args.output_path || `chart.${format}`, format);
+        return {
+            success: true,
+            output_path: outputPath,
+            message: `${args.type} chart rendered as ${format.toUpperCase()}.`
+        };
+    } catch (error) {
+        console.error("Error rendering chart:", error.message);
+        throw error;
+    }
+}
+
+function buildPlaceholderSvg({ width, height, style = "solid", color = "#cccccc", color2 = "#999999", label, text_color = "#333333" }) {
+    width = Math.round(width);
+    height = Math.round(height || width);
+    if (!(width > 0) || !(height > 0)) throw new Error("width and height must be positive numbers.");
+
+    const c1 = escapeXml(color), c2 = escapeXml(color2);
+    const cell = Math.max(8, Math.round(Math.min(width, height) / 16));
+    let defs = "";
+    let fill = c1;
+    if (style === "gradient") {
+        defs = `<linearGradient id="p" x1="0" y1="0" x2="1" y2="1"><stop offset="0" stop-color="${c1}"/><stop offset="1" stop-color="${c2}"/></linearGradient>`;
+        fill = "url(#p)";
+    } else if (style === "stripes") {
+        defs = `<pattern id="p" width="${cell * 2}" height="${cell * 2}" patternUnits="userSpaceOnUse" patternTransform="rotate(45)"><rect width="${cell * 2}" height="${cell * 2}" fill="${c1}"/><rect width="${cell}" height="${cell * 2}" fill="${c2}"/></pattern>`;
+        fill = "url(#p)";
+    } else if (style === "checker") {
+        defs = `<pattern id="p" width="${cell * 2}" height="${cell * 2}" patternUnits="userSpaceOnUse"><rect width="${cell * 2}" height="${cell * 2}" fill="${c1}"/><rect width="${cell}" height="${cell}" fill="${c2}"/><rect x="${cell}" y="${cell}" width="${cell}" height="${cell}" fill="${c2}"/></pattern>`;
+        fill = "url(#p)";
+    } else if (style === "grid") {
+        defs = `<pattern id="p" width="${cell * 2}" height="${cell * 2}" patternUnits="userSpaceOnUse"><rect width="${cell * 2}" height="${cell * 2}" fill="${c1}"/><path d="M${cell * 2} 0H0V${cell * 2}" fill="none" stroke="${c2}" stroke-width="1"/></pattern>`;
+        fill = "url(#p)";
+    } else if (style !== "solid") {
+        throw new Error(`Unknown style: ${style}. Use solid, gradient, stripes, checker or grid.`);
+    }
+
+    const text = label === undefined ? `${width}×${height}` : label;
+    const fontSize = Math.max(10, Math.min(Math.round(height / 5), Math.round(width / Math.max(4, text.length * 0.6))));
+    const labelSvg = text ? `<text x="50%" y="50%" dominant-baseline="central" text-anchor="middle" font-family="${SVG_FONT}" font-size="${fontSize}" fill="${escapeXml(text_color)}">${escapeXml(text)}</text>` : "";
+
+    return `<svg xmlns="http://www.w3.org/2000/svg" width="${width}" height="${height}" viewBox="0 0 ${width} ${height}">` +
+        (defs ? `<defs>${defs}</defs>` : "") + `<rect width="100%" height="100%" fill="${fill}"/>${labelSvg}</svg>`;
+}
+
+async function createPlaceholder(args) {
+    try {
+        const format = (args.format || "png").toLowerCase();
+        const svg = buildPlaceholderSvg(args);
+        const outputPath = await saveRenderedSvg(svg, args.output_path || `placeholder.${format}`, format);
+        return {
+            success: true,
+            output_path: outputPath,
+            message: `Placeholder ${Math.round(args.width)}x${Math.round(args.height || args.width)} created.`
+        };
+    } catch (error) {
+        console.error("Error creating placeholder:", error.message);
+        throw error;
+    }
+}
+
+// CJK ideographs, kana, hangul and full-width forms: rendered about twice as wide as Latin letters and breakable between any two characters.
+const CJK_CHAR = /[\u1100-\u11ff\u2e80-\u2fdf\u3000-\u303f\u3040-\u30ff\u3100-\u31ff\u3400-\u4dbf\u4e00-\u9fff\uac00-\ud7af\uf900-\ufaff\uff00-\uffef]/;
+const TEXT_TOKEN = /[\u1100-\u11ff\u2e80-\u2fdf\u3000-\u303f\u3040-\u30ff\u3100-\u31ff\u3400-\u4dbf\u4e00-\u9fff\uac00-\ud7af\uf900-\ufaff\uff00-\uffef]|[^\s\u1100-\u11ff\u2e80-\u2fdf\u3000-\u303f\u3040-\u30ff\u3100-\u31ff\u3400-\u4dbf\u4e00-\u9fff\uac00-\ud7af\uf900-\ufaff\uff00-\uffef]+|\s+/g;
+// Kinsoku shori: characters that may not start a line, and characters that may not end one.
+const NO_LINE_START = "、。，．・：；？！ー」』）〕］｝〉》】〙〗’”ゝゞヽヾ々ぁぃぅぇぉっゃゅょゎゕゖァィゥェォッャュョヮヵヶ,.:;!?)]}%";
+const NO_LINE_END = "「『（〔［｛〈《【〘〖‘“([{";
+const RTL_CHAR = /[\u0590-\u08ff\ufb1d-\ufdff\ufe70-\ufeff]/;
+
+// Width of a string in half-width character units.
+function measureTextUnits(text) {
+    let units = 0;
+    for (const ch of text) units += CJK_CHAR.test(ch) ? 2 : 1;
+    return units;
+}
+
+// SVG attributes for right-to-left scripts (Hebrew, Arabic, ...). With direction="rtl", text-anchor="start" anchors at the right edge.
+function textDirectionAttributes(text) {
+    return RTL_CHAR.test(text) ? ` direction="rtl" unicode-bidi="embed"` : "";
+}
+
+// Greedy wrap to at most `maxChars` half-width units per line. Latin text breaks at spaces (overlong words are hard-split);
+// CJK text may break between any two characters, except where kinsoku rules forbid it.
+function wrapText(text, maxChars) {
+    const lines = [];
+    for (const paragraph of String(text).split("\n")) {
+        const tokens = [];
+        for (const token of paragraph.match(TEXT_TOKEN) || []) {
+            const prev = tokens[tokens.length - 1];
+            const glued = prev && !/^\s/.test(prev) && !/^\s/.test(token) &&
+                (NO_LINE_START.includes(token[0]) || NO_LINE_END.includes(prev[prev.length - 1]));
+            if (glued) tokens[tokens.length - 1] = prev + token;
+            else tokens.push(token);
+        }
+
+        let line = "";
+        let pendingSpace = false;
+        for (let word of tokens) {
+            if (/^\s/.test(word)) { pendingSpace = line.length > 0; continue; }
+            const separator = pendingSpace ? " " : "";
+            pendingSpace = false;
+            while (measureTextUnits(word) > maxChars) {
+                if (line) { lines.push(line); line = ""; }
+                let head = "";
+                for (const ch of word) {
+                    if (head && measureTextUnits(head + ch) > maxChars) break;
+                    head += ch;
+                }
+                lines.push(head);
+                word = word.slice(head.length);
+            }
+            if (!word) continue;
+            if (!line) line = word;
+            else if (measureTextUnits(line + separator + word) <= maxChars) line += separator + word;
+            else { lines.push(line); line = word; }
+        }
+        lines.push(line);
+    }
+    return lines;
+}
+
+const MEME_FONT = "Impact, Anton, 'Arial Black', 'DejaVu Sans', sans-serif";
+
+// Fits `text` into a box of `width` x `maxHeight`, shrinking the font until the wrapped lines fit.
+function fitTextLines(text, width, maxHeight, startSize, charWidthRatio = 0.55, lineHeightRatio = 1.1) {
+    let fontSize = startSize;
+    let lines;
+    do {
+        lines = wrapText(text, Math.max(1, Math.floor(width / (fontSize * charWidthRatio))));
+        if (lines.length * fontSize * lineHeightRatio <= maxHeight) break;
+        fontSize -= 2;
+    } while (fontSize > 10);
+    return { lines, fontSize, lineHeight: fontSize * lineHeightRatio };
+}
+
+function buildMemeTextSvg(text, width, height, position) {
+    const margin = Math.round(width * 0.04);
+    const { lines, fontSize, lineHeight } = fitTextLines(text.toUpperCase(), width - margin * 2, height * 0.3, Math.round(width / 9));
+    const blockHeight = lines.length * lineHeight;
+    const startY = position === "top" ? margin + fontSize : height - margin - blockHeight + fontSize * 0.9;
+    const tspans = lines.map((l, i) => `<tspan x="${width / 2}" y="${(startY + i * lineHeight).toFixed(1)}">${escapeXml(l)}</tspan>`).join("");
+    return `<text text-anchor="middle"${textDirectionAttributes(text)} font-family="${MEME_FONT}" font-size="${fontSize}" font-weight="bold" fill="#fff" stroke="#000" stroke-width="${Math.max(2, fontSize / 12).toFixed(1)}" paint-order="stroke" stroke-linejoin="round">${tspans}</text>`;
+}
+
+async function makeMeme(args) {
+    let generatedPath = null;
+    try {
+        let imagePath = args.image_path;
+        const outputPath = path.resolve(args.output_path || "meme.png");
+        fs.mkdirSync(path.dirname(outputPath), { recursive: true });
+
+        if (!imagePath) {
+            if (!args.prompt) throw new Error("Provide image_path or prompt.");
+            const generated = await generateImageFromText(args.prompt, path.join(os.tmpdir(), `mcp-meme-${process.pid}-${Date.now()}.png`), { numberOfImages: 1 });
+            imagePath = generatedPath = generated.output_paths[0];
+        }
+        const resolvedPath = path.resolve(imagePath);
+        if (!fs.existsSync(resolvedPath)) throw new Error(`Image file not found: ${resolvedPath}`);
+
+        const { width, height } = await sharp(resolvedPath).metadata();
+        const template = args.template || "top_bottom";
+
+        if (template === "top_bottom") {
+            if (!args.top_text && !args.bottom_text) throw new Error("Provide top_text and/or bottom_text.");
+            const texts = [];
+            if (args.top_text) texts.push(buildMemeTextSvg(args.top_text, width, height, "top"));
+            if (args.bottom_text) texts.push(buildMemeTextSvg(args.bottom_text, width, height, "bottom"));
+            const overlay = `<svg xmlns="http://www.w3.org/2000/svg" width="${width}" height="${height}">${texts.join("")}</svg>`;
+            await sharp(resolvedPath).composite([{ input: Buffer.from(overlay), top: 0, left: 0 }]).png().toFile(outputPath);
+        } else if (template === "caption") {
+            if (!args.caption) throw new Error("Provide caption.");
+            const padding = Math.round(width * 0.05);
+            const { lines, fontSize, lineHeight } = fitTextLines(args.caption, width - padding * 2, height * 0.5, Math.max(18, Math.round(width / 18)), 0.5, 1.3);
+            const barHeight = Math.round(lines.length * lineHeight + padding * 2);
+            const direction = textDirectionAttributes(args.caption);
+            const x = direction ? width - padding : padding;
+            const tspans = lines.map((l, i) => `<tspan x="${x}" y="${(padding + fontSize + i * lineHeight).toFixed(1)}">${escapeXml(l)}</tspan>`).join("");
+            const overlay = `<svg xmlns="http://www.w3.org/2000/svg" width="${width}" height="${barHeight}"><text${direction} font-family="${SVG_FONT}" font-size="${fontSize}" fill="#000">${tspans}</text></svg>`;
+            await sharp(resolvedPath)
+                .extend({ top: barHeight, background: { r: 255, g: 255, b: 255, alpha: 1 } })
+                .composite([{ input: Buffer.from(overlay), top: 0, left: 0 }])
+                .png()
+                .toFile(outputPath);
+        } else {
+            throw new Error(`Unknown template: ${template}. Use 'top_bottom' or 'caption'.`);
+        }
+
+        return {
+            success: true,
+            output_path: outputPath,
+            message: `Meme created with the ${template} template.`
+        };
+    } catch (error) {
+        console.error("Error making meme:", error.message);
+        throw error;
+    } finally {
+        if (generatedPath) fs.rmSync(generatedPath, { force: true });
+    }
+}
+
+function detectDiagramLanguage(source) {
+    // Mermaid flowcharts also start with "graph", but only DOT follows it with a "{" body.
+    return /^\s*(strict\s+)?(di)?graph\b[^\n{]*\{/i.test(source) ? "dot" : "mermaid";
+}
+
+async function renderDiagram(args) {
+    const tmpDir = fs.mkdtempSync(path.join(os.tmpdir(), "mcp-diagram-"));
+    try {
+        const language = (args.language || detectDiagramLanguage(args.source)).toLowerCase();
+        const format = (args.format || "png").toLowerCase();
+        if (format !== "png" && format !== "svg") throw new Error(`Unsupported output format: ${format}. Use 'png' or 'svg'.`);
+
+        const resolvedOutputPath = path.resolve(args.output_path || `diagram.${format}`);
+        fs.mkdirSync(path.dirname(resolvedOutputPath), { recursive: true });
+        const scale = args.scale || 2;
+
+        let command, commandArgs;
+        if (language === "dot" || language === "graphviz") {
+            const input = path.join(tmpDir, "diagram.dot");
+            fs.writeFileSync(input, args.source);
+            command = GRAPHVIZ_DOT_PATH;
+            commandArgs = [`-T${format}`, ...(format === "png" ? [`-Gdpi=${Math.round(96 * scale)}`] : []), input, "-o", resolvedOutputPath];
+        } else if (language === "mermaid") {
+            const input = path.join(tmpDir, "diagram.mmd");
+            fs.writeFileSync(input, args.source);
+            command = MERMAID_CLI_PATH;
+            commandArgs = ["-i", input, "-o", resolvedOutputPath, "-b", "white", "-s", String(scale)];
+            if (args.theme) commandArgs.push("-t", args.theme);
+        } else {
+            throw new Error(`Unsupported diagram language: ${language}. Use 'mermaid' or 'dot'.`);
+        }
+
+        try {
+            await execFileAsync(command, commandArgs, { timeout: 60000 });
+        } catch (err) {
+            if (err.code === "ENOENT") {
+                const hint = command === GRAPHVIZ_DOT_PATH ? "Install Graphviz or set GRAPHVIZ_DOT_PATH" : "Install @mermaid-js/mermaid-cli or set MERMAID_CLI_PATH";
+                throw new Error(`${command} not found. ${hint}.`);
+            }
+            throw new Error(`${language} rendering failed: ${(err.stderr || err.message).toString().trim()}`);
+        }
+
+        return {
+            success: true,
+            output_path: resolvedOutputPath,
+            message: `${language} diagram rendered as ${format.toUpperCase()}.`
+        };
+    } catch (error) {
+        console.error("Error rendering diagram:", error.message);
+        throw error;
+    } finally {
+        fs.rmSync(tmpDir, { recursive: true, force: true });
+    }
+}
+
+function buildMathDocument(latex, display = true, color) {
+    const hex = color && /^#?[0-9a-f]{6}$/i.test(color) ? color.replace("#", "").toUpperCase() : null;
+    return [
+        "\\documentclass[preview,border=2pt]{standalone}",
+        "\\usepackage{amsmath,amssymb}",
+        hex ? "\\usepackage{xcolor}" : "",
+        "\\begin{document}",
+        hex ? `\\color[HTML]{${hex}}` : "",
+        display ? `$\\displaystyle ${latex}$` : `$${latex}$`,
+        "\\end{document}",
+        "",
+    ].filter(line => line !== "").join("\n") + "\n";
+}
+
+async function renderMath(args) {
+    const tmpDir = fs.mkdtempSync(path.join(os.tmpdir(), "mcp-math-"));
+    try {
+        const format = (args.format || "png").toLowerCase();
+        const texPath = path.join(tmpDir, "formula.tex");
+        fs.writeFileSync(texPath, buildMathDocument(args.latex, args.display !== false, args.color));
+
+        try {
+            await execFileAsync(LATEX_PATH, ["-interaction=nonstopmode", "-halt-on-error", "-no-shell-escape", "formula.tex"], { cwd: tmpDir, timeout: 60000 });
+            await execFileAsync(DVISVGM_PATH, ["--no-fonts", "--exact", "-o", "formula.svg", "formula.dvi"], { cwd: tmpDir, timeout: 60000 });
+        } catch (err) {
+            if (err.code === "ENOENT") {
+                throw new Error(`${err.path || "latex/dvisvgm"} not found. Install a TeX distribution (TeX Live / MiKTeX) or set LATEX_PATH and DVISVGM_PATH.`);
+            }
+            // LaTeX reports errors on lines starting with "!" in its stdout.
+            const texError = (err.stdout || "").toString().split("\n").filter(l => l.startsWith("!")).join(" ");
+            throw new Error(`LaTeX rendering failed: ${texError || (err.stderr || err.message).toString().trim()}`);
+        }
+
+        const svg = fs.readFileSync(path.join(tmpDir, "formula.svg"), "utf-8");
+        const outputPath = await saveRenderedSvg(svg, args.output_path || `formula.${format}`, format, args.scale || 4);
+        return {
+            success: true,
+            output_path: outputPath,
+            message: `Formula rendered as ${format.toUpperCase()}.`
+        };
+    } catch (error) {
+        console.error("Error rendering math:", error.message);
+        throw error;
+    } finally {
+        fs.rmSync(tmpDir, { recursive: true, force: true });
+    }
+}
+
+function getChromeCandidates() {
+    if (CHROME_PATH) return [CHROME_PATH];
+    if (process.platform === "darwin") {
+        return [
+            "/Applications/Google Chrome.app/Contents/MacOS/Google Chrome",
+            "/Applications/Chromium.app/Contents/MacOS/Chromium",
+            "/Applications/Microsoft Edge.app/Contents/MacOS/Microsoft Edge",
+        ];
+    }
+    if (process.platform === "win32") {
+        return [
+            path.join(process.env.PROGRAMFILES || "C:\\Program Files", "Google\\Chrome\\Application\\chrome.exe"),
+            path.join(process.env["PROGRAMFILES(X86)"] || "C:\\Program Files (x86)", "Microsoft\\Edge\\Application\\msedge.exe"),
+        ];
+    }
+    return ["google-chrome", "google-chrome-stable", "chromium", "chromium-browser", "microsoft-edge"];
+}
+
+function buildHtmlDocument(html, css) {
+    const style = css ? `<style>${css}</style>` : "";
+    if (/<html[\s>]/i.test(html)) {
+        return /<\/head>/i.test(html) ? html.replace(/<\/head>/i, `${style}</head>`) : html.replace(/<html([^>]*)>/i, `<html$1><head>${style}</head>`);
+    }
+    return `<!DOCTYPE html><html><head><meta charset="utf-8"><style>html,body{margin:0}</style>${style}</head><body>${html}</body></html>`;
+}
+
+async function renderHtml(args) {
+    const tmpDir = fs.mkdtempSync(path.join(os.tmpdir(), "mcp-html-"));
+    try {
+        let pagePath;
+        if (args.html_path) {
+            pagePath = path.resolve(args.html_path);
+            if (!fs.existsSync(pagePath)) throw new Error(`HTML file not found: ${pagePath}`);
+            if (args.css) {
+                // Keep the page next to the original so relative assets still resolve.
+                const withCss = path.join(path.dirname(pagePath), `.mcp-render-${process.pid}-${Date.now()}.html`);
+                fs.writeFileSync(withCss, buildHtmlDocument(fs.readFileSync(pagePath, "utf-8"), args.css));
+                pagePath = withCss;
+            }
+        } else if (args.html) {
+            pagePath = path.join(tmpDir, "page.html");
+            fs.writeFileSync(pagePath, buildHtmlDocument(args.html, args.css));
+        } else {
+            throw new Error("Provide html or html_path.");
+        }
+
+        const width = Math.round(args.width || 1200);
+        const height = Math.round(args.height || 630);
+        const resolvedOutputPath = path.resolve(args.output_path || "render.png");
+        fs.mkdirSync(path.dirname(resolvedOutputPath), { recursive: true });
+
+        const chromeArgs = [
+            "--headless=new",
+            "--disable-gpu",
+            "--hide-scrollbars",
+            "--no-first-run",
+            "--no-default-browser-check",
+            `--user-data-dir=${path.join(tmpDir, "profile")}`,
+            `--window-size=${width},${height}`,
+            `--force-device-scale-factor=${args.scale || 1}`,
+            `--screenshot=${resolvedOutputPath}`,
+            pathToFileURL(pagePath).href,
+        ];
+
+        let rendered = false;
+        const errors = [];
+        try {
+            for (const chrome of getChromeCandidates()) {
+                try {
+                    await execFileAsync(chrome, chromeArgs, { timeout: 60000 });
+                    rendered = true;
+                    break;
+                } catch (err) {
+                    errors.push(`${chrome}: ${err.code === "ENOENT" ? "not installed" : err.message}`);
+                }
+            }
+        } finally {
+            if (args.html_path && args.css) fs.rmSync(pagePath, { force: true });
+        }
+
+        if (!rendered || !fs.existsSync(resolvedOutputPath)) {
+            throw new Error(`Headless Chrome rendering failed (set CHROME_PATH to a Chrome/Chromium/Edge binary). Tried: ${errors.join("; ")}`);
+        }
+
+        return {
+            success: true,
+            output_path: resolvedOutputPath,
+            message: `HTML rendered at ${width}x${height} (scale ${args.scale || 1}).`
+        };
+    } catch (error) {
+        console.error("Error rendering HTML:", error.message);
+        throw error;
+    } finally {
+        fs.rmSync(tmpDir, { recursive: true, force: true });
+    }
+}
+
+const CODE_KEYWORDS = {
+    javascript: "async await break case catch class const continue debugger default delete do else export extends false finally for from function if import in instanceof let new null of return static super switch this throw true try typeof undefined var void while with yield",
+    typescript: "abstract any as async await boolean break case catch class const continue declare default delete do else enum export extends false finally for from function if implements import in instanceof interface keyof let namespace never new null number of private protected public readonly return static string super switch this throw true try type typeof undefined unknown var void while yield",
+    python: "False None True and as assert async await break class continue def del elif else except finally for from global if import in is lambda nonlocal not or pass raise return self try while with yield",
+    rust: "as async await break const continue crate dyn else enum extern false fn for if impl in let loop match mod move mut pub ref return self Self static struct super trait true type unsafe use where while Some None Ok Err",
+    go: "break case chan const continue default defer else fallthrough false for func go goto if import interface map nil package range return select struct switch true type var",
+    java: "abstract boolean break byte case catch char class const continue default do double else enum extends false final finally float for if implements import instanceof int interface long new null package private protected public return short static super switch this throw throws true try void volatile while",
+    c: "auto break case char const continue default do double else enum extern float for goto if inline int long register return short signed sizeof static struct switch typedef union unsigned void volatile while NULL true false",
+    cpp: "auto bool break case catch char class const constexpr continue default delete do double else enum explicit extern false float for friend if inline int long namespace new nullptr operator private protected public return short signed sizeof static struct switch template this throw true try typedef typename union unsigned using virtual void volatile while",
+    csharp: "abstract as async await base bool break case catch class const continue decimal default delegate do double else enum event false finally float for foreach if in int interface internal is lock long namespace new null object out override private protected public readonly ref return sealed static string struct switch this throw true try typeof using var virtual void while",
+    bash: "if then else elif fi case esac for while until do done in function return local export readonly echo exit",
+    sql: "select from where and or not insert into values update set delete create table drop alter add join left right inner outer on group by order having limit offset as distinct union all null is in like between case when then else end primary key",
+    json: "true false null",
+};
+const CODE_LANGUAGE_ALIASES = { js: "javascript", jsx: "javascript", ts: "typescript", tsx: "typescript", py: "python", rs: "rust", golang: "go", "c++": "cpp", "c#": "csharp", cs: "csharp", sh: "bash", shell: "bash", zsh: "bash" };
+const HASH_COMMENT_LANGUAGES = ["python", "bash"];
+
+const CODE_THEMES = {
+    dark: { window: "#282c34", text: "#abb2bf", keyword: "#c678dd", string: "#98c379", number: "#d19a66", comment: "#7f848e", function: "#61afef", lineNumber: "#4b5263", title: "#9da5b4", background: ["#4e54c8", "#8f94fb"] },
+    light: { window: "#fafafa", text: "#383a42", keyword: "#a626a4", string: "#50a14f", number: "#986801", comment: "#a0a1a7", function: "#4078f2", lineNumber: "#c2c2c3", title: "#696c77", background: ["#a1c4fd", "#c2e9fb"] },
+};
+
+// Splits source into lines of { text, type } tokens with a small regex-based highlighter.
+function tokenizeCode(code, language) {
+    const lang = CODE_LANGUAGE_ALIASES[(language || "").toLowerCase()] || (language || "").toLowerCase();
+    const keywords = new Set((CODE_KEYWORDS[lang] || "").split(" ").filter(Boolean));
+    const caseInsensitive = lang === "sql";
+    const commentPattern = HASH_COMMENT_LANGUAGES.includes(lang) ? "#[^\\n]*" : lang === "sql" ? "--[^\\n]*|\\/\\*[\\s\\S]*?\\*\\/" : "\\/\\/[^\\n]*|\\/\\*[\\s\\S]*?\\*\\/";
+    const tokenRegex = new RegExp(
+        `(${commentPattern})|("(?:[^"\\\\\\n]|\\\\.)*"|'(?:[^'\\\\\\n]|\\\\.)*'|\`(?:[^\`\\\\]|\\\\.)*\`)|(\\b\\d[\\d_]*(?:\\.\\d+)?(?:e[+-]?\\d+)?\\b|\\b0x[0-9a-f]+\\b)|([A-Za-z_$][\\w$]*)(?=\\s*\\()|([A-Za-z_$][\\w$]*)`,
+        "gi"
+    );
+
+    const tokens = [];
+    let last = 0;
+    const source = code.replace(/\t/g, "    ").replace(/\r\n?/g, "\n");
+    for (const match of source.matchAll(tokenRegex)) {
+        if (match.index > last) tokens.push({ text: source.slice(last, match.index), type: "text" });
+        let type = "text";
+        if (match[1]) type = "comment";
+        else if (match[2]) type = "string";
+        else if (match[3]) type = "number";
+        else {
+            const word = match[4] || match[5];
+            if (keywords.has(caseInsensitive ? word.toLowerCase() : word)) type = "keyword";
+            else if (match[4] && lang) type = "function";
+        }
+        tokens.push({ text: match[0], type: type });
+        last = match.index + match[0].length;
+    }
+    if (last < source.length) tokens.push({ text: source.slice(last), type: "text" });
+
+    // Multi-line tokens (block comments, template strings) are split at newlines so each line renders on its own row.
+    const lines = [[]];
+    for (const token of tokens) {
+        token.text.split("\n").forEach((piece, i) => {
+            if (i > 0) lines.push([]);
+            if (piece) lines[lines.length - 1].push({ text: piece, type: token.type });
+        });
+    }
+    return lines;
+}
+
+function buildCodeSvg(args) {
+    const theme = CODE_THEMES[args.theme || "dark"];
+    if (!theme) throw new Error(`Unknown theme: ${args.theme}. Use 'dark' or 'light'.`);
+
+    const lines = tokenizeCode(args.code, args.language);
+    const fontSize = args.font_size || 16;
+    const charWidth = fontSize * 0.6;
+    const lineHeight = Math.round(fontSize * 1.5);
+    const gutter = args.line_numbers ? (String(lines.length).length + 2) * charWidth : 0;
+    const maxColumns = Math.max(20, ...lines.map(l => l.reduce((n, t) => n + t.text.length, 0)));
+
+    const framed = args.background !== "none";
+    const outer = framed ? 48 : 0;
+    const barHeight = 40;
+    const padding = 20;
+    const windowWidth = Math.ceil(padding * 2 + gutter + maxColumns * charWidth);
+    const windowHeight = barHeight + padding + lines.length * lineHeight + padding / 2;
+    const width = windowWidth + outer * 2;
+    const height = windowHeight + outer * 2;
+
+    const parts = [];
+    if (framed) {
+        if (args.background) {
+            parts.push(`<rect width="100%" height="100%" fill="${escapeXml(args.background)}"/>`);
+        } else {
+            parts.push(`<defs><linearGradient id="bg" x1="0" y1="0" x2="1" y2="1"><stop offset="0" stop-color="${theme.background[0]}"/><stop offset="1" stop-color="${theme.background[1]}"/></linearGradient></defs>`);
+            parts.push(`<rect width="100%" height="100%" fill="url(#bg)"/>`);
+        }
+    }
+    parts.push(`<rect x="${outer}" y="${outer}" width="${windowWidth}" height="${windowHeight}" rx="10" fill="${theme.window}"/>`);
+    ["#ff5f56", "#ffbd2e", "#27c93f"].forEach((color, i) => {
+        parts.push(`<circle cx="${outer + 20 + i * 20}" cy="${outer + barHeight / 2}" r="6" fill="${color}"/>`);
+    });
+    if (args.title) {
+        parts.push(`<text x="${outer + windowWidth / 2}" y="${outer + barHeight / 2 + 5}" text-anchor="middle" font-family="${SVG_FONT}" font-size="13" fill="${theme.title}">${escapeXml(args.title)}</text>`);
+    }
+
+    lines.forEach((line, i) => {
+        const y = outer + barHeight + padding / 2 + (i + 1) * lineHeight - (lineHeight - fontSize) / 2;
+        if (args.line_numbers) {
+            parts.push(`<text x="${outer + padding + gutter - 2 * charWidth}" y="${y}" text-anchor="end" fill="${theme.lineNumber}">${i + 1}</text>`);
+        }
+        if (line.length === 0) return;
+        const spans = line.map(t => `<tspan fill="${theme[t.type] || theme.text}">${escapeXml(t.text)}</tspan>`).join("");
+        parts.push(`<text x="${outer + padding + gutter}" y="${y}" xml:space="preserve">${spans}</text>`);
+    });
+
+    return `<svg xmlns="http://www.w3.org/2000/svg" width="${width}" height="${height}" viewBox="0 0 ${width} ${height}" font-family="DejaVu Sans Mono, Menlo, Consolas, monospace" font-size="${fontSize}">${parts.join("")}</svg>`;
+}
+
+async function renderCode(args) {
+    try {
+        const format = (args.format || "png").toLowerCase();
+        const svg = buildCodeSvg(args);
+        const outputPath = await saveRenderedSvg(svg, args.output_path || `code.${format}`, format, args.scale || 2);
+        return {
+            success: true,
+            output_path: outputPath,
+            message: `Code rendered as ${format.toUpperCase()}.`
+        };
+    } catch (error) {
+        console.error("Error rendering code:", error.message);
+        throw error;
+    }
+}
+
+// Parses "16:9" (or "16x9") into a width/height ratio.
+function parseAspectRatio(ratio) {
+    const match = /^\s*(\d+(?:\.\d+)?)\s*[:x]\s*(\d+(?:\.\d+)?)\s*$/.exec(String(ratio));
+    if (!match || Number(match[1]) <= 0 || Number(match[2]) <= 0) throw new Error(`Invalid aspect ratio: ${ratio}. Use the form W:H, e.g. 16:9.`);
+    return Number(match[1]) / Number(match[2]);
+}
+
+const OUTPAINT_PROMPT = "Fill the blank white border areas so the picture extends naturally beyond its original edges. Keep the existing content unchanged and match its style, lighting and perspective.";
+
+async function smartCrop(imagePath, aspectRatios = ["1:1", "9:16", "16:9"], mode = "crop", outputDir) {
+    try {
+        const resolvedPath = path.resolve(imagePath);
+        if (!fs.existsSync(resolvedPath)) throw new Error(`Image file not found: ${resolvedPath}`);
+        if (mode !== "crop" && mode !== "extend") throw new Error(`Unsupported mode: ${mode}. Use 'crop' or 'extend'.`);
+
+        const { width, height } = await sharp(resolvedPath).metadata();
+        const resolvedOutputDir = path.resolve(outputDir || path.dirname(resolvedPath));
+        fs.mkdirSync(resolvedOutputDir, { recursive: true });
+        const name = path.basename(resolvedPath, path.extname(resolvedPath));
+
+        const outputs = [];
+        for (const ratioText of aspectRatios) {
+            const ratio = parseAspectRatio(ratioText);
+            const outputPath = path.join(resolvedOutputDir, `${name}_${String(ratioText).replace(/[:x]/, "x")}.png`);
+
+            if (mode === "crop") {
+                // Largest box of the target ratio that fits inside the image, positioned by attention (saliency) detection.
+                const targetWidth = width / height > ratio ? Math.round(height * ratio) : width;
+                const targetHeight = width / height > ratio ? height : Math.round(width / ratio);
+                await sharp(resolvedPath)
+                    .resize({ width: targetWidth, height: targetHeight, fit: "cover", position: sharp.strategy.attention })
+                    .png()
+                    .toFile(outputPath);
+                outputs.push({ aspect_ratio: ratioText, width: targetWidth, height: targetHeight, output_path: outputPath });
+            } else {
+                // Smallest canvas of the target ratio that contains the whole image; the padding is outpainted.
+                const canvasWidth = width / height < ratio ? Math.round(height * ratio) : width;
+                const canvasHeight = width / height < ratio ? height : Math.round(width / ratio);
+                const padX = canvasWidth - width;
+                const padY = canvasHeight - height;
+                const padded = path.join(os.tmpdir(), `mcp-outpaint-${process.pid}-${Date.now()}.png`);
+                await sharp(resolvedPath)
+                    .extend({
+                        left: Math.floor(padX / 2), right: Math.ceil(padX / 2),
+                        top: Math.floor(padY / 2), bottom: Math.ceil(padY / 2),
+                        background: { r: 255, g: 255, b: 255, alpha: 1 },
+                    })
+                    .png()
+                    .toFile(padded);
+                try {
+                    await editImage(padded, outputPath, OUTPAINT_PROMPT);
+                } finally {
+                    fs.rmSync(padded, { force: true });
+                }
+                // Providers may return a different size; snap back to the exact target canvas.
+                const fitted = await sharp(outputPath).resize({ width: canvasWidth, height: canvasHeight, fit: "fill" }).png().toBuffer();
+                fs.writeFileSync(outputPath, fitted);
+                outputs.push({ aspect_ratio: ratioText, width: canvasWidth, height: canvasHeight, output_path: outputPath });
+            }
+        }
+
+        return {
+            success: true,
+            output_paths: outputs.map(o => o.output_path),
+            results: outputs,
+            message: `Produced ${outputs.length} ${mode === "crop" ? "smart crop(s)" : "outpainted extension(s)"}.`
+        };
+    } catch (error) {
+        console.error("Error smart cropping image:", error.message);
+        throw error;
+    }
+}
+
+function buildAvatarPrompt(subject, style = "photorealistic", background = "solid", backgroundColor) {
+    let backgroundText;
+    if (background === "solid" || background === "transparent") {
+        backgroundText = `a plain, uniform ${backgroundColor || (background === "transparent" ? "light grey" : "neutral")} background`;
+    } else if (background === "gradient") {
+        backgroundText = `a soft, smooth ${backgroundColor || "neutral"} gradient background`;
+    } else {
+        backgroundText = `${background}, softly out of focus`;
+    }
+    return `Head-and-shoulders avatar portrait of ${subject}. ${style} style. ` +
+        `Centered, facing the viewer, eyes at about 40% from the top, the face filling the central part of a square frame with some headroom. ` +
+        `${backgroundText}. Even, flattering lighting. No text, no watermark, no border.`;
+}
+
+async function generateAvatar(args) {
+    try {
+        const outputDir = path.resolve(args.output_dir || ".");
+        const name = args.name || "avatar";
+        const sizes = args.sizes && args.sizes.length > 0 ? args.sizes : [512, 256, 128, 64];
+        fs.mkdirSync(outputDir, { recursive: true });
+
+        const prompt = buildAvatarPrompt(args.subject, args.style, args.background, args.background_color);
+        const masterPath = path.join(outputDir, `${name}_master.png`);
+        await generateImageFromText(prompt, masterPath, { aspectRatio: "1:1", numberOfImages: 1 });
+
+        let sourcePath = masterPath;
+        if (args.background === "transparent") {
+            const result = await removeBackground(masterPath, path.join(outputDir, `${name}_master_nobg.png`));
+            sourcePath = result.output_paths[0];
+        }
+
+        // Square, face-centred master at the largest requested size.
+        const largest = Math.max(...sizes);
+        let master = sharp(sourcePath).resize({ width: largest, height: largest, fit: "cover", position: sharp.strategy.attention });
+        if (args.shape === "circle") {
+            const mask = Buffer.from(`<svg width="${largest}" height="${largest}"><circle cx="${largest / 2}" cy="${largest / 2}" r="${largest / 2}" fill="#fff"/></svg>`);
+            master = sharp(await master.ensureAlpha().png().toBuffer()).composite([{ input: mask, blend: "dest-in" }]);
+        }
+        const masterBuffer = await master.png().toBuffer();
+
+        const outputPaths = [];
+        for (const size of sizes) {
+            const filePath = path.join(outputDir, `${name}_${size}.png`);
+            await sharp(masterBuffer).resize(size, size).png().toFile(filePath);
+            outputPaths.push(filePath);
+        }
+
+        return {
+            success: true,
+            output_paths: outputPaths,
+            master_path: masterPath,
+            prompt: prompt,
+            message: `Avatar generated in ${sizes.length} size(s).`
+        };
+    } catch (error) {
+        console.error("Error generating avatar:", error.message);
+        throw error;
+    }
+}
+
+
+// --- Textures ---
+
+// Blends the image with a copy rolled by half its size; the rolled copy wraps seamlessly at the borders and the original covers its seams in the middle.
+function makeSeamless(data, width, height, channels) {
+    const out = Buffer.alloc(data.length);
+    const halfW = Math.floor(width / 2);
+    const halfH = Math.floor(height / 2);
+    for (let y = 0; y < height; y++) {
+        const wy = 1 - Math.abs((2 * (y + 0.5)) / height - 1);
+        const sy = (y + halfH) % height;
+        for (let x = 0; x < width; x++) {
+            const wx = 1 - Math.abs((2 * (x + 0.5)) / width - 1);
+            const t = Math.min(1, Math.min(wx, wy) * 2);
+            const weight = t * t * (3 - 2 * t);
+            const sx = (x + halfW) % width;
+            const i = (y * width + x) * channels;
+            const j = (sy * width + sx) * channels;
+            for (let c = 0; c < channels; c++) {
+                out[i + c] = Math.round(data[i + c] * weight + data[j + c] * (1 - weight));
+            }
+        }
+    }
+    return out;
+}
+
+// Compares the difference across the wrap-around seams with the average difference between neighbouring pixels. Around 1 means the seams are invisible.
+function measureTileability(data, width, height, channels) {
+    const diff = (i, j) => {
+        let sum = 0;
+        for (let c = 0; c < Math.min(channels, 3); c++) sum += Math.abs(data[i + c] - data[j + c]);
+        return sum;
+    };
+    const px = (x, y) => (y * width + x) * channels;
+
+    let seamX = 0, interiorX = 0, seamY = 0, interiorY = 0;
+    for (let y = 0; y < height; y++) {
+        seamX += diff(px(width - 1, y), px(0, y));
+        for (let x = 0; x < width - 1; x++) interiorX += diff(px(x, y), px(x + 1, y));
+    }
+    for (let x = 0; x < width; x++) {
+        seamY += diff(px(x, height - 1), px(x, 0));
+        for (let y = 0; y < height - 1; y++) interiorY += diff(px(x, y), px(x, y + 1));
+    }
+    seamX /= height;
+    seamY /= width;
+    interiorX /= height * (width - 1);
+    interiorY /= width * (height - 1);
+
+    const ratio = (seam, interior) => (interior > 0 ? seam / interior : (seam > 0 ? Infinity : 1));
+    const horizontal = ratio(seamX, interiorX);
+    const vertical = ratio(seamY, interiorY);
+    const score = Math.max(horizontal, vertical);
+    return {
+        horizontal_seam_ratio: Number(horizontal.toFixed(3)),
+        vertical_seam_ratio: Number(vertical.toFixed(3)),
+        tileable: score <= 1.5,
+    };
+}
+
+async function generateTexture(args) {
+    try {
+        const size = args.size || 1024;
+        const outputPath = path.resolve(args.output_path || "texture.png");
+        fs.mkdirSync(path.dirname(outputPath), { recursive: true });
+
+        const prompt = `${args.prompt}. Seamless tileable texture, flat orthographic top-down view, even lighting, no vignette, no borders, pattern continues across all edges.`;
+        const generated = await generateImageFromText(prompt, outputPath, { aspectRatio: "1:1", numberOfImages: 1 });
+        const sourcePath = generated.output_paths[0];
+
+        const { data, info } = await sharp(sourcePath)
+            .resize(size, size, { fit: "cover" })
+            .removeAlpha()
+            .raw()
+            .toBuffer({ resolveWithObject: true });
+
+        const before = measureTileability(data, info.width, info.height, info.channels);
+        const pixels = args.seamless_fix === false ? data : makeSeamless(data, info.width, info.height, info.channels);
+        const after = measureTileability(pixels, info.width, info.height, info.channels);
+
+        const raw = { raw: { width: info.width, height: info.height, channels: info.channels } };
+        const textureBuffer = await sharp(pixels, raw).png().toBuffer();
+        fs.writeFileSync(outputPath, textureBuffer);
+
+        let previewPath;
+        if (args.preview_path) {
+            previewPath = path.resolve(args.preview_path);
+            fs.mkdirSync(path.dirname(previewPath), { recursive: true });
+            await sharp({ create: { width: size * 2, height: size * 2, channels: 3, background: "#000" } })
+                .composite([
+                    { input: textureBuffer, left: 0, top: 0 },
+                    { input: textureBuffer, left: size, top: 0 },
+                    { input: textureBuffer, left: 0, top: size },
+                    { input: textureBuffer, left: size, top: size },
+                ])
+                .png()
+                .toFile(previewPath);
+        }
+
+        return {
+            success: true,
+            output_paths: [outputPath],
+            preview_path: previewPath,
+            tileability: { before_fix: before, after_fix: after },
+            message: after.tileable
+                ? "Seamless texture generated."
+                : "Texture generated, but the seams may still be visible when tiled.",
+        };
+    } catch (error) {
+        console.error("Error generating texture:", error.message);
+        throw error;
+    }
+}
+
+// Derives a tangent-space normal map from a single-channel height map with Sobel gradients, wrapping at the borders so it tiles.
+function heightToNormalMap(height, width, heightPx, strength = 2, directx = false) {
+    const out = Buffer.alloc(width * heightPx * 3);
+    const h = (x, y) => height[((y + heightPx) % heightPx) * width + ((x + width) % width)] / 255;
+    for (let y = 0; y < heightPx; y++) {
+        for (let x = 0; x < width; x++) {
+            const dx = (h(x + 1, y - 1) + 2 * h(x + 1, y) + h(x + 1, y + 1)) - (h(x - 1, y - 1) + 2 * h(x - 1, y) + h(x - 1, y + 1));
+            const dy = (h(x - 1, y + 1) + 2 * h(x, y + 1) + h(x + 1, y + 1)) - (h(x - 1, y - 1) + 2 * h(x, y - 1) + h(x + 1, y - 1));
+            let nx = -dx * strength;
+            let ny = (directx ? dy : -dy) * strength;
+            let nz = 1;
+            const len = Math.sqrt(nx * nx + ny * ny + nz * nz);
+            nx /= len; ny /= len; nz /= len;
+            const i = (y * width + x) * 3;
+            out[i] = Math.round((nx * 0.5 + 0.5) * 255);
+            out[i + 1] = Math.round((ny * 0.5 + 0.5) * 255);
+            out[i + 2] = Math.round((nz * 0.5 + 0.5) * 255);
+        }
+    }
+    return out;
+}
+
+async function generatePbrTextures(args) {
+    try {
+        const outputDir = path.resolve(args.output_dir || ".");
+        const name = args.name || "material";
+        const size = args.size || 1024;
+        fs.mkdirSync(outputDir, { recursive: true });
+
+        const albedoPath = path.join(outputDir, `${name}_albedo.png`);
+        let tileability;
+        if (args.image_path) {
+            const resolvedImagePath = path.resolve(args.image_path);
+            if (!fs.existsSync(resolvedImagePath)) throw new Error(`Image file not found: ${resolvedImagePath}`);
+            await sharp(resolvedImagePath).resize(size, size, { fit: "cover" }).removeAlpha().png().toFile(albedoPath);
+        } else if (args.prompt) {
+            const texture = await generateTexture({
+                prompt: `${args.prompt}, PBR albedo map, diffuse color only, no shadows, no highlights, no reflections`,
+                size,
+                output_path: albedoPath,
+            });
+            tileability = texture.tileability.after_fix;
+        } else {
+            throw new Error("Provide prompt or image_path.");
+        }
+
+        // Height: smoothed, contrast-stretched luminance.
+        const heightPath = path.join(outputDir, `${name}_height.png`);
+        const { data: height, info } = await sharp(albedoPath)
+            .greyscale()
+            .blur(1)
+            .normalise()
+            .raw()
+            .toBuffer({ resolveWithObject: true });
+        await sharp(height, { raw: { width: info.width, height: info.height, channels: 1 } }).png().toFile(heightPath);
+
+        const normalPath = path.join(outputDir, `${name}_normal.png`);
+        const normal = heightToNormalMap(height, info.width, info.height, args.normal_strength ?? 2, args.normal_convention === "directx");
+        await sharp(normal, { raw: { width: info.width, height: info.height, channels: 3 } }).png().toFile(normalPath);
+
+        // Roughness: recesses read rougher than raised, worn areas; kept within a plausible range.
+        const roughnessPath = path.join(outputDir, `${name}_roughness.png`);
+        const roughness = Buffer.alloc(height.length);
+        for (let i = 0; i < height.length; i++) roughness[i] = Math.round(255 * (0.95 - (height[i] / 255) * 0.5));
+        await sharp(roughness, { raw: { width: info.width, height: info.height, channels: 1 } }).png().toFile(roughnessPath);
+
+        return {
+            success: true,
+            output_paths: [albedoPath, heightPath, normalPath, roughnessPath],
+            maps: { albedo: albedoPath, height: heightPath, normal: normalPath, roughness: roughnessPath },
+            tileability,
+            message: `PBR texture set written to ${outputDir}`,
+        };
+    } catch (error) {
+        console.error("Error generating PBR textures:", error.message);
+        throw error;
+    }
+}
+
+// --- Sequential Art ---
+
+// Generates one image per prompt, keeping characters consistent. On providers that take reference images (the call's
+// `provider` or the active one) every frame after the first is generated as an edit with the reference images (or the
+// first frame) attached; other providers rely on the shared prompt text.
+async function generateConsistentFrames(prompts, options = {}) {
+    const provider = getProviderOverride()?.provider || activeProvider;
+    const useReferences = getProviderCapabilities(provider).includes("reference_images");
+    const references = (options.referenceImagePaths || []).map(p => {
+        const resolved = path.resolve(p);
+        if (!fs.existsSync(resolved)) throw new Error(`Reference image not found: ${resolved}`);
+        return loadReferenceImage(resolved);
+    });
+
+    const frames = [];
+    for (const prompt of prompts) {
+        let images;
+        if (useReferences && references.length > 0) {
+            const [base, ...extraImages] = references;
+            const guidedPrompt = `Create a new image. Keep the characters, their faces, clothing and colors exactly as in the reference image(s); do not copy the reference composition.\n\n${prompt}`;
+            ({ images } = await runProviderEdit(base.data, base.mimeType, guidedPrompt, {
+                aspectRatio: options.aspectRatio, numberOfImages: 1, extraImages, requireReferenceImages: true,
+            }));
+        } else {
+            ({ images } = await runProviderGenerate(prompt, { aspectRatio: options.aspectRatio, numberOfImages: 1 }));
+        }
+        if (!images || images.length === 0) throw new Error(`No image generated for: ${prompt}`);
+        frames.push(images[0]);
+        if (references.length === 0 && useReferences) {
+            references.push({ mimeType: "image/png", data: (await sharp(images[0]).png().toBuffer()).toString("base64") });
+        }
+    }
+    return frames;
+}
+
+async function composeStoryboardSheet(frames, captions, columns, frameWidth, aspectRatio) {
+    const frameHeight = Math.round(frameWidth / aspectRatio);
+    const gap = 24;
+    const captionHeight = 72;
+    const cellHeight = frameHeight + captionHeight;
+    const rows = Math.ceil(frames.length / columns);
+    const width = gap + columns * (frameWidth + gap);
+    const height = gap + rows * (cellHeight + gap);
+
+    const composites = [];
+    const overlays = [];
+    for (let i = 0; i < frames.length; i++) {
+        const left = gap + (i % columns) * (frameWidth + gap);
+        const top = gap + Math.floor(i / columns) * (cellHeight + gap);
+        composites.push({ input: await sharp(frames[i]).resize(frameWidth, frameHeight, { fit: "cover" }).png().toBuffer(), left, top });
+
+        overlays.push(`<rect x="${left}" y="${top}" width="${frameWidth}" height="${frameHeight}" fill="none" stroke="#222" stroke-width="2"/>`);
+        overlays.push(`<rect x="${left}" y="${top}" width="40" height="32" fill="#222"/><text x="${left + 20}" y="${top + 23}" text-anchor="middle" font-family="${SVG_FONT}" font-size="18" font-weight="bold" fill="#fff">${i + 1}</text>`);
+        if (captions[i]) {
+            const { lines, fontSize, lineHeight } = fitTextLines(captions[i], frameWidth, captionHeight - 8, 16);
+            const direction = textDirectionAttributes(captions[i]);
+            const x = direction ? left + frameWidth : left;
+            const tspans = lines.map((l, j) => `<tspan x="${x}" y="${(top + frameHeight + 6 + fontSize + j * lineHeight).toFixed(1)}">${escapeXml(l)}</tspan>`).join("");
+            overlays.push(`<text${direction} font-family="${SVG_FONT}" font-size="${fontSize}" fill="#222">${tspans}</text>`);
+        }
+    }
+    composites.push({ input: Buffer.from(`<svg xmlns="http://www.w3.org/2000/svg" width="${width}" height="${height}">${overlays.join("")}</svg>`), left: 0, top: 0 });
+
+    return await sharp({ create: { width, height, channels: 3, background: "#ffffff" } }).composite(composites).png().toBuffer();
+}
+
+async function generateStoryboard(args) {
+    try {
+        if (!Array.isArray(args.beats) || args.beats.length === 0) throw new Error("beats must be a non-empty array.");
+        const outputDir = path.resolve(args.output_dir || ".");
+        const name = args.name || "storyboard";
+        const aspectRatio = args.aspect_ratio || "16:9";
+        const style = args.style || "rough storyboard sketch, pencil and grey marker";
+        fs.mkdirSync(outputDir, { recursive: true });
+
+        const profile = args.style_profile ? loadStyleProfile(args.style_profile) : null;
+
+        const beats = args.beats.map(b => (typeof b === "string" ? { description: b } : b));
+        const prompts = beats.map(b => [
+            `Storyboard frame, ${style}.`,
+            args.characters ? `Characters: ${args.characters}.` : "",
+            `Shot: ${b.description}`,
+            "No text, no captions, no panel borders.",
+        ].filter(Boolean).join(" ")).map(p => (profile ? applyStyleProfile(p, profile) : p));
+
+        const frames = await generateConsistentFrames(prompts, { referenceImagePaths: args.reference_image_paths, aspectRatio });
+
+        const framePaths = [];
+        for (let i = 0; i < frames.length; i++) {
+            const framePath = path.join(outputDir, `${name}_frame_${String(i + 1).padStart(2, "0")}.png`);
+            await sharp(frames[i]).png().toFile(framePath);
+            framePaths.push(framePath);
+        }
+
+        const sheetPath = path.join(outputDir, `${name}_sheet.png`);
+        const sheet = await composeStoryboardSheet(
+            frames,
+            beats.map(b => b.caption || b.description),
+            Math.max(1, args.columns || 3),
+            480,
+            parseAspectRatio(aspectRatio)
+        );
+        fs.writeFileSync(sheetPath, sheet);
+
+        return {
+            success: true,
+            output_paths: [sheetPath, ...framePaths],
+            sheet_path: sheetPath,
+            frame_paths: framePaths,
+            message: `Storyboard with ${frames.length} frame(s) generated.`,
+        };
+    } catch (error) {
+        console.error("Error generating storyboard:", error.message);
+        throw error;
+    }
+}
+
+const COMIC_FONT = "'Comic Neue', 'Comic Sans MS', 'DejaVu Sans', sans-serif";
+
+// Lays dialogue out from the top of the panel; each speaker keeps a side (unnamed lines alternate). Speech gets a rounded bubble with a tail; captions get a yellow box.
+function buildComicDialogueSvg(dialogue, width, height) {
+    const margin = Math.round(width * 0.03);
+    const fontSize = Math.max(12, Math.round(width / 30));
+    const maxBubbleWidth = Math.round(width * 0.5);
+    const shapes = [];
+    const speakerSides = new Map();
+    let speechCount = 0;
+    let y = margin;
+
+    dialogue.forEach((entry) => {
+        const item = typeof entry === "string" ? { text: entry } : entry;
+        const isCaption = item.type === "caption";
+        const text = (item.text || "").toUpperCase();
+        const { lines, fontSize: size, lineHeight } = fitTextLines(text, maxBubbleWidth - fontSize * 2, height * 0.3, fontSize, 0.6, 1.15);
+        const textWidth = Math.max(...lines.map(l => measureTextUnits(l))) * size * 0.6;
+        const boxWidth = Math.min(maxBubbleWidth, textWidth + size * 2);
+        const boxHeight = lines.length * lineHeight + size;
+        let left = true;
+        if (!isCaption) {
+            if (item.speaker && !speakerSides.has(item.speaker)) speakerSides.set(item.speaker, speakerSides.size % 2 === 0);
+            left = item.speaker ? speakerSides.get(item.speaker) : speechCount % 2 === 0;
+            speechCount++;
+        }
+        const x = left ? margin : width - margin - boxWidth;
+        const cx = x + boxWidth / 2;
+
+        if (isCaption) {
+            shapes.push(`<rect x="${x}" y="${y}" width="${boxWidth}" height="${boxHeight}" fill="#fff6a8" stroke="#000" stroke-width="2"/>`);
+        } else {
+            const tailX = x + boxWidth * (left ? 0.35 : 0.65);
+            const tailTip = y + boxHeight + Math.round(height * 0.08);
+            shapes.push(`<path d="M ${tailX - size * 0.6} ${y + boxHeight - 2} L ${tailX + (left ? 1 : -1) * size * 0.8} ${tailTip} L ${tailX + size * 0.6} ${y + boxHeight - 2} Z" fill="#fff" stroke="#000" stroke-width="2"/>`);
+            shapes.push(`<rect x="${x}" y="${y}" width="${boxWidth}" height="${boxHeight}" rx="${size}" ry="${size}" fill="#fff" stroke="#000" stroke-width="2"/>`);
+            // Hide the tail's stroke where it joins the bubble.
+            shapes.push(`<rect x="${tailX - size * 0.6 + 2}" y="${y + boxHeight - 4}" width="${size * 1.2 - 4}" height="4" fill="#fff"/>`);
+        }
+        const tspans = lines.map((l, j) => `<tspan x="${cx.toFixed(1)}" y="${(y + size * 0.5 + size + j * lineHeight).toFixed(1)}">${escapeXml(l)}</tspan>`).join("");
+        shapes.push(`<text text-anchor="middle"${textDirectionAttributes(text)} font-family="${COMIC_FONT}" font-size="${size}" font-weight="bold" fill="#000">${tspans}</text>`);
+
+        y += boxHeight + (isCaption ? margin : Math.round(height * 0.08) + margin);
+    });
+
+    return `<svg xmlns="http://www.w3.org/2000/svg" width="${width}" height="${height}">${shapes.join("")}</svg>`;
+}
+
+async function generateComicStrip(args) {
+    try {
+        if (!Array.isArray(args.panels) || args.panels.length === 0) throw new Error("panels must be a non-empty array.");
+        const outputPath = path.resolve(args.output_path || "comic.png");
+        fs.mkdirSync(path.dirname(outputPath), { recursive: true });
+        const style = args.style || "clean western comic art, bold ink lines, flat colors";
+        const panelWidth = args.panel_width || 600;
+        const panelHeight = panelWidth;
+        const columns = Math.max(1, args.columns || Math.min(args.panels.length, 4));
+
+        const profile = args.style_profile ? loadStyleProfile(args.style_profile) : null;
+
+        const panels = args.panels.map(p => (typeof p === "string" ? { description: p } : p));
+        const prompts = panels.map(p => [
+            `Comic panel, ${style}.`,
+            args.characters ? `Characters: ${args.characters}.` : "",
+            `Scene: ${p.description}`,
+            (p.dialogue || []).length > 0 ? "Leave empty space at the top of the panel for speech bubbles." : "",
+            "No text, no lettering, no speech bubbles, no panel borders.",
+        ].filter(Boolean).join(" ")).map(p => (profile ? applyStyleProfile(p, profile) : p));
+
+        const frames = await generateConsistentFrames(prompts, { referenceImagePaths: args.reference_image_paths, aspectRatio: "1:1" });
+
+        const gutter = Math.round(panelWidth * 0.04);
+        const titleHeight = args.title ? Math.round(panelWidth * 0.15) : 0;
+        const rows = Math.ceil(frames.length / columns);
+        const width = gutter + columns * (panelWidth + gutter);
+        const height = titleHeight + gutter + rows * (panelHeight + gutter);
+
+        const composites = [];
+        for (let i = 0; i < frames.length; i++) {
+            const left = gutter + (i % columns) * (panelWidth + gutter);
+            const top = titleHeight + gutter + Math.floor(i / columns) * (panelHeight + gutter);
+            const dialogueSvg = buildComicDialogueSvg(panels[i].dialogue || [], panelWidth, panelHeight);
+            const panel = await sharp(frames[i])
+                .resize(panelWidth, panelHeight, { fit: "cover" })
+                .composite([
+                    { input: Buffer.from(dialogueSvg), left: 0, top: 0 },
+                    { input: Buffer.from(`<svg xmlns="http://www.w3.org/2000/svg" width="${panelWidth}" height="${panelHeight}"><rect x="2" y="2" width="${panelWidth - 4}" height="${panelHeight - 4}" fill="none" stroke="#000" stroke-width="4"/></svg>`), left: 0, top: 0 },
+                ])
+                .png()
+                .toBuffer();
+            composites.push({ input: panel, left, top });
+        }
+        if (args.title) {
+            const titleSize = Math.round(titleHeight * 0.55);
+            composites.push({
+                input: Buffer.from(`<svg xmlns="http://www.w3.org/2000/svg" width="${width}" height="${titleHeight + gutter}"><text x="${width / 2}" y="${gutter + titleSize}" text-anchor="middle" font-family="${COMIC_FONT}" font-size="${titleSize}" font-weight="bold" fill="#000">${escapeXml(args.title.toUpperCase())}</text></svg>`),
+                left: 0,
+                top: 0,
+            });
+        }
+
+        await sharp({ create: { width, height, channels: 3, background: "#ffffff" } }).composite(composites).png().toFile(outputPath);
+
+        return {
+            success: true,
+            output_paths: [outputPath],
+            message: `Comic strip with ${frames.length} panel(s) generated.`,
+        };
+    } catch (error) {
+        console.error("Error generating comic strip:", error.message);
+        throw error;
+    }
+}
+
+// --- Covers ---
+
+const COVER_TITLE_FONT = "'Playfair Display', Georgia, 'DejaVu Serif', serif";
+
+function buildCoverTypographySvg(args, width, height) {
+    const margin = Math.round(width * 0.07);
+    const titleFont = args.font_family || COVER_TITLE_FONT;
+    const bodyFont = args.body_font_family || SVG_FONT;
+    const color = args.text_color || "#ffffff";
+    const stroke = `stroke="#000" stroke-opacity="0.6" paint-order="stroke" stroke-linejoin="round"`;
+    const atTop = args.title_position !== "bottom";
+    const parts = [];
+
+    // Scrims behind the title and author blocks keep the text legible over busy artwork.
+    parts.push(`<defs><linearGradient id="scrimTop" x1="0" y1="0" x2="0" y2="1"><stop offset="0" stop-color="#000" stop-opacity="0.55"/><stop offset="1" stop-color="#000" stop-opacity="0"/></linearGradient><linearGradient id="scrimBottom" x1="0" y1="1" x2="0" y2="0"><stop offset="0" stop-color="#000" stop-opacity="0.55"/><stop offset="1" stop-color="#000" stop-opacity="0"/></linearGradient></defs>`);
+    parts.push(`<rect x="0" y="0" width="${width}" height="${Math.round(height * 0.35)}" fill="url(#scrimTop)"/>`);
+    parts.push(`<rect x="0" y="${Math.round(height * 0.75)}" width="${width}" height="${Math.round(height * 0.25)}" fill="url(#scrimBottom)"/>`);
+
+    const title = fitTextLines(args.title, width - margin * 2, height * 0.22, Math.round(width / 7), 0.55, 1.05);
+    const subtitleSize = Math.round(title.fontSize * 0.32);
+    const subtitle = args.subtitle ? fitTextLines(args.subtitle, width - margin * 2, height * 0.08, subtitleSize) : null;
+    const titleBlockHeight = title.lines.length * title.lineHeight + (subtitle ? subtitle.lines.length * subtitle.lineHeight + subtitleSize * 0.6 : 0);
+    let y = atTop ? margin + title.fontSize : height - margin - titleBlockHeight + title.fontSize;
+    if (!atTop && args.volume) y -= Math.round(width / 14);
+
+    const titleSpans = title.lines.map((l, i) => `<tspan x="${width / 2}" y="${(y + i * title.lineHeight).toFixed(1)}">${escapeXml(l)}</tspan>`).join("");
+    parts.push(`<text text-anchor="middle"${textDirectionAttributes(args.title)} font-family="${escapeXml(titleFont)}" font-size="${title.fontSize}" font-weight="bold" fill="${escapeXml(color)}" stroke-width="${Math.max(2, title.fontSize / 20).toFixed(1)}" ${stroke}>${titleSpans}</text>`);
+    y += (title.lines.length - 1) * title.lineHeight;
+
+    if (subtitle) {
+        y += subtitleSize * 0.6 + subtitle.fontSize;
+        const subSpans = subtitle.lines.map((l, i) => `<tspan x="${width / 2}" y="${(y + i * subtitle.lineHeight).toFixed(1)}">${escapeXml(l)}</tspan>`).join("");
+        parts.push(`<text text-anchor="middle"${textDirectionAttributes(args.subtitle)} font-family="${escapeXml(bodyFont)}" font-size="${subtitle.fontSize}" letter-spacing="${(subtitle.fontSize * 0.08).toFixed(1)}" fill="${escapeXml(color)}" stroke-width="${Math.max(1, subtitle.fontSize / 15).toFixed(1)}" ${stroke}>${subSpans}</text>`);
+    }
+
+    if (args.author) {
+        const authorSize = Math.round(width / 22);
+        const authorY = atTop ? height - margin : margin + authorSize;
+        parts.push(`<text x="${width / 2}" y="${authorY}" text-anchor="middle"${textDirectionAttributes(args.author)} font-family="${escapeXml(bodyFont)}" font-size="${authorSize}" letter-spacing="${(authorSize * 0.12).toFixed(1)}" fill="${escapeXml(color)}" stroke-width="${Math.max(1, authorSize / 15).toFixed(1)}" ${stroke}>${escapeXml(args.author.toUpperCase())}</text>`);
+    }
+
+    if (args.volume) {
+        const label = /^\d+$/.test(String(args.volume)) ? `VOL. ${args.volume}` : String(args.volume).toUpperCase();
+        const volSize = Math.round(width / 24);
+        const badgeWidth = Math.round(label.length * volSize * 0.65 + volSize * 1.4);
+        const badgeHeight = Math.round(volSize * 1.8);
+        const badgeX = width - margin - badgeWidth;
+        const badgeY = atTop ? height - margin - badgeHeight - (args.author ? volSize * 2.2 : 0) : height - margin - badgeHeight;
+        parts.push(`<rect x="${badgeX}" y="${badgeY}" width="${badgeWidth}" height="${badgeHeight}" fill="#000" fill-opacity="0.7" stroke="${escapeXml(color)}" stroke-width="2"/>`);
+        parts.push(`<text x="${badgeX + badgeWidth / 2}" y="${badgeY + badgeHeight / 2 + volSize * 0.35}" text-anchor="middle" font-family="${escapeXml(bodyFont)}" font-size="${volSize}" font-weight="bold" fill="${escapeXml(color)}">${escapeXml(label)}</text>`);
+    }
+
+    return `<svg xmlns="http://www.w3.org/2000/svg" width="${width}" height="${height}">${parts.join("")}</svg>`;
+}
+
+async function generateCover(args) {
+    let generatedPath = null;
+    try {
+        const outputPath = path.resolve(args.output_path || "cover.png");
+        fs.mkdirSync(path.dirname(outputPath), { recursive: true });
+        const aspectRatio = args.aspect_ratio || "2:3";
+        const width = args.width || 1600;
+        const height = Math.round(width / parseAspectRatio(aspectRatio));
+
+        let imagePath = args.image_path;
+        if (!imagePath) {
+            if (!args.prompt) throw new Error("Provide prompt or image_path.");
+            const atTop = args.title_position !== "bottom";
+            const prompt = `Cover illustration: ${args.prompt}. Leave calm, uncluttered space at the ${atTop ? "top" : "bottom"} for the title. No text, no letters, no logos.`;
+            const generated = await generateImageFromText(prompt, path.join(os.tmpdir(), `mcp-cover-${process.pid}-${Date.now()}.png`), { aspectRatio, numberOfImages: 1 });
+            imagePath = generatedPath = generated.output_paths[0];
+        }
+        const resolvedPath = path.resolve(imagePath);
+        if (!fs.existsSync(resolvedPath)) throw new Error(`Image file not found: ${resolvedPath}`);
+
+        await sharp(resolvedPath)
+            .resize(width, height, { fit: "cover", position: sharp.strategy.attention })
+            .composite([{ input: Buffer.from(buildCoverTypographySvg(args, width, height)), left: 0, top: 0 }])
+            .png()
+            .toFile(outputPath);
+
+        return {
+            success: true,
+            output_paths: [outputPath],
+            message: `Cover "${args.title}" created.`,
+        };
+    } catch (error) {
+        console.error("Error generating cover:", error.message);
+        throw error;
+    } finally {
+        if (generatedPath) fs.rmSync(generatedPath, { force: true });
+    }
+}
+
+// Tiles images into a labeled grid and returns it as a PNG buffer.
+async function buildContactSheet(imagePaths, options = {}) {
+    const cellSize = options.cellSize || 320;
+    const columns = Math.max(1, options.columns || Math.ceil(Math.sqrt(imagePaths.length)));
+    const rows = Math.ceil(imagePaths.length / columns);
+    const gap = Math.round(cellSize * 0.05);
+    const labelHeight = Math.max(18, Math.round(cellSize * 0.09));
+    const fontSize = Math.round(labelHeight * 0.7);
+    const width = gap + columns * (cellSize + gap);
+    const height = gap + rows * (cellSize + labelHeight + gap);
+
+    const composites = [];
+    const labels = [];
+    for (let i = 0; i < imagePaths.length; i++) {
+        const left = gap + (i % columns) * (cellSize + gap);
+        const top = gap + Math.floor(i / columns) * (cellSize + labelHeight + gap);
+        const thumb = await sharp(imagePaths[i])
+            .resize(cellSize, cellSize, { fit: "inside" })
+            .png()
+            .toBuffer({ resolveWithObject: true });
+        composites.push({
+            input: thumb.data,
+            left: left + Math.floor((cellSize - thumb.info.width) / 2),
+            top: top + Math.floor((cellSize - thumb.info.height) / 2),
+        });
+
+        const number = options.formatter ? options.formatter.number(i + 1) : String(i + 1);
+        const label = options.labels && options.labels[i] != null ? String(options.labels[i]) : `${number}. ${path.basename(imagePaths[i])}`;
+        const maxChars = Math.max(4, Math.floor(cellSize / (fontSize * 0.55)));
+        const shown = label.length > maxChars ? label.slice(0, maxChars - 1) + "…" : label;
+        labels.push(`<text x="${left + cellSize / 2}" y="${top + cellSize + fontSize + 2}" text-anchor="middle"${textDirectionAttributes(shown)} font-family="${SVG_FONT}" font-size="${fontSize}" fill="#e0e0e0">${escapeXml(shown)}</text>`);
+    }
+    composites.push({ input: Buffer.from(`<svg xmlns="http://www.w3.org/2000/svg" width="${width}" height="${height}">${labels.join("")}</svg>`), left: 0, top: 0 });
+
+    return await sharp({ create: { width, height, channels: 3, background: options.background || "#1e1e1e" } })
+        .composite(composites)
+        .png()
+        .toBuffer();
+}
+
+async function contactSheet(args) {
+    try {
+        const imagePaths = args.image_paths && args.image_paths.length > 0
+            ? args.image_paths.map(p => path.resolve(p))
+            : args.source_dir ? listImagesInDir(args.source_dir) : [];
+        if (imagePaths.length === 0) throw new Error("Provide image_paths or a source_dir containing images.");
+        for (const p of imagePaths) {
+            if (!fs.existsSync(p)) throw new Error(`Image file not found: ${p}`);
+        }
+
+        const locale = args.locale || OUTPUT_LOCALE;
+        const formatter = locale ? createLocaleFormatter(locale) : null;
+        const outputPath = path.resolve(args.output_path || "contact_sheet.png");
+        fs.mkdirSync(path.dirname(outputPath), { recursive: true });
+        const sheet = await buildContactSheet(imagePaths, {
+            labels: args.labels,
+            formatter,
+            columns: args.columns,
+            cellSize: args.cell_size,
+            background: args.background,
+        });
+        fs.writeFileSync(outputPath, sheet);
+
+        return {
+            success: true,
+            output_paths: [outputPath],
+            images: imagePaths.map((p, i) => ({ index: i + 1, path: p })),
+            message: `Contact sheet with ${imagePaths.length} image(s) created.`,
+        };
+    } catch (error) {
+        console.error("Error creating contact sheet:", error.message);
+        throw error;
+    }
+}
+
+const CHAPTER_OVERVIEW_SCHEMA = {
+    type: Type.OBJECT,
+    properties: {
+        key_pages: { type: Type.ARRAY, items: { type: Type.INTEGER } },
+        recap: { type: Type.STRING },
+    },
+    required: ["key_pages", "recap"],
+};
+
+const KEY_PANEL_SCHEMA = {
+    type: Type.OBJECT,
+    properties: {
+        box_2d: { type: Type.ARRAY, items: { type: Type.INTEGER } },
+    },
+    required: ["box_2d"],
+};
+
+// Crops the most story-relevant panel from a page, falling back to the whole page.
+async function extractKeyPanel(pagePath) {
+    const { width, height } = await sharp(pagePath).metadata();
+    try {
+        const text = await describeImage(pagePath, "This is a comic/manga page. Return box_2d [ymin, xmin, ymax, xmax] (0-1000) of the single panel that matters most to the story.", { responseSchema: KEY_PANEL_SCHEMA });
+        const { box_2d } = JSON.parse(text.replace(/^```(json)?/, "").replace(/```$/, ""));
+        const [ymin, xmin, ymax, xmax] = box_2d.map(v => Math.max(0, Math.min(1000, v)) / 1000);
+        const box = {
+            left: Math.round(xmin * width),
+            top: Math.round(ymin * height),
+            width: Math.round((xmax - xmin) * width),
+            height: Math.round((ymax - ymin) * height),
+        };
+        if (box.width < width * 0.1 || box.height < height * 0.1) throw new Error("panel box too small");
+        return await sharp(pagePath).extract(box).png().toBuffer();
+    } catch (err) {
+        console.error(`Key panel detection failed for ${pagePath}, using the full page:`, err.message);
+        return await sharp(pagePath).png().toBuffer();
+    }
+}
+
+async function chapterSummaryPage(args) {
+    try {
+        const pages = listImagesInDir(args.source_dir);
+        if (pages.length === 0) throw new Error(`No images found in ${args.source_dir}`);
+        const outputPath = path.resolve(args.output_path || "chapter_summary.png");
+        fs.mkdirSync(path.dirname(outputPath), { recursive: true });
+        const panelCount = Math.max(1, Math.min(args.panel_count || 6, pages.length));
+        const width = args.width || 1600;
+
+        // Long chapters are sampled evenly so the numbered overview stays legible.
+        const maxOverview = 36;
+        const sampled = pages.length <= maxOverview
+            ? pages.map((p, i) => i)
+            : Array.from({ length: maxOverview }, (_, i) => Math.round((i * (pages.length - 1)) / (maxOverview - 1)));
+        const overview = await buildContactSheet(sampled.map(i => pages[i]), {
+            cellSize: 256,
+            labels: sampled.map(i => String(i + 1)),
+        });
+        const text = await describeImageData(overview.toString("base64"), "image/png",
+            `This grid shows the pages of one comic/manga chapter, each labeled with its page number. Choose the ${panelCount} page numbers that best summarize the chapter's story in order (key_pages), ` +
+            "and write a spoiler-light recap of 2-4 sentences (recap).",
+            { responseSchema: CHAPTER_OVERVIEW_SCHEMA });
+        let overviewResult;
+        try {
+            overviewResult = JSON.parse(text.replace(/^```(json)?/, "").replace(/```$/, ""));
+        } catch (err) {
+            throw new Error(`Vision model returned invalid JSON: ${text.slice(0, 200)}`);
+        }
+
+        let keyPages = [...new Set((overviewResult.key_pages || []).map(n => n - 1))]
+            .filter(i => i >= 0 && i < pages.length)
+            .sort((a, b) => a - b)
+            .slice(0, panelCount);
+        if (keyPages.length === 0) keyPages = sampled.filter((_, i) => i % Math.ceil(sampled.length / panelCount) === 0).slice(0, panelCount);
+
+        const panels = [];
+        for (const i of keyPages) panels.push(await extractKeyPanel(pages[i]));
+
+        const margin = Math.round(width * 0.03);
+        const columns = panels.length > 4 ? 3 : Math.min(2, panels.length);
+        const cellWidth = Math.floor((width - margin * (columns + 1)) / columns);
+        const cellHeight = Math.round(cellWidth * 0.75);
+        const rows = Math.ceil(panels.length / columns);
+        const titleHeight = args.title ? Math.round(width * 0.07) : 0;
+        const summaryText = args.summary || overviewResult.recap || "";
+        const summarySize = Math.round(width / 45);
+        const summary = summaryText ? fitTextLines(summaryText, width - margin * 2, width * 0.2, summarySize) : null;
+        const summaryHeight = summary ? Math.round(summary.lines.length * summary.lineHeight + margin) : 0;
+        const height = titleHeight + margin + rows * (cellHeight + margin) + summaryHeight;
+
+        const composites = [];
+        for (let i = 0; i < panels.length; i++) {
+            composites.push({
+                input: await sharp(panels[i]).resize(cellWidth, cellHeight, { fit: "cover", position: sharp.strategy.attention }).png().toBuffer(),
+                left: margin + (i % columns) * (cellWidth + margin),
+                top: titleHeight + margin + Math.floor(i / columns) * (cellHeight + margin),
+            });
+        }
+
+        const overlay = [];
+        if (args.title) {
+            overlay.push(`<text x="${width / 2}" y="${Math.round(margin + titleHeight * 0.6)}" text-anchor="middle" font-family="${SVG_FONT}" font-size="${Math.round(titleHeight * 0.55)}" font-weight="bold" fill="#111">${escapeXml(args.title)}</text>`);
+        }
+        for (let i = 0; i < panels.length; i++) {
+            const left = margin + (i % columns) * (cellWidth + margin);
+            const top = titleHeight + margin + Math.floor(i / columns) * (cellHeight + margin);
+            overlay.push(`<rect x="${left}" y="${top}" width="${cellWidth}" height="${cellHeight}" fill="none" stroke="#111" stroke-width="3"/>`);
+            overlay.push(`<rect x="${left}" y="${top + cellHeight - 30}" width="70" height="30" fill="#111"/><text x="${left + 35}" y="${top + cellHeight - 9}" text-anchor="middle" font-family="${SVG_FONT}" font-size="16" fill="#fff">p. ${keyPages[i] + 1}</text>`);
+        }
+        if (summary) {
+            const startY = height - summaryHeight + summary.fontSize;
+            const direction = textDirectionAttributes(summaryText);
+            const x = direction ? width - margin : margin;
+            const tspans = summary.lines.map((l, i) => `<tspan x="${x}" y="${(startY + i * summary.lineHeight).toFixed(1)}">${escapeXml(l)}</tspan>`).join("");
+            overlay.push(`<text${direction} font-family="${SVG_FONT}" font-size="${summary.fontSize}" fill="#222">${tspans}</text>`);
+        }
+        composites.push({ input: Buffer.from(`<svg xmlns="http://www.w3.org/2000/svg" width="${width}" height="${height}">${overlay.join("")}</svg>`), left: 0, top: 0 });
+
+        await sharp({ create: { width, height, channels: 3, background: "#fafafa" } }).composite(composites).png().toFile(outputPath);
+
+        return {
+            success: true,
+            output_paths: [outputPath],
+            key_pages: keyPages.map(i => ({ page: i + 1, path: pages[i] })),
+            summary: summaryText,
+            message: `Chapter summary page created from ${pages.length} page(s).`,
+        };
+    } catch (error) {
+        console.error("Error creating chapter summary page:", error.message);
+        throw error;
+    }
+}
+
+// --- Color Analysis ---
+
+// Machado et al. (2009) simulation matrices at full severity, applied to sRGB values.
+const COLOR_BLINDNESS_MATRICES = {
+    protanopia: [[0.152286, 1.052583, -0.204868], [0.114503, 0.786281, 0.099216], [-0.003882, -0.048116, 1.051998]],
+    deuteranopia: [[0.367322, 0.860646, -0.227968], [0.280085, 0.672501, 0.047413], [-0.011820, 0.042940, 0.968881]],
+    tritanopia: [[1.255528, -0.076749, -0.178779], [-0.078411, 0.930809, 0.147602], [0.004733, 0.691367, 0.303900]],
+    achromatopsia: [[0.2126, 0.7152, 0.0722], [0.2126, 0.7152, 0.0722], [0.2126, 0.7152, 0.0722]],
+};
+
+function toHex([r, g, b]) {
+    return "#" + [r, g, b].map(v => Math.round(Math.max(0, Math.min(255, v))).toString(16).padStart(2, "0")).join("");
+}
+
+function parseHexColor(hex) {
+    const match = /^#?([0-9a-f]{3}|[0-9a-f]{6})$/i.exec(String(hex).trim());
+    if (!match) throw new Error(`Invalid hex color: ${hex}`);
+    const h = match[1].length === 3 ? match[1].split("").map(c => c + c).join("") : match[1];
+    return [0, 2, 4].map(i => parseInt(h.slice(i, i + 2), 16));
+}
+
+function applyColorMatrix(rgb, matrix) {
+    return matrix.map(row => Math.max(0, Math.min(255, row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2])));
+}
+
+function srgbToLinear(v) {
+    v /= 255;
+    return v <= 0.04045 ? v / 12.92 : Math.pow((v + 0.055) / 1.055, 2.4);
+}
+
+function srgbToLab(rgb) {
+    const [r, g, b] = rgb.map(srgbToLinear);
+    const xyz = [
+        (r * 0.4124 + g * 0.3576 + b * 0.1805) / 0.95047,
+        (r * 0.2126 + g * 0.7152 + b * 0.0722) / 1.0,
+        (r * 0.0193 + g * 0.1192 + b * 0.9505) / 1.08883,
+    ].map(t => t > 0.008856 ? Math.cbrt(t) : 7.787 * t + 16 / 116);
+    return [116 * xyz[1] - 16, 500 * (xyz[0] - xyz[1]), 200 * (xyz[1] - xyz[2])];
+}
+
+// CIE76 color difference; ~2.3 is a just-noticeable difference, >10 is clearly distinct.
+function deltaE(rgb1, rgb2) {
+    const a = srgbToLab(rgb1), b = srgbToLab(rgb2);
+    return Math.hypot(a[0] - b[0], a[1] - b[1], a[2] - b[2]);
+}
+
+function relativeLuminance(rgb) {
+    const [r, g, b] = rgb.map(srgbToLinear);
+    return 0.2126 * r + 0.7152 * g + 0.0722 * b;
+}
+
+// WCAG 2.x contrast ratio (1-21).
+function contrastRatio(rgb1, rgb2) {
+    const l1 = relativeLuminance(rgb1), l2 = relativeLuminance(rgb2);
+    return (Math.max(l1, l2) + 0.05) / (Math.min(l1, l2) + 0.05);
+}
+
+// Dominant colors via histogram seeding + k-means on a downsampled copy. Returns [{ rgb, hex, coverage }] by coverage.
+async function extractDominantColors(input, count = 6) {
+    const { data, info } = await sharp(input)
+        .resize({ width: 128, height: 128, fit: "inside" })
+        .removeAlpha()
+        .raw()
+        .toBuffer({ resolveWithObject: true });
+    const pixels = [];
+    for (let i = 0; i < data.length; i += info.channels) {
+        pixels.push([data[i], data[i + 1], data[i + 2]]);
+    }
+
+    // Seed with the most populated 4-bit-per-channel buckets that are not near-duplicates of each other.
+    const buckets = new Map();
+    for (const p of pixels) {
+        const key = (p[0] >> 4) << 8 | (p[1] >> 4) << 4 | (p[2] >> 4);
+        const bucket = buckets.get(key) || { sum: [0, 0, 0], n: 0 };
+        bucket.sum[0] += p[0]; bucket.sum[1] += p[1]; bucket.sum[2] += p[2]; bucket.n++;
+        buckets.set(key, bucket);
+    }
+    const seeds = [];
+    for (const b of [...buckets.values()].sort((x, y) => y.n - x.n)) {
+        const mean = b.sum.map(v => v / b.n);
+        if (seeds.every(s => deltaE(s, mean) > 12)) seeds.push(mean);
+        if (seeds.length === count) break;
+    }
+
+    let centers = seeds;
+    const assignments = new Array(pixels.length).fill(0);
+    for (let iteration = 0; iteration < 8; iteration++) {
+        const sums = centers.map(() => [0, 0, 0, 0]);
+        pixels.forEach((p, i) => {
+            let best = 0, bestDistance = Infinity;
+            centers.forEach((c, ci) => {
+                const d = (p[0] - c[0]) ** 2 + (p[1] - c[1]) ** 2 + (p[2] - c[2]) ** 2;
+                if (d < bestDistance) { bestDistance = d; best = ci; }
+            });
+            assignments[i] = best;
+            sums[best][0] += p[0]; sums[best][1] += p[1]; sums[best][2] += p[2]; sums[best][3]++;
+        });
+        centers = centers.map((c, ci) => sums[ci][3] ? [sums[ci][0] / sums[ci][3], sums[ci][1] / sums[ci][3], sums[ci][2] / sums[ci][3]] : c);
+    }
+
+    const counts = centers.map(() => 0);
+    assignments.forEach(a => counts[a]++);
+    return centers
+        .map((c, i) => ({ rgb: c.map(Math.round), hex: toHex(c), coverage: counts[i] / pixels.length }))
+        .filter(c => c.coverage > 0)
+        .sort((a, b) => b.coverage - a.coverage);
+}
+
+async function analyzeColors(imagePath, count = 6, palette, tolerance = 10) {
+    try {
+        const resolvedPath = path.resolve(imagePath);
+        if (!fs.existsSync(resolvedPath)) throw new Error(`Image file not found: ${resolvedPath}`);
+
+        const brand = (palette || []).map(hex => ({ hex: toHex(parseHexColor(hex)), rgb: parseHexColor(hex) }));
+        const colors = await extractDominantColors(resolvedPath, count);
+
+        const report = colors.map(c => {
+            const entry = { hex: c.hex, rgb: c.rgb, coverage_percent: +(c.coverage * 100).toFixed(1) };
+            if (brand.length > 0) {
+                const nearest = brand
+                    .map(b => ({ hex: b.hex, delta_e: deltaE(c.rgb, b.rgb) }))
+                    .sort((a, b) => a.delta_e - b.delta_e)[0];
+                entry.nearest_brand_color = nearest.hex;
+                entry.delta_e = +nearest.delta_e.toFixed(1);
+                entry.on_brand = nearest.delta_e <= tolerance;
+            }
+            return entry;
+        });
+
+        const result = { success: true, colors: report };
+        if (brand.length > 0) {
+            const onBrand = report.filter(c => c.on_brand).reduce((sum, c) => sum + c.coverage_percent, 0);
+            result.on_brand_percent = +onBrand.toFixed(1);
+            result.off_brand_colors = report.filter(c => !c.on_brand).map(c => c.hex);
+            result.message = `${result.on_brand_percent}% of the image is within ΔE ${tolerance} of the brand palette.`;
+        } else {
+            result.message = `Extracted ${report.length} dominant color(s).`;
+        }
+        return result;
+    } catch (error) {
+        console.error("Error analyzing colors:", error.message);
+        throw error;
+    }
+}
+
+const PHASH_SIZE = 32;
+const PHASH_COS = Array.from({ length: 8 }, (_, u) => Array.from({ length: PHASH_SIZE }, (_, x) => Math.cos(((2 * x + 1) * u * Math.PI) / (2 * PHASH_SIZE))));
+
+// 64-bit DCT perceptual hash as a 16-char hex string.
+async function perceptualHash(input) {
+    const data = await sharp(input)
+        .flatten({ background: "#ffffff" })
+        .greyscale()
+        .resize(PHASH_SIZE, PHASH_SIZE, { fit: "fill" })
+        .raw()
+        .toBuffer();
+
+    // Only the lowest 8x8 frequencies are needed.
+    const coefficients = [];
+    for (let u = 0; u < 8; u++) {
+        for (let v = 0; v < 8; v++) {
+            let sum = 0;
+            for (let y = 0; y < PHASH_SIZE; y++) {
+                for (let x = 0; x < PHASH_SIZE; x++) {
+                    sum += data[y * PHASH_SIZE + x] * PHASH_COS[u][y] * PHASH_COS[v][x];
+                }
+            }
+            coefficients.push(sum);
+        }
+    }
+
+    // Compare against the median of the AC terms (the DC term only encodes overall brightness).
+    const median = [...coefficients.slice(1)].sort((a, b) => a - b)[31];
+    let hash = 0n;
+    for (const c of coefficients) {
+        hash = (hash << 1n) | (c > median ? 1n : 0n);
+    }
+    return hash.toString(16).padStart(16, "0");
+}
+
+function hammingDistance(hexA, hexB) {
+    let x = BigInt(`0x${hexA}`) ^ BigInt(`0x${hexB}`);
+    let count = 0;
+    while (x) {
+        count += Number(x & 1n);
+        x >>= 1n;
+    }
+    return count;
+}
+
+function listImagesRecursive(dir) {
+    const files = [];
+    for (const entry of fs.readdirSync(dir, { withFileTypes: true })) {
+        const full = path.join(dir, entry.name);
+        if (entry.isDirectory()) files.push(...listImagesRecursive(full));
+        else if (IMAGE_EXTENSIONS.includes(path.extname(entry.name).toLowerCase())) files.push(full);
+    }
+    return files;
+}
+
+async function findDuplicates(directory, recursive = false, threshold = 8) {
+    try {
+        const resolvedDir = path.resolve(directory);
+        if (!fs.existsSync(resolvedDir)) throw new Error(`Directory not found: ${resolvedDir}`);
+
+        const files = (recursive ? listImagesRecursive(resolvedDir) : listImagesInDir(resolvedDir))
+            .filter(f => path.extname(f).toLowerCase() !== ".svg");
+
+        const hashes = [];
+        const skipped = [];
+        for (const file of files) {
+            try {
+                hashes.push({ path: file, hash: await perceptualHash(file) });
+            } catch (err) {
+                skipped.push({ path: file, error: err.message });
+            }
+        }
+
+        // Union-find over all pairs within the threshold.
+        const parent = hashes.map((_, i) => i);
+        const find = i => (parent[i] === i ? i : (parent[i] = find(parent[i])));
+        const pairs = [];
+        for (let i = 0; i < hashes.length; i++) {
+            for (let j = i + 1; j < hashes.length; j++) {
+                const distance = hammingDistance(hashes[i].hash, hashes[j].hash);
+                if (distance <= threshold) {
+                    parent[find(i)] = find(j);
+                    pairs.push({ a: hashes[i].path, b: hashes[j].path, distance: distance });
+                }
+            }
+        }
+
+        const groups = new Map();
+        hashes.forEach((h, i) => {
+            const root = find(i);
+            if (!groups.has(root)) groups.set(root, []);
+            groups.get(root).push(h);
+        });
+        const clusters = [...groups.values()]
+            .filter(g => g.length > 1)
+            .map(g => ({
+                files: g.map(h => ({ path: h.path, hash: h.hash, size: fs.statSync(h.path).size })),
+                pairs: pairs.filter(p => g.some(h => h.path === p.a)),
+            }));
+
+        return {
+            success: true,
+            scanned: hashes.length,
+            skipped: skipped,
+            clusters: clusters,
+            message: clusters.length === 0
+                ? `No near-duplicates among ${hashes.length} image(s).`
+                : `Found ${clusters.length} cluster(s) of near-duplicates covering ${clusters.reduce((n, c) => n + c.files.length, 0)} image(s).`
+        };
+    } catch (error) {
+        console.error("Error finding duplicates:", error.message);
+        throw error;
+    }
+}
+
+async function simulateColorBlindness(imagePath, types = ["protanopia", "deuteranopia", "tritanopia"], outputDir) {
+    try {
+        const resolvedPath = path.resolve(imagePath);
+        if (!fs.existsSync(resolvedPath)) throw new Error(`Image file not found: ${resolvedPath}`);
+        const unknown = types.filter(t => !COLOR_BLINDNESS_MATRICES[t]);
+        if (unknown.length > 0) throw new Error(`Unknown simulation type(s): ${unknown.join(", ")}`);
+
+        const resolvedOutputDir = path.resolve(outputDir || path.dirname(resolvedPath));
+        fs.mkdirSync(resolvedOutputDir, { recursive: true });
+        const name = path.basename(resolvedPath, path.extname(resolvedPath));
+
+        // Only colors covering a meaningful share of the image are worth reporting.
+        const colors = (await extractDominantColors(resolvedPath, 8)).filter(c => c.coverage >= 0.02);
+        const lowContrastPairs = [];
+        for (let i = 0; i < colors.length; i++) {
+            for (let j = i + 1; j < colors.length; j++) {
+                const ratio = contrastRatio(colors[i].rgb, colors[j].rgb);
+                if (deltaE(colors[i].rgb, colors[j].rgb) > 10 && ratio < 3) {
+                    lowContrastPairs.push({ colors: [colors[i].hex, colors[j].hex], contrast_ratio: +ratio.toFixed(2) });
+                }
+            }
+        }
+
+        const simulations = [];
+        for (const type of types) {
+            const matrix = COLOR_BLINDNESS_MATRICES[type];
+            const outputPath = path.join(resolvedOutputDir, `${name}_${type}.png`);
+            await sharp(resolvedPath).recomb(matrix).png().toFile(outputPath);
+
+            // Pairs clearly distinct in the original that collapse (ΔE < 10) under the simulation.
+            const confusablePairs = [];
+            for (let i = 0; i < colors.length; i++) {
+                for (let j = i + 1; j < colors.length; j++) {
+                    const original = deltaE(colors[i].rgb, colors[j].rgb);
+                    const simulated = deltaE(applyColorMatrix(colors[i].rgb, matrix), applyColorMatrix(colors[j].rgb, matrix));
+                    if (original > 20 && simulated < 10) {
+                        confusablePairs.push({
+                            colors: [colors[i].hex, colors[j].hex],
+                            coverage: +(colors[i].coverage + colors[j].coverage).toFixed(3),
+                            delta_e_original: +original.toFixed(1),
+                            delta_e_simulated: +simulated.toFixed(1),
+                        });
+                    }
+                }
+            }
+            simulations.push({ type: type, output_path: outputPath, confusable_pairs: confusablePairs });
+        }
+
+        const issues = simulations.reduce((n, s) => n + s.confusable_pairs.length, 0) + lowContrastPairs.length;
+        return {
+            success: true,
+            output_paths: simulations.map(s => s.output_path),
+            dominant_colors: colors.map(c => ({ hex: c.hex, coverage: +c.coverage.toFixed(3) })),
+            low_contrast_pairs: lowContrastPairs,
+            simulations: simulations,
+            message: issues === 0 ? "No accessibility issues found among the dominant colors." : `Found ${issues} potential accessibility issue(s).`
+        };
+    } catch (error) {
+        console.error("Error simulating color blindness:", error.message);
+        throw error;
+    }
+}
+
+// --- Project Export ---
+
+const IMAGE_EXTENSIONS = [".png", ".jpg", ".jpeg", ".webp", ".gif", ".tiff", ".avif", ".svg"];
+
+function listImagesInDir(dir) {
+    const resolvedDir = path.resolve(dir);
+    if (!fs.existsSync(resolvedDir)) throw new Error(`Directory not found: ${resolvedDir}`);
+    return fs.readdirSync(resolvedDir)
+        .filter(f => IMAGE_EXTENSIONS.includes(path.extname(f).toLowerCase()))
+        .sort((a, b) => a.localeCompare(b, undefined, { numeric: true }))
+        .map(f => path.join(resolvedDir, f));
+}
+
+// Writes `content` only when it differs from what is on disk, so re-exports don't produce spurious git changes.
+function writeIfChanged(filePath, content) {
+    if (fs.existsSync(filePath) && fs.readFileSync(filePath).equals(Buffer.from(content))) return false;
+    fs.writeFileSync(filePath, content);
+    return true;
+}
+
+// Human-readable numbers, byte sizes and dates for one locale, in the locale's own digits and separators. Dates are
+// shown in UTC so a re-export on another machine produces the same text.
+function createLocaleFormatter(locale) {
+    let canonical;
+    try {
+        [canonical] = Intl.getCanonicalLocales(locale);
+    } catch (err) {
+        throw new Error(`Invalid locale "${locale}": ${err.message}`);
     }
-  },
-  {
-    name: "resize_image",
-    description: "Resize an image to specific dimensions.",
-    inputSchema: {
-        type: "object",
-        properties: {
-            source_path: { type: "string", description: "Path to the source image." },
-            output_path: { type: "string", description: "Path where the resized image will be saved." },
-            width: { type: "number", description: "Target width in pixels." },
-            height: { type: "number", description: "Target height in pixels. Optional if preserving aspect ratio." },
-            fit: { type: "string", description: "How the image should be resized to fit the dimensions (cover, contain, fill, inside, outside). Default is 'cover'." }
+    const numberFormat = new Intl.NumberFormat(canonical);
+    const indexFormat = new Intl.NumberFormat(canonical, { useGrouping: false });
+    const dateFormat = new Intl.DateTimeFormat(canonical, { dateStyle: "medium", timeStyle: "short", timeZone: "UTC" });
+    const byteUnits = ["byte", "kilobyte", "megabyte", "gigabyte"];
+    return {
+        locale: canonical,
+        number: (n) => indexFormat.format(n),
+        dimensions: (width, height) => `${numberFormat.format(width)} × ${numberFormat.format(height)}`,
+        bytes: (n) => {
+            if (!Number.isFinite(n)) return undefined;
+            const exponent = Math.min(byteUnits.length - 1, Math.max(0, Math.floor(Math.log(n) / Math.log(1024))));
+            return new Intl.NumberFormat(canonical, { style: "unit", unit: byteUnits[exponent], unitDisplay: "short", maximumFractionDigits: exponent === 0 ? 0 : 1 })
+                .format(n / 1024 ** exponent);
         },
-        required: ["source_path", "width"]
-    }
-  },
-  {
-    name: "get_image_info",
-    description: "Get metadata about an image (dimensions, format, etc.).",
-    inputSchema: {
-        type: "object",
-        properties: {
-            image_path: { type: "string", description: "Path to the image file." }
+        date: (iso) => {
+            const date = new Date(iso);
+            return Number.isNaN(date.getTime()) ? undefined : dateFormat.format(date);
         },
-        required: ["image_path"]
+    };
+}
+
+async function exportProject(imagePaths, sourceDir, outputDir, namePrefix = "page", locale = OUTPUT_LOCALE) {
+    try {
+        const formatter = locale ? createLocaleFormatter(locale) : null;
+        const fromDir = !(imagePaths && imagePaths.length > 0) && sourceDir;
+        const sources = fromDir ? listImagesInDir(sourceDir) : (imagePaths || []).map(p => path.resolve(p));
+        if (sources.length === 0) throw new Error("No images to export. Provide image_paths or source_dir.");
+
+        const resolvedOutputDir = path.resolve(outputDir);
+        fs.mkdirSync(resolvedOutputDir, { recursive: true });
+        // Sources are recorded relative to source_dir (or the project directory) so the committed files don't carry
+        // machine-specific absolute paths.
+        const sourceBase = fromDir ? path.resolve(sourceDir) : resolvedOutputDir;
+
+        const digits = Math.max(4, String(sources.length).length);
+        const entries = [];
+        let changed = 0;
+
+        for (let i = 0; i < sources.length; i++) {
+            const source = sources[i];
+            if (!fs.existsSync(source)) throw new Error(`Image file not found: ${source}`);
+
+            const ext = path.extname(source).toLowerCase();
+            const fileName = `${namePrefix}-${String(i + 1).padStart(digits, "0")}${ext}`;
+            const buffer = fs.readFileSync(source);
+            const entry = {
+                index: i + 1,
+                file: fileName,
+                source: path.relative(sourceBase, source).split(path.sep).join("/"),
+                sha256: sha256(buffer),
+                size: buffer.length,
+            };
+
+            const sourceSidecar = getSidecarPath(source);
+            if (fs.existsSync(sourceSidecar)) {
+                entry.generation = JSON.parse(fs.readFileSync(sourceSidecar, "utf-8"));
+            }
+
+            if (ext !== ".svg") {
+                const metadata = await sharp(buffer).metadata();
+                entry.format = metadata.format;
+                entry.width = metadata.width;
+                entry.height = metadata.height;
+            } else {
+                entry.format = "svg";
+            }
+
+            if (formatter) {
+                entry.display = {
+                    index: formatter.number(i + 1),
+                    size: formatter.bytes(entry.size),
+                    dimensions: entry.width ? formatter.dimensions(entry.width, entry.height) : undefined,
+                    created: entry.generation?.created_at ? formatter.date(entry.generation.created_at) : undefined,
+                };
+            }
+
+            if (writeIfChanged(path.join(resolvedOutputDir, fileName), buffer)) changed++;
+            writeIfChanged(path.join(resolvedOutputDir, `${fileName}.json`), JSON.stringify(entry, null, 2) + "\n");
+            entries.push(entry);
+        }
+
+        const manifest = { name: namePrefix, ...(formatter ? { locale: formatter.locale } : {}), count: entries.length, files: entries };
+        writeIfChanged(path.join(resolvedOutputDir, "manifest.json"), JSON.stringify(manifest, null, 2) + "\n");
+
+        // Pages left over from a longer previous export (or another extension) would otherwise linger next to the new set.
+        const exported = new Set(entries.flatMap(e => [e.file, `${e.file}.json`]));
+        const pagePattern = new RegExp(`^${namePrefix.replace(/[.*+?^${}()|[\]\\]/g, "\\$&")}-\\d+(\\.[^.]+)(\\.json)?$`);
+        const removed = [];
+        for (const file of fs.readdirSync(resolvedOutputDir)) {
+            if (pagePattern.test(file) && !exported.has(file)) {
+                fs.unlinkSync(path.join(resolvedOutputDir, file));
+                removed.push(file);
+            }
+        }
+
+        const lfsExtensions = [...new Set(entries.map(e => path.extname(e.file).toLowerCase()).filter(e => e !== ".svg"))].sort();
+        const gitattributes = lfsExtensions.map(e => `*${e} filter=lfs diff=lfs merge=lfs -text`).join("\n") + "\n";
+        writeIfChanged(path.join(resolvedOutputDir, ".gitattributes"), gitattributes);
+
+        return {
+            success: true,
+            output_dir: resolvedOutputDir,
+            output_paths: entries.map(e => path.join(resolvedOutputDir, e.file)),
+            manifest_path: path.join(resolvedOutputDir, "manifest.json"),
+            removed: removed.length > 0 ? removed : undefined,
+            message: `Exported ${entries.length} image(s) (${changed} new or changed${removed.length > 0 ? `, ${removed.length} stale file(s) removed` : ""}).`
+        };
+    } catch (error) {
+        console.error("Error exporting project:", error.message);
+        throw error;
     }
-  }
-];
+}
 
-if (activeProvider === PROVIDERS.GEMINI) {
-    // Enhance generate_image_from_text with Gemini params
-    const genTool = tools.find(t => t.name === "generate_image_from_text");
-    genTool.description += " Supports advanced parameters like aspectRatio and resolution.";
-    genTool.inputSchema.properties.aspectRatio = { 
-        type: "string", 
-        description: "Aspect ratio of the image (e.g., '1:1', '3:4', '4:3', '9:16', '16:9')." 
-    };
-    genTool.inputSchema.properties.resolution = { 
-        type: "string", 
-        description: "Resolution/Size of the image (e.g., '1K', '2K', '4K')." 
-    };
-    genTool.inputSchema.properties.numberOfImages = { 
-        type: "number", 
-        description: "Number of images to generate." 
-    };
+const WEB_FORMAT_EXTENSIONS = { avif: "avif", webp: "webp", jpeg: "jpg", png: "png" };
 
-    // Enhance edit_image with Gemini params
-    const editTool = tools.find(t => t.name === "edit_image");
-    editTool.inputSchema.properties.aspectRatio = { 
-        type: "string", 
-        description: "Aspect ratio." 
-    };
-    editTool.inputSchema.properties.resolution = { 
-        type: "string", 
-        description: "Resolution/Size." 
-    };
-    editTool.inputSchema.properties.numberOfImages = { 
-        type: "number", 
-        description: "Number of images." 
+async function exportWebImages(imagePaths, sourceDir, outputDir, widths = [480, 960, 1600], formats = ["avif", "webp", "jpeg"], quality = 75, locale = OUTPUT_LOCALE) {
+    try {
+        const sources = imagePaths && imagePaths.length > 0 ? imagePaths.map(p => path.resolve(p)) : sourceDir ? listImagesInDir(sourceDir) : [];
+        if (sources.length === 0) throw new Error("No images to export. Provide image_paths or source_dir.");
+
+        if (!Array.isArray(formats) || formats.length === 0) {
+            throw new Error(`formats must list at least one of: ${Object.keys(WEB_FORMAT_EXTENSIONS).join(", ")}`);
+        }
+        if (!Array.isArray(widths) || widths.length === 0 || !widths.every(w => w > 0)) throw new Error("widths must list at least one positive width.");
+        formats = formats.map(f => f.toLowerCase() === "jpg" ? "jpeg" : f.toLowerCase());
+        const unsupported = formats.filter(f => !WEB_FORMAT_EXTENSIONS[f]);
+        if (unsupported.length > 0) throw new Error(`Unsupported web format(s): ${unsupported.join(", ")}. Use ${Object.keys(WEB_FORMAT_EXTENSIONS).join(", ")}.`);
+        const formatter = locale ? createLocaleFormatter(locale) : null;
+
+        const resolvedOutputDir = path.resolve(outputDir);
+        fs.mkdirSync(resolvedOutputDir, { recursive: true });
+
+        const pages = [];
+        let totalBytes = 0;
+
+        for (const source of sources) {
+            if (!fs.existsSync(source)) throw new Error(`Image file not found: ${source}`);
+            const metadata = await sharp(source).metadata();
+            const name = path.basename(source, path.extname(source));
+            // Never upscale: clamp to the original width and drop duplicate targets.
+            const targetWidths = [...new Set(widths.map(w => Math.min(Math.round(w), metadata.width)))].sort((a, b) => a - b);
+
+            const variants = [];
+            for (const format of formats) {
+                for (const width of targetWidths) {
+                    const fileName = `${name}-${width}w.${WEB_FORMAT_EXTENSIONS[format]}`;
+                    const filePath = path.join(resolvedOutputDir, fileName);
+                    const info = await sharp(source)
+                        .resize({ width: width, withoutEnlargement: true })
+                        .toFormat(format, { quality: quality })
+                        .toFile(filePath);
+                    variants.push({
+                        format: format, width: info.width, height: info.height, file: fileName, bytes: info.size,
+                        ...(formatter ? { display: { dimensions: formatter.dimensions(info.width, info.height), size: formatter.bytes(info.size) } } : {}),
+                    });
+                    totalBytes += info.size;
+                }
+            }
+
+            const fallbackFormat = formats[formats.length - 1];
+            const fallback = variants.filter(v => v.format === fallbackFormat).pop();
+            pages.push({
+                name: name,
+                source: source,
+                width: metadata.width,
+                height: metadata.height,
+                sources: formats.map(format => ({
+                    type: `image/${format}`,
+                    srcset: variants.filter(v => v.format === format).map(v => `${v.file} ${v.width}w`).join(", "),
+                })),
+                fallback: fallback.file,
+                ...(formatter ? { display: { dimensions: formatter.dimensions(metadata.width, metadata.height) } } : {}),
+                variants: variants,
+            });
+        }
+
+        const manifestPath = path.join(resolvedOutputDir, "web-manifest.json");
+        const manifest = formatter
+            ? { locale: formatter.locale, widths: widths, formats: formats, display: { total_size: formatter.bytes(totalBytes) }, pages: pages }
+            : { widths: widths, formats: formats, pages: pages };
+        fs.writeFileSync(manifestPath, JSON.stringify(manifest, null, 2) + "\n");
+
+        return {
+            success: true,
+            output_dir: resolvedOutputDir,
+            manifest_path: manifestPath,
+            message: `Exported ${pages.length} image(s) as ${pages.reduce((n, p) => n + p.variants.length, 0)} web variants (${(totalBytes / 1024).toFixed(0)} KiB total).`
+        };
+    } catch (error) {
+        console.error("Error exporting web images:", error.message);
+        throw error;
+    }
+}
+
+
+function collectVerificationRecords(target, recursive) {
+    const records = new Map();
+    const isManifest = fs.statSync(target).isFile();
+    const dir = isManifest ? path.dirname(target) : target;
+
+    const manifestPath = isManifest ? target : path.join(dir, "manifest.json");
+    if (fs.existsSync(manifestPath)) {
+        const manifest = JSON.parse(fs.readFileSync(manifestPath, "utf-8"));
+        for (const entry of manifest.files || []) {
+            const file = path.join(dir, entry.file);
+            records.set(file, { path: file, sha256: entry.sha256, size: entry.size, record: manifestPath });
+        }
+    }
+    if (isManifest) return [...records.values()];
+
+    const scan = (current) => {
+        for (const entry of fs.readdirSync(current, { withFileTypes: true })) {
+            const full = path.join(current, entry.name);
+            if (entry.isDirectory()) {
+                if (recursive) scan(full);
+                continue;
+            }
+            // Sidecars are named "<image>.<ext>.json".
+            const imagePath = full.slice(0, -".json".length);
+            if (!entry.name.endsWith(".json") || !IMAGE_EXTENSIONS.includes(path.extname(imagePath).toLowerCase()) || records.has(imagePath)) continue;
+            try {
+                const sidecar = JSON.parse(fs.readFileSync(full, "utf-8"));
+                const hash = sidecar.output?.sha256 || sidecar.sha256;
+                if (hash) {
+                    records.set(imagePath, { path: imagePath, sha256: hash, size: sidecar.output?.size ?? sidecar.size, record: full });
+                }
+            } catch (err) {
+                console.error(`Skipping unreadable sidecar ${full}:`, err.message);
+            }
+        }
     };
+    scan(dir);
+    return [...records.values()];
 }
 
-async function generateSvgFromTextRecraft(prompt, outputPath = "output.svg", options = {}) {
-  try {
-    const svgBuffer = await generateSvgRecraft(prompt, options);
+async function verifyOutputs(targetPath, recursive = false) {
+    try {
+        const resolvedPath = path.resolve(targetPath);
+        if (!fs.existsSync(resolvedPath)) throw new Error(`Path not found: ${resolvedPath}`);
 
-    const resolvedOutputPath = path.resolve(outputPath || "output.svg");
-    const dir = path.dirname(resolvedOutputPath);
+        const records = collectVerificationRecords(resolvedPath, recursive);
+        const ok = [], missing = [], modified = [], corrupted = [];
 
-    if (!fs.existsSync(dir)) {
-      fs.mkdirSync(dir, { recursive: true });
+        for (const record of records) {
+            if (!fs.existsSync(record.path)) {
+                missing.push({ path: record.path, record: record.record });
+                continue;
+            }
+            const buffer = fs.readFileSync(record.path);
+            const actual = sha256(buffer);
+            if (actual !== record.sha256) {
+                modified.push({ path: record.path, expected_sha256: record.sha256, actual_sha256: actual, expected_size: record.size, actual_size: buffer.length, record: record.record });
+                continue;
+            }
+            try {
+                // A full decode catches truncated or damaged files whose hash was recorded after the damage.
+                await sharp(buffer).stats();
+                ok.push(record.path);
+            } catch (err) {
+                corrupted.push({ path: record.path, error: err.message, record: record.record });
+            }
+        }
+
+        const problems = missing.length + modified.length + corrupted.length;
+        return {
+            success: true,
+            checked: records.length,
+            ok: ok.length,
+            missing: missing,
+            modified: modified,
+            corrupted: corrupted,
+            message: records.length === 0
+                ? "No manifest or sidecar records found."
+                : problems === 0 ? `All ${records.length} file(s) verified.` : `${problems} of ${records.length} file(s) failed verification.`
+        };
+    } catch (error) {
+        console.error("Error verifying outputs:", error.message);
+        throw error;
     }
+}
 
-    fs.writeFileSync(resolvedOutputPath, svgBuffer);
+// --- Desktop Tools ---
 
-    return {
-      success: true,
-      output_path: resolvedOutputPath,
-      message: "SVG generated successfully using Recraft V3 SVG on Replicate.",
-    };
-  } catch (error) {
-    console.error("Error generating SVG:", error.response ? error.response.data : error.message);
-    throw error;
-  }
+const execFileAsync = promisify(execFile);
+
+const DEFAULT_CAPTURE_PROMPT = "Redraw this UI mockup as a clean, polished, high-fidelity design. Keep the layout, content and hierarchy; fix alignment, spacing and typography.";
+
+// Platform-specific screenshot commands. Each writes a full-screen PNG to `file`; the region is cropped afterwards with sharp.
+function getScreenCaptureCommands(file) {
+    if (process.platform === "darwin") {
+        return [["screencapture", ["-x", "-t", "png", file]]];
+    }
+    if (process.platform === "win32") {
+        const script = [
+            "Add-Type -AssemblyName System.Windows.Forms,System.Drawing",
+            "$b = [System.Windows.Forms.SystemInformation]::VirtualScreen",
+            "$bmp = New-Object System.Drawing.Bitmap $b.Width, $b.Height",
+            "$g = [System.Drawing.Graphics]::FromImage($bmp)",
+            "$g.CopyFromScreen($b.Left, $b.Top, 0, 0, $bmp.Size)",
+            `$bmp.Save('${file.replace(/'/g, "''")}', [System.Drawing.Imaging.ImageFormat]::Png)`,
+        ].join("; ");
+        return [["powershell", ["-NoProfile", "-Command", script]]];
+    }
+    return [
+        ["grim", [file]],
+        ["gnome-screenshot", ["-f", file]],
+        ["import", ["-window", "root", file]],
+        ["scrot", ["-o", file]],
+    ];
 }
 
-async function generateImageFromText(prompt, outputPath = "output.png", options = {}) {
-  try {
-    let imageBuffers = [];
-    
-    if (IMAGE_GENERATION_MODE === MODES.NANO_BANANA_PRO) {
-      const buf = await generateImageNanoBanana(prompt, options);
-      imageBuffers = [buf];
-    } else if (activeProvider === PROVIDERS.GEMINI) {
-      imageBuffers = await generateImageGemini(prompt, options);
-    } else if (activeProvider === PROVIDERS.REPLICATE) {
-      const buf = await generateImageReplicate(prompt);
-      imageBuffers = [buf];
-    } else if (activeProvider === PROVIDERS.HUGGINGFACE) {
-      const buf = await generateImageHuggingFace(prompt);
-      imageBuffers = [buf];
+async function captureScreen(region, capturePath) {
+    const tmpFile = path.join(os.tmpdir(), `mcp-capture-${process.pid}-${Date.now()}.png`);
+    let captured = false;
+    const errors = [];
+
+    for (const [command, args] of getScreenCaptureCommands(tmpFile)) {
+        try {
+            await execFileAsync(command, args, { timeout: 30000 });
+            if (fs.existsSync(tmpFile)) {
+                captured = true;
+                break;
+            }
+        } catch (err) {
+            errors.push(`${command}: ${err.code === "ENOENT" ? "not installed" : err.message}`);
+        }
     }
 
-    const results = [];
-    const resolvedOutputPath = path.resolve(outputPath);
-    const dir = path.dirname(resolvedOutputPath);
-    const ext = path.extname(resolvedOutputPath);
-    const name = path.basename(resolvedOutputPath, ext);
+    if (!captured) {
+        throw new Error(`Screen capture failed. Tried: ${errors.join("; ")}`);
+    }
 
-    // Ensure directory exists
-    if (!fs.existsSync(dir)) {
-        fs.mkdirSync(dir, { recursive: true });
+    try {
+        let image = sharp(tmpFile);
+        if (region.width && region.height) {
+            image = image.extract({
+                left: Math.round(region.x || 0),
+                top: Math.round(region.y || 0),
+                width: Math.round(region.width),
+                height: Math.round(region.height),
+            });
+        }
+        const buffer = await image.png().toBuffer();
+
+        if (capturePath) {
+            const resolvedCapturePath = path.resolve(capturePath);
+            fs.mkdirSync(path.dirname(resolvedCapturePath), { recursive: true });
+            fs.writeFileSync(resolvedCapturePath, buffer);
+        }
+
+        return buffer;
+    } finally {
+        fs.rmSync(tmpFile, { force: true });
     }
+}
 
-    imageBuffers.forEach((buf, index) => {
-        let filePath;
-        if (index === 0) {
-            filePath = resolvedOutputPath;
-        } else {
-            filePath = path.join(dir, `${name}_${index + 1}${ext}`);
+async function captureAndGenerate(args) {
+    try {
+        const buffer = await captureScreen(args, args.capture_path);
+        const tmpInput = path.join(os.tmpdir(), `mcp-capture-input-${process.pid}-${Date.now()}.png`);
+        fs.writeFileSync(tmpInput, buffer);
+
+        try {
+            const result = await editImage(tmpInput, args.output_path, args.prompt || DEFAULT_CAPTURE_PROMPT);
+            if (args.capture_path) {
+                result.capture_path = path.resolve(args.capture_path);
+            }
+            return result;
+        } finally {
+            fs.rmSync(tmpInput, { force: true });
         }
-        
-        fs.writeFileSync(filePath, buf);
-        results.push(filePath);
-    });
-    
+    } catch (error) {
+        console.error("Error capturing screen:", error.message);
+        throw error;
+    }
+}
+
+// --- Server Info ---
+
+let hardwareInfoPromise = null;
+
+async function readCpuFeatures() {
+    const features = new Set();
+    if (process.arch === "arm64") features.add("neon");
+    try {
+        if (process.platform === "linux") {
+            const cpuinfo = fs.readFileSync("/proc/cpuinfo", "utf8");
+            const flags = (/^(flags|Features)\s*:\s*(.*)$/m.exec(cpuinfo) || [])[2] || "";
+            for (const flag of flags.split(/\s+/)) {
+                if (["sse4_2", "avx", "avx2", "fma", "avx512f", "asimd", "sve"].includes(flag)) features.add(flag === "asimd" ? "neon" : flag);
+            }
+        } else if (process.platform === "darwin" && process.arch === "x64") {
+            const { stdout } = await execFileAsync("sysctl", ["-n", "machdep.cpu.features", "machdep.cpu.leaf7_features"], { timeout: 3000 });
+            for (const flag of stdout.toLowerCase().split(/\s+/)) {
+                if (["sse4.2", "avx1.0", "avx2", "fma", "avx512f"].includes(flag)) features.add(flag.replace("sse4.2", "sse4_2").replace("avx1.0", "avx"));
+            }
+        }
+    } catch (err) {
+        // Feature probing is best-effort.
+    }
+    return [...features];
+}
+
+async function detectGpus() {
+    const gpus = [];
+    try {
+        const { stdout } = await execFileAsync("nvidia-smi", ["--query-gpu=name,memory.total", "--format=csv,noheader"], { timeout: 3000 });
+        for (const line of stdout.split("\n").filter(l => l.trim())) {
+            const [name, memory] = line.split(",").map(s => s.trim());
+            gpus.push({ vendor: "nvidia", name, memory, api: "cuda" });
+        }
+    } catch (err) {
+        // No NVIDIA driver.
+    }
+    if (process.platform === "darwin" && process.arch === "arm64") {
+        gpus.push({ vendor: "apple", name: "Apple Silicon GPU", api: "metal" });
+    }
+    return gpus;
+}
+
+// Detects CPU/GPU acceleration once and applies the fastest settings for local image processing.
+async function detectAcceleration() {
+    const cpus = os.cpus();
+    const cores = typeof os.availableParallelism === "function" ? os.availableParallelism() : cpus.length;
+    const cpuFeatures = await readCpuFeatures();
+    const gpus = await detectGpus();
+
+    let onnxRuntime = false;
+    try {
+        await import("onnxruntime-node");
+        onnxRuntime = true;
+    } catch (err) {
+        // Optional dependency, not installed.
+    }
+    const onnxProviders = [];
+    if (gpus.some(g => g.api === "cuda")) onnxProviders.push("cuda");
+    if (process.platform === "darwin") onnxProviders.push("coreml");
+    if (process.platform === "win32") onnxProviders.push("dml");
+    onnxProviders.push("cpu");
+
+    // libvips SIMD paths (Highway) cover SSE4/AVX2/NEON; enable them when the CPU has any of those.
+    const wantSimd = cpuFeatures.some(f => ["sse4_2", "avx2", "neon"].includes(f));
+    const sharpSimd = typeof sharp.simd === "function" ? sharp.simd(wantSimd) : null;
+    const sharpConcurrency = typeof sharp.concurrency === "function" ? sharp.concurrency(cores) : null;
+
     return {
-      success: true,
-      output_paths: results,
-      message: `Image(s) generated successfully using ${
-        IMAGE_GENERATION_MODE === MODES.NANO_BANANA_PRO ? "nano-banana-pro" : activeProvider
-      }`,
+        platform: process.platform,
+        arch: process.arch,
+        node: process.version,
+        cpu: { model: cpus[0] ? cpus[0].model : "unknown", cores, features: cpuFeatures },
+        gpus,
+        onnx_runtime: { installed: onnxRuntime, execution_providers: onnxRuntime ? onnxProviders : [] },
+        selected: {
+            sharp_simd: sharpSimd,
+            sharp_concurrency: sharpConcurrency,
+            libvips: sharp.versions ? sharp.versions.vips : undefined,
+            onnx_execution_provider: onnxRuntime ? onnxProviders[0] : null,
+        },
     };
-  } catch (error) {
-    console.error("Error generating image:", error.response ? error.response.data : error.message);
-    throw error;
-  }
 }
 
-async function editImage(imagePath, outputPath = "output.png", prompt, options = {}) {
+function getHardwareInfo() {
+    if (!hardwareInfoPromise) hardwareInfoPromise = detectAcceleration();
+    return hardwareInfoPromise;
+}
+
+async function serverInfo() {
+    let version = "unknown";
     try {
-        const resolvedPath = path.resolve(imagePath);
-        if (!fs.existsSync(resolvedPath)) throw new Error(`Image file not found: ${resolvedPath}`);
-        
-        const imageBuffer = fs.readFileSync(resolvedPath);
-        const base64Image = imageBuffer.toString("base64");
-        const ext = path.extname(resolvedPath).toLowerCase();
-        const mimeTypes = { ".png": "image/png", ".jpg": "image/jpeg", ".jpeg": "image/jpeg", ".gif": "image/gif", ".webp": "image/webp" };
-        const mimeType = mimeTypes[ext] || "image/png";
+        version = JSON.parse(fs.readFileSync(path.join(__dirname, "package.json"), "utf8")).version;
+    } catch (err) {
+        // Not running from the package directory.
+    }
+    return {
+        success: true,
+        name: "image-generation-server",
+        version,
+        provider: activeProvider,
+        mode: IMAGE_GENERATION_MODE,
+        models: {
+            gemini_image: activeProvider === PROVIDERS.GEMINI ? GEMINI_MODEL : undefined,
+            gemini_vision: VISION_PROVIDER !== "ollama" && geminiClient ? GEMINI_VISION_MODEL : undefined,
+            ollama_vision: VISION_PROVIDER === "ollama" ? OLLAMA_VISION_MODEL : undefined,
+            vertex_imagen: activeProvider === PROVIDERS.VERTEX ? VERTEX_IMAGEN_MODEL : undefined,
+            replicate_pose: REPLICATE_POSE_MODEL,
+        },
+        features: {
+            desktop_tools: ENABLE_DESKTOP_TOOLS,
+            metadata_sidecars: WRITE_METADATA_SIDECARS,
+            notifications: Boolean(NOTIFY_WEBHOOK_URL),
+            style_profiles_dir: STYLE_PROFILES_DIR,
+            experiments_dir: EXPERIMENTS_DIR,
+        },
+        hardware: await getHardwareInfo(),
+        tool_count: tools.length,
+    };
+}
+
+// --- Provider Health ---
+// Each configured provider gets a cheap authenticated request (account, model or status lookup; nothing is generated).
+// Results are kept as the latency/availability baseline and reported by list_providers.
+
+const providerHealth = new Map();
+
+function getConfiguredProviders() {
+    return Object.values(PROVIDERS).filter(provider => PROVIDER_REGISTRY[provider].configured());
+}
+
+// Returns an axios-style response (status checked by the caller) or throws for transport errors.
+function sendProviderProbe(provider) {
+    const get = (url, headers = {}) => axios.get(url, { headers, timeout: PROBE_TIMEOUT_MS, validateStatus: () => true });
+    switch (provider) {
+        case PROVIDERS.GEMINI:
+            return geminiClient.models.get({ model: GEMINI_MODEL }).then(() => ({ status: 200 }));
+        case PROVIDERS.VERTEX:
+            return vertexClient.models.get({ model: VERTEX_IMAGEN_MODEL }).then(() => ({ status: 200 }));
+        case PROVIDERS.REPLICATE:
+            return get("https://api.replicate.com/v1/account", { Authorization: `Bearer ${REPLICATE_API_TOKEN}` });
+        case PROVIDERS.HUGGINGFACE:
+            return get("https://huggingface.co/api/whoami-v2", { Authorization: `Bearer ${HUGGING_FACE_TOKEN}` });
+        case PROVIDERS.OPENAI:
+            return get(`${OPENAI_BASE_URL}/models/${encodeURIComponent(OPENAI_IMAGE_MODEL)}`, { Authorization: `Bearer ${OPENAI_API_KEY}` });
+        case PROVIDERS.AZURE_OPENAI:
+            return getAzureOpenAiAuthHeaders().then(headers => get(`${AZURE_OPENAI_ENDPOINT}/openai/models?api-version=${encodeURIComponent(AZURE_OPENAI_API_VERSION)}`, headers));
+        case PROVIDERS.STABILITY:
+            return get("https://api.stability.ai/v1/user/account", { Authorization: `Bearer ${STABILITY_API_KEY}` });
+        case PROVIDERS.FAL:
+            // Status of an unknown request: 404 with a valid key, 401/403 without.
+            return get(`https://queue.fal.run/${FAL_MODEL}/requests/00000000-0000-0000-0000-000000000000/status`, { Authorization: `Key ${FAL_KEY}` })
+                .then(response => (response.status === 404 ? { ...response, status: 200 } : response));
+        case PROVIDERS.TOGETHER:
+            return get("https://api.together.xyz/v1/models", { Authorization: `Bearer ${TOGETHER_API_KEY}` });
+        case PROVIDERS.RECRAFT:
+            return get(`${RECRAFT_API_URL}/users/me`, { Authorization: `Bearer ${RECRAFT_API_TOKEN}` });
+        case PROVIDERS.LEONARDO:
+            return get(`${LEONARDO_API_URL}/me`, { Authorization: `Bearer ${LEONARDO_API_KEY}` });
+        case PROVIDERS.BEDROCK: {
+            const url = `https://bedrock.${AWS_REGION}.amazonaws.com/foundation-models?byOutputModality=IMAGE`;
+            const headers = signAwsRequest("GET", url, { host: new URL(url).host }, "", {
+                region: AWS_REGION,
+                service: "bedrock",
+                accessKeyId: AWS_ACCESS_KEY_ID,
+                secretAccessKey: AWS_SECRET_ACCESS_KEY,
+                sessionToken: AWS_SESSION_TOKEN,
+            });
+            return get(url, headers);
+        }
+        case PROVIDERS.AUTOMATIC1111:
+            return get(`${AUTOMATIC1111_URL}/sdapi/v1/options`, AUTOMATIC1111_AUTH ? { Authorization: `Basic ${Buffer.from(AUTOMATIC1111_AUTH).toString("base64")}` } : {});
+        case PROVIDERS.COMFYUI:
+            return get(`${COMFYUI_URL}/system_stats`);
+        case PROVIDERS.MOCK:
+            return Promise.resolve({ status: 200 });
+        case PROVIDERS.BALANCED:
+            // Its members are probed as providers of their own, and unavailable ones are skipped when picking.
+            return Promise.resolve({ status: 200 });
+        default:
+            return Promise.reject(new Error(`No probe for provider ${provider}`));
+    }
+}
+
+async function probeProvider(provider) {
+    const started = Date.now();
+    let entry;
+    try {
+        const timeout = new Promise((resolve, reject) => setTimeout(() => reject(new Error(`timed out after ${PROBE_TIMEOUT_MS}ms`)), PROBE_TIMEOUT_MS).unref());
+        const response = await Promise.race([sendProviderProbe(provider), timeout]);
+        const available = response.status >= 200 && response.status < 300;
+        entry = { available, latency_ms: Date.now() - started, error: available ? undefined : `HTTP ${response.status}` };
+    } catch (error) {
+        entry = { available: false, latency_ms: Date.now() - started, error: error.message };
+    }
+    entry.checked_at = new Date().toISOString();
+    providerHealth.set(provider, entry);
+    return entry;
+}
+
+async function probeProviders() {
+    const providers = getConfiguredProviders();
+    await Promise.all(providers.map(probeProvider));
+    return providers;
+}
+
+async function listProviders(probe = false) {
+    if (probe) await probeProviders();
+    return {
+        success: true,
+        active: activeProvider,
+        providers: getConfiguredProviders().map(name => ({
+            name,
+            active: name === activeProvider,
+            capabilities: getProviderCapabilities(name),
+            default_model: PROVIDER_REGISTRY[name].defaultModel || null,
+            allowed_models: getAllowedModels(name),
+            health: providerHealth.get(name) || null,
+        })),
+    };
+}
+
+
+// --- Quality Routing ---
+// MODEL_ROUTES maps models to quality tiers and relative costs, e.g.
+// [{"tier":"draft","provider":"fal","model":"fal-ai/flux/schnell","cost":1},{"tier":"best","provider":"openai","model":"gpt-image-1","cost":20}].
+// A request for a tier is served by the cheapest route at that tier or above whose provider is configured and not marked unavailable.
+
+const QUALITY_TIERS = ["draft", "standard", "best"];
+
+function getModelRoutes() {
+    if (!Array.isArray(MODEL_ROUTES)) {
+        if (process.env.MODEL_ROUTES) console.error("Ignoring MODEL_ROUTES: expected a JSON array of routes.");
+        return [];
+    }
+    return MODEL_ROUTES.filter(route => QUALITY_TIERS.includes(route.tier) && Object.values(PROVIDERS).includes(route.provider));
+}
+
+function routeByQuality(tier) {
+    const rank = QUALITY_TIERS.indexOf(tier);
+    if (rank === -1) throw new Error(`Unknown quality tier: ${tier}. Use ${QUALITY_TIERS.join(", ")}.`);
+    const configured = getConfiguredProviders();
+    const candidates = getModelRoutes()
+        .filter(route => QUALITY_TIERS.indexOf(route.tier) >= rank)
+        .filter(route => configured.includes(route.provider) && providerHealth.get(route.provider)?.available !== false)
+        .sort((a, b) => (a.cost ?? Infinity) - (b.cost ?? Infinity) || QUALITY_TIERS.indexOf(a.tier) - QUALITY_TIERS.indexOf(b.tier));
+    if (candidates.length === 0) {
+        throw new Error(`No model route for quality '${tier}'. Add a route for a configured provider at this tier or above to MODEL_ROUTES.`);
+    }
+    const { provider, model, tier: routedTier, cost } = candidates[0];
+    return { quality: tier, tier: routedTier, provider, model, cost };
+}
+
+// --- Provider and Model Overrides ---
+// Generation tools take `provider` and `model` arguments that apply to every provider request of that call. Providers
+// must be configured; models must be the provider's default, listed for it in ALLOWED_MODELS (a JSON object of
+// provider -> [models], or "*" for any) or used by one of its MODEL_ROUTES. An explicit choice beats quality routing.
+
+function getAllowedModels(provider) {
+    if (ALLOWED_MODELS[provider] === "*") return "*";
+    const listed = Array.isArray(ALLOWED_MODELS[provider]) ? ALLOWED_MODELS[provider] : [];
+    const routed = getModelRoutes().filter(route => route.provider === provider).map(route => route.model);
+    return [...new Set([PROVIDER_REGISTRY[provider]?.defaultModel, ...listed, ...routed].filter(Boolean))];
+}
+
+function validateProviderOverride(provider, model) {
+    if (provider !== undefined) {
+        if (!PROVIDER_REGISTRY[provider]) {
+            throw new Error(`Unknown provider: ${provider}. Use one of: ${Object.values(PROVIDERS).join(", ")}.`);
+        }
+        if (!PROVIDER_REGISTRY[provider].configured()) {
+            throw new Error(`Provider ${provider} is not configured. Configured providers: ${getConfiguredProviders().join(", ")}.`);
+        }
+    }
+    if (model !== undefined) {
+        const target = provider || activeProvider;
+        const allowed = getAllowedModels(target);
+        if (allowed !== "*" && !allowed.includes(model)) {
+            throw new Error(`Model ${model} is not allowed for ${target}. Allowed: ${allowed.length > 0 ? allowed.join(", ") : "none"}. Add it to ALLOWED_MODELS to use it.`);
+        }
+    }
+}
+
+// Runs `fn` with a tool call's provider/model choice in effect (validated first).
+function runWithProviderOverride(args, fn) {
+    validateProviderOverride(args.provider, args.model);
+    if (args.provider === undefined && args.model === undefined) return fn();
+    const store = requestHttpContext.getStore() || {};
+    return requestHttpContext.run({ ...store, provider: args.provider, model: args.model }, fn);
+}
+
+function getProviderOverride() {
+    const { provider, model } = requestHttpContext.getStore() || {};
+    return provider || model ? { provider, model } : null;
+}
 
-        let imageBuffers = [];
+// A request that already picked its own provider (quality routing, replicate_model) keeps it.
+function applyProviderOverride(options) {
+    const override = getProviderOverride();
+    if (!override || options.provider || options.replicateModel) return options;
+    return { ...options, provider: override.provider || activeProvider, model: override.model };
+}
 
-        if (activeProvider === PROVIDERS.GEMINI) {
-            imageBuffers = await editImageGemini(base64Image, mimeType, prompt, options);
-        } else if (activeProvider === PROVIDERS.REPLICATE) {
-            const buf = await editImageReplicate(base64Image, mimeType, prompt);
-            imageBuffers = [buf];
-        } else if (activeProvider === PROVIDERS.HUGGINGFACE) {
-            const buf = await editImageHuggingFace(base64Image, mimeType, prompt);
-            imageBuffers = [buf];
-        }
+// --- Plugins ---
 
-        const results = [];
-        const resolvedOutputPath = path.resolve(outputPath);
-        const dir = path.dirname(resolvedOutputPath);
-        const extOutput = path.extname(resolvedOutputPath);
-        const name = path.basename(resolvedOutputPath, extOutput);
+// Plugins are external processes speaking line-delimited JSON on stdin/stdout:
+//   request:  { "id": 1, "method": "describe" | "call", "params": {...} }
+//   response: { "id": 1, "result": {...} } or { "id": 1, "error": { "message": "..." } }
+// "describe" returns { "tools": [{ name, description, inputSchema }] }; "call" receives { tool, arguments }.
+const pluginTools = new Map();
+const loadedPlugins = [];
+let pluginsReady = Promise.resolve();
 
-        if (!fs.existsSync(dir)) {
-            fs.mkdirSync(dir, { recursive: true });
-        }
+function startPlugin(spec, baseDir) {
+    const plugin = { name: spec.name || spec.command, spec, child: null, pending: new Map(), nextId: 1 };
 
-        imageBuffers.forEach((buf, index) => {
-            let filePath;
-            if (index === 0) {
-                filePath = resolvedOutputPath;
-            } else {
-                filePath = path.join(dir, `${name}_${index + 1}${extOutput}`);
+    plugin.ensureRunning = () => {
+        if (plugin.child && plugin.child.exitCode === null) return;
+        const child = spawn(spec.command, spec.args || [], {
+            cwd: spec.cwd ? path.resolve(baseDir, spec.cwd) : baseDir,
+            env: { ...process.env, ...(spec.env || {}) },
+            stdio: ["pipe", "pipe", "inherit"],
+        });
+        readline.createInterface({ input: child.stdout }).on("line", (line) => {
+            let message;
+            try {
+                message = JSON.parse(line);
+            } catch (err) {
+                console.error(`[plugin ${plugin.name}] ${line}`);
+                return;
             }
-            
-            fs.writeFileSync(filePath, buf);
-            results.push(filePath);
+            const pending = plugin.pending.get(message.id);
+            if (!pending) return;
+            plugin.pending.delete(message.id);
+            clearTimeout(pending.timer);
+            if (message.error) pending.reject(new Error(`Plugin ${plugin.name}: ${message.error.message || message.error}`));
+            else pending.resolve(message.result);
         });
-
-        return {
-            success: true,
-            output_paths: results,
-            message: `Image(s) edited successfully using ${activeProvider}`,
+        const failAll = (error) => {
+            for (const pending of plugin.pending.values()) {
+                clearTimeout(pending.timer);
+                pending.reject(error);
+            }
+            plugin.pending.clear();
         };
-    } catch (error) {
-        console.error("Error editing image:", error.response ? error.response.data : error.message);
-        throw error;
+        child.on("error", (err) => failAll(new Error(`Plugin ${plugin.name} failed to start: ${err.message}`)));
+        child.on("exit", (code) => failAll(new Error(`Plugin ${plugin.name} exited with code ${code}`)));
+        // A plugin that closes stdin (or dies mid-write) raises EPIPE here; unhandled, it would take the server down.
+        child.stdin.on("error", (err) => failAll(new Error(`Plugin ${plugin.name} stopped accepting requests: ${err.message}`)));
+        plugin.child = child;
+    };
+
+    plugin.request = (method, params, timeoutMs = spec.timeout_ms || 300000) => {
+        plugin.ensureRunning();
+        const id = plugin.nextId++;
+        return new Promise((resolve, reject) => {
+            const timer = setTimeout(() => {
+                plugin.pending.delete(id);
+                reject(new Error(`Plugin ${plugin.name} timed out on ${method}`));
+            }, timeoutMs);
+            plugin.pending.set(id, { resolve, reject, timer });
+            plugin.child.stdin.write(JSON.stringify({ id, method, params }) + "\n");
+        });
+    };
+
+    return plugin;
+}
+
+async function loadPlugins() {
+    if (!PLUGINS_CONFIG) return;
+    const configPath = path.resolve(PLUGINS_CONFIG);
+    const config = JSON.parse(fs.readFileSync(configPath, "utf8"));
+    for (const spec of config.plugins || []) {
+        if (!spec.command) {
+            console.error("Skipping plugin without a command:", JSON.stringify(spec));
+            continue;
+        }
+        const plugin = startPlugin(spec, path.dirname(configPath));
+        loadedPlugins.push(plugin);
+        try {
+            const description = await plugin.request("describe", {}, 10000);
+            for (const tool of description.tools || []) {
+                if (tools.some(t => t.name === tool.name)) {
+                    console.error(`Plugin ${plugin.name}: tool ${tool.name} conflicts with an existing tool and was skipped.`);
+                    continue;
+                }
+                tools.push({
+                    name: tool.name,
+                    description: tool.description || `Provided by plugin ${plugin.name}.`,
+                    inputSchema: tool.inputSchema || { type: "object", properties: {} },
+                });
+                pluginTools.set(tool.name, plugin);
+            }
+            console.error(`[System] Loaded plugin ${plugin.name} (${(description.tools || []).length} tool(s))`);
+        } catch (err) {
+            console.error(`Failed to load plugin ${plugin.name}:`, err.message);
+        }
     }
 }
 
-async function removeBackground(imagePath, outputPath) {
-    try {
-        const resolvedPath = path.resolve(imagePath);
-        if (!fs.existsSync(resolvedPath)) throw new Error(`Image file not found: ${resolvedPath}`);
-        
-        const imageBuffer = fs.readFileSync(resolvedPath);
-        const base64Image = imageBuffer.toString("base64");
-        const ext = path.extname(resolvedPath).toLowerCase();
-        const mimeTypes = { ".png": "image/png", ".jpg": "image/jpeg", ".jpeg": "image/jpeg", ".gif": "image/gif", ".webp": "image/webp" };
-        const mimeType = mimeTypes[ext] || "image/png";
+function stopPlugins() {
+    for (const plugin of loadedPlugins) {
+        if (plugin.child && plugin.child.exitCode === null) plugin.child.kill();
+    }
+}
 
-        let imageBufferResult = null;
-        let usedProvider = "";
+// Plugin results may carry images as { images: [{ data: base64 }] }; these are saved to output_path like built-in tools.
+async function callPluginTool(toolName, args = {}) {
+    const plugin = pluginTools.get(toolName);
+    const result = await plugin.request("call", { tool: toolName, arguments: args });
+    if (!result || !Array.isArray(result.images)) return result;
 
-        // Priority: Replicate > Hugging Face
-        if (REPLICATE_API_TOKEN) {
+    const buffers = result.images.map(img => Buffer.from(img.data, "base64"));
+    const outputPaths = saveImageBuffers(buffers, args.output_path || "output.png", {
+        tool: toolName,
+        request: args,
+        provider: `plugin:${plugin.name}`,
+        provider_metadata: result.metadata || {},
+    }, args.write_metadata);
+    const { images, ...rest } = result;
+    return { success: true, ...rest, output_paths: outputPaths };
+}
+
+
+// --- Hooks ---
+
+// Runs a hook command through the shell with `payload` as JSON on stdin. Resolves with the parsed stdout JSON (or null when stdout is empty); rejects on a non-zero exit.
+function runHook(command, payload) {
+    return new Promise((resolve, reject) => {
+        const child = spawn(command, { shell: true, stdio: ["pipe", "pipe", "pipe"] });
+        let stdout = "";
+        let stderr = "";
+        const timer = setTimeout(() => {
+            child.kill();
+            reject(new Error(`Hook timed out after ${HOOK_TIMEOUT_MS} ms: ${command}`));
+        }, HOOK_TIMEOUT_MS);
+        child.stdout.on("data", (data) => { stdout += data.toString(); });
+        child.stderr.on("data", (data) => { stderr += data.toString(); });
+        child.on("error", (err) => {
+            clearTimeout(timer);
+            reject(new Error(`Hook failed to start: ${err.message}`));
+        });
+        child.on("close", (code) => {
+            clearTimeout(timer);
+            if (code !== 0) {
+                reject(new Error(stderr.trim() || `Hook exited with code ${code}`));
+                return;
+            }
+            if (!stdout.trim()) {
+                resolve(null);
+                return;
+            }
             try {
-                console.error("[System] Attempting background removal with Replicate...");
-                imageBufferResult = await removeBackgroundReplicate(base64Image, mimeType);
-                usedProvider = "Replicate";
+                resolve(JSON.parse(stdout));
             } catch (err) {
-                console.error("Replicate background removal failed, falling back if possible:", err.message);
+                reject(new Error(`Hook printed invalid JSON: ${stdout.slice(0, 200)}`));
             }
-        }
-        
-        if (!imageBufferResult) {
-            // Fallback to Hugging Face (Space doesn't necessarily need token, or we can use the Space implementation)
-             console.error("[System] Attempting background removal with Hugging Face Space...");
-             imageBufferResult = await removeBackgroundHuggingFace(imageBuffer);
-             usedProvider = "Hugging Face";
-        }
+        });
+        child.stdin.on("error", () => {});
+        child.stdin.end(JSON.stringify(payload));
+    });
+}
 
-        if (!imageBufferResult) {
-            throw new Error("Background removal failed with all available providers.");
-        }
+// Loads the user's post-processing module, re-importing it whenever the file changes so edits apply without a restart.
+let postProcessModule = null;
+async function loadPostProcessScript() {
+    const scriptPath = path.resolve(POSTPROCESS_SCRIPT);
+    const mtime = fs.statSync(scriptPath).mtimeMs;
+    if (!postProcessModule || postProcessModule.mtime !== mtime) {
+        const module = await import(`${pathToFileURL(scriptPath).href}?mtime=${mtime}`);
+        const fn = module.default || module.postProcess;
+        if (typeof fn !== "function") throw new Error(`${scriptPath} must export a default (or postProcess) function.`);
+        postProcessModule = { mtime, fn };
+    }
+    return postProcessModule.fn;
+}
 
-        // Determine output path
-        if (!outputPath) {
-             const dir = path.dirname(resolvedPath);
-             const name = path.basename(resolvedPath, ext);
-             outputPath = path.join(dir, `${name}_nobg.png`);
-        }
+// Runs the post-processing script on a tool result that wrote images. The script gets a context with helpers and may return a replacement result.
+async function runPostProcessScript(toolName, args, result) {
+    const postProcess = await loadPostProcessScript();
+    const outputs = Array.isArray(result.output_paths) ? result.output_paths : [];
+    const ctx = {
+        tool: toolName,
+        arguments: args,
+        result: result,
+        outputs: [...outputs],
+        sharp: sharp,
+        path: path,
+        log: (...parts) => console.error("[postprocess]", ...parts),
+        // Renames an output (and its sidecar) and updates output_paths.
+        rename: (from, to) => {
+            const source = path.resolve(from);
+            const target = path.resolve(path.dirname(source), to);
+            fs.mkdirSync(path.dirname(target), { recursive: true });
+            fs.renameSync(source, target);
+            if (fs.existsSync(getSidecarPath(source))) fs.renameSync(getSidecarPath(source), getSidecarPath(target));
+            ctx.result.output_paths = (ctx.result.output_paths || []).map(p => (path.resolve(p) === source ? target : p));
+            return target;
+        },
+        readSidecar: (file) => {
+            const sidecarPath = getSidecarPath(path.resolve(file));
+            return fs.existsSync(sidecarPath) ? JSON.parse(fs.readFileSync(sidecarPath, "utf8")) : null;
+        },
+        writeSidecar: (file, data) => {
+            fs.writeFileSync(getSidecarPath(path.resolve(file)), JSON.stringify(data, null, 2) + "\n");
+        },
+    };
+    const returned = await postProcess(ctx);
+    return returned && typeof returned === "object" ? returned : ctx.result;
+}
 
-        const resolvedOutputPath = path.resolve(outputPath);
-        const dirOutput = path.dirname(resolvedOutputPath);
-        if (!fs.existsSync(dirOutput)) {
-            fs.mkdirSync(dirOutput, { recursive: true });
+// Wraps a tool call with the configured hooks. The pre-hook can veto the call (non-zero exit) or replace its arguments;
+// the post-hook can reject the result or replace it.
+async function callToolWithHooks(toolName, toolInput = {}) {
+    const hooked = !HOOK_TOOLS || HOOK_TOOLS.includes(toolName);
+    let args = toolInput;
+
+    if (PRE_GENERATION_HOOK && hooked) {
+        try {
+            const replacement = await runHook(PRE_GENERATION_HOOK, { stage: "pre", tool: toolName, arguments: args });
+            if (replacement && replacement.arguments) args = replacement.arguments;
+        } catch (err) {
+            throw new Error(`Vetoed by pre-generation hook: ${err.message}`);
         }
+    }
 
-        fs.writeFileSync(resolvedOutputPath, imageBufferResult);
+    const asObject = (value) => (value && typeof value === "object" && !Array.isArray(value) ? value : undefined);
+    const asRate = (value) => (typeof value === "number" && value >= 0 ? value : undefined);
+    const httpExtras = {
+        headers: asObject(args.http_headers),
+        query: asObject(args.http_query),
+        maxUploadRate: asRate(args.max_upload_rate),
+        maxDownloadRate: asRate(args.max_download_rate),
+    };
+    let result = await requestHttpContext.run(httpExtras, () => (PROVIDER_OVERRIDE_TOOLS.includes(toolName)
+        ? runWithProviderOverride(args, () => processToolCall(toolName, args))
+        : processToolCall(toolName, args)));
 
-        return {
-            success: true,
-            output_paths: [resolvedOutputPath],
-            message: `Background removed successfully using ${usedProvider}`,
-        };
+    if (POSTPROCESS_SCRIPT && result && Array.isArray(result.output_paths) && result.output_paths.length > 0) {
+        try {
+            result = await runPostProcessScript(toolName, args, result);
+        } catch (err) {
+            throw new Error(`Post-processing script failed: ${err.message}`);
+        }
+    }
 
-    } catch (error) {
-        console.error("Error removing background:", error.message);
-        throw error;
+    if (POST_GENERATION_HOOK && hooked) {
+        try {
+            const replacement = await runHook(POST_GENERATION_HOOK, { stage: "post", tool: toolName, arguments: args, result });
+            if (replacement && replacement.result) result = replacement.result;
+        } catch (err) {
+            throw new Error(`Rejected by post-generation hook: ${err.message}`);
+        }
     }
-}
 
-// --- New Tools Implementation (Sharp) ---
+    return result;
+}
 
-async function convertImage(sourcePath, outputPath, format) {
-    try {
-        const resolvedSourcePath = path.resolve(sourcePath);
-        if (!fs.existsSync(resolvedSourcePath)) throw new Error(`Source image not found: ${resolvedSourcePath}`);
 
-        // Normalize format
-        format = format.toLowerCase().replace('.', '');
-        if (format === 'jpg') format = 'jpeg';
-        
-        const validFormats = ['png', 'jpeg', 'webp', 'gif', 'tiff', 'avif', 'heif'];
-        if (!validFormats.includes(format)) throw new Error(`Unsupported format: ${format}`);
+// --- Notifications ---
 
-        // Determine output path if not provided
-        if (!outputPath) {
-            const dir = path.dirname(resolvedSourcePath);
-            const name = path.basename(resolvedSourcePath, path.extname(resolvedSourcePath));
-            outputPath = path.join(dir, `${name}.${format}`);
-        }
-        
-        const resolvedOutputPath = path.resolve(outputPath);
-        const dirOutput = path.dirname(resolvedOutputPath);
-        if (!fs.existsSync(dirOutput)) {
-            fs.mkdirSync(dirOutput, { recursive: true });
-        }
+function getWebhookType(url) {
+    if (/hooks\.slack\.com/.test(url)) return "slack";
+    if (/discord(app)?\.com\/api\/webhooks/.test(url)) return "discord";
+    return "generic";
+}
 
-        await sharp(resolvedSourcePath)
-            .toFormat(format)
-            .toFile(resolvedOutputPath);
+function getResultPaths(result) {
+    if (!result) return [];
+    if (Array.isArray(result.output_paths)) return result.output_paths;
+    if (result.output_path) return [result.output_path];
+    return [];
+}
 
-        return {
-            success: true,
-            output_path: resolvedOutputPath,
-            message: `Image converted to ${format} successfully.`
-        };
+// Posts a short summary of a completed tool call to NOTIFY_WEBHOOK_URL (Slack, Discord or a generic JSON endpoint).
+// Thumbnails of the raster outputs (max 10), as PNG buffers with file names.
+async function buildNotificationThumbnails(outputPaths) {
+    const thumbnails = [];
+    for (const p of outputPaths.slice(0, 10)) {
+        if (path.extname(p).toLowerCase() === ".svg" || !fs.existsSync(p)) continue;
+        const data = await sharp(p).resize({ width: 512, height: 512, fit: "inside", withoutEnlargement: true }).png().toBuffer();
+        thumbnails.push({ name: `${path.basename(p, path.extname(p))}.png`, data });
+    }
+    return thumbnails;
+}
 
-    } catch (error) {
-        console.error("Error converting image:", error.message);
-        throw error;
+// Incoming webhooks can't carry files, so with SLACK_BOT_TOKEN and SLACK_CHANNEL_ID the thumbnails are uploaded through
+// Slack's external upload API and shared to the channel in one message, with the notification text as its comment.
+async function uploadSlackThumbnails(text, thumbnails) {
+    const headers = { Authorization: `Bearer ${SLACK_BOT_TOKEN}` };
+    const files = [];
+    for (const thumb of thumbnails) {
+        const { data: ticket } = await axios.post(`${SLACK_API_URL}/files.getUploadURLExternal`,
+            new URLSearchParams({ filename: thumb.name, length: String(thumb.data.length) }), { headers });
+        if (!ticket.ok) throw new Error(`Slack files.getUploadURLExternal failed: ${ticket.error}`);
+        await axios.post(ticket.upload_url, thumb.data, { headers: { "Content-Type": "application/octet-stream" } });
+        files.push({ id: ticket.file_id, title: thumb.name });
     }
+    const { data: completed } = await axios.post(`${SLACK_API_URL}/files.completeUploadExternal`,
+        { files, channel_id: SLACK_CHANNEL_ID, initial_comment: text }, { headers });
+    if (!completed.ok) throw new Error(`Slack files.completeUploadExternal failed: ${completed.error}`);
 }
 
-async function resizeImage(sourcePath, outputPath, width, height, fit = 'cover') {
-    try {
-        const resolvedSourcePath = path.resolve(sourcePath);
-        if (!fs.existsSync(resolvedSourcePath)) throw new Error(`Source image not found: ${resolvedSourcePath}`);
+async function notifyCompletion(toolName, result) {
+    const outputPaths = getResultPaths(result);
+    if (!NOTIFY_WEBHOOK_URL || outputPaths.length === 0) return;
 
-        if (!outputPath) {
-             const dir = path.dirname(resolvedSourcePath);
-             const name = path.basename(resolvedSourcePath, path.extname(resolvedSourcePath));
-             outputPath = path.join(dir, `${name}_resized${path.extname(resolvedSourcePath)}`);
-        }
+    const lines = outputPaths.map(p => `• ${p}`).join("\n");
+    const text = `✅ \`${toolName}\` finished: ${result.message || "done"}\n${lines}`;
+    const type = getWebhookType(NOTIFY_WEBHOOK_URL);
 
-        const resolvedOutputPath = path.resolve(outputPath);
-        const dirOutput = path.dirname(resolvedOutputPath);
-        if (!fs.existsSync(dirOutput)) {
-            fs.mkdirSync(dirOutput, { recursive: true });
+    try {
+        if (type === "slack") {
+            const thumbnails = SLACK_BOT_TOKEN && SLACK_CHANNEL_ID ? await buildNotificationThumbnails(outputPaths) : [];
+            if (thumbnails.length > 0) await uploadSlackThumbnails(text, thumbnails);
+            else await axios.post(NOTIFY_WEBHOOK_URL, { text });
+        } else if (type === "discord") {
+            // Discord webhooks accept file attachments, so include a thumbnail of each raster output (max 10 per message).
+            const formData = new FormData();
+            formData.append("payload_json", JSON.stringify({ content: text.slice(0, 2000) }));
+            (await buildNotificationThumbnails(outputPaths)).forEach((thumb, index) => {
+                formData.append(`files[${index}]`, new Blob([thumb.data], { type: "image/png" }), thumb.name);
+            });
+            const resp = await fetch(NOTIFY_WEBHOOK_URL, { method: "POST", body: formData });
+            if (!resp.ok) throw new Error(`Discord webhook returned ${resp.status}`);
+        } else {
+            await axios.post(NOTIFY_WEBHOOK_URL, { tool: toolName, message: result.message, output_paths: outputPaths });
         }
+    } catch (error) {
+        console.error("Webhook notification failed:", error.message);
+    }
+}
 
-        await sharp(resolvedSourcePath)
-            .resize({
-                width: width,
-                height: height,
-                fit: fit
-            })
-            .toFile(resolvedOutputPath);
 
-        return {
-            success: true,
-            output_path: resolvedOutputPath,
-            message: `Image resized to ${width}x${height || 'auto'} successfully.`
-        };
+// --- Call Isolation ---
+// An error that escapes a tool call's promise chain (a throw in a timer or stream callback, an unhandled rejection)
+// would crash the process and end the MCP session. Each call runs with its own failure hook instead: such an error
+// fails only the call whose async context raised it, as an internal error, and the server keeps running. Work the
+// call had started elsewhere is not cancelled.
 
-    } catch (error) {
-        console.error("Error resizing image:", error.message);
-        throw error;
-    }
+const isolationContext = new AsyncLocalStorage();
+
+class EscapedToolError extends Error {
+  constructor(tool, cause) {
+    super(`Internal error in ${tool}: ${cause?.message ?? String(cause)}`);
+    this.tool = tool;
+    this.cause = cause;
+  }
 }
 
-async function getImageInfo(imagePath) {
-    try {
-        const resolvedPath = path.resolve(imagePath);
-        if (!fs.existsSync(resolvedPath)) throw new Error(`Image file not found: ${resolvedPath}`);
+function runIsolated(tool, fn) {
+  return new Promise((resolve, reject) => {
+    let settled = false;
+    const settle = (callback) => (value) => {
+      if (settled) return;
+      settled = true;
+      callback(value);
+    };
+    const fail = settle((error) => reject(new EscapedToolError(tool, error)));
+    isolationContext.run({ tool, fail }, () => Promise.resolve().then(fn)).then(settle(resolve), settle(reject));
+  });
+}
 
-        const metadata = await sharp(resolvedPath).metadata();
-        
-        return {
-            success: true,
-            info: {
-                format: metadata.format,
-                width: metadata.width,
-                height: metadata.height,
-                channels: metadata.channels,
-                size: fs.statSync(resolvedPath).size
-            }
-        };
+function handleEscapedError(kind, error) {
+  const store = isolationContext.getStore();
+  console.error(`[Isolation] ${kind} ${store ? `in ${store.tool}` : "outside any tool call"}:`, error?.stack || error);
+  if (store) store.fail(error);
+}
 
-    } catch (error) {
-        console.error("Error getting image info:", error.message);
-        throw error;
-    }
+process.on("uncaughtException", (error) => handleEscapedError("Uncaught exception", error));
+process.on("unhandledRejection", (reason) => handleEscapedError("Unhandled rejection", reason));
+
+// Structured JSON-RPC error for a failed tool call; `data.internal` marks errors that escaped the tool's own handling,
+// `data.retryable` ones worth resubmitting as they are (e.g. a provider job stopped by the poll watchdog).
+function toolCallError(tool, error) {
+  const escaped = error instanceof EscapedToolError;
+  return {
+    code: -32603,
+    message: error?.message ?? String(error),
+    data: {
+      tool,
+      internal: escaped,
+      ...(escaped && error.cause?.name ? { cause: error.cause.name } : {}),
+      ...(error?.retryable ? { retryable: true } : {}),
+    },
+  };
 }
 
 
 // --- MCP Server Boilerplate ---
 
+// Set for tools/call requests that carry a progress token (params._meta.progressToken).
+const progressContext = new AsyncLocalStorage();
+
+function reportProgress(progress, total, message) {
+  const store = progressContext.getStore();
+  if (!store) return;
+  sendMessage({
+    jsonrpc: "2.0",
+    method: "notifications/progress",
+    params: { progressToken: store.progressToken, progress, total, message },
+  });
+}
+
 async function processToolCall(toolName, toolInput) {
   if (toolName === "generate_image_from_text") {
-    return await generateImageFromText(toolInput.prompt, toolInput.output_path, {
+    const result = await generateImageFromText(toolInput.prompt, toolInput.output_path, {
         aspectRatio: toolInput.aspectRatio,
         resolution: toolInput.resolution,
-        numberOfImages: toolInput.numberOfImages
+        numberOfImages: toolInput.numberOfImages,
+        writeMetadata: toolInput.write_metadata,
+        styleProfile: toolInput.style_profile,
+        replicateModel: toolInput.replicate_model,
+        replicateInput: toolInput.replicate_input,
+        negativePrompt: toolInput.negative_prompt,
+        sampler: toolInput.sampler,
+        steps: toolInput.steps,
+        cfgScale: toolInput.cfg_scale,
+        seed: toolInput.seed,
+        width: toolInput.width,
+        height: toolInput.height,
+        style: toolInput.style,
+        substyle: toolInput.substyle,
+        vector: toolInput.vector,
+        leonardoModel: toolInput.leonardo_model,
+        presetStyle: toolInput.preset_style,
+        styleUuid: toolInput.style_uuid,
+        leonardoInput: toolInput.leonardo_input,
+        safeRewrite: toolInput.safe_rewrite,
+        quality: toolInput.quality
     });
+    return toolInput.alt_text ? await addAltTexts(result) : result;
+  }
+  if (toolName === "generate_image") {
+    return await generateImage(toolInput);
   }
   if (toolName === "generate_svg_from_text") {
     return await generateSvgFromTextRecraft(toolInput.prompt, toolInput.output_path, {
       size: toolInput.size,
       style: toolInput.style,
       aspect_ratio: toolInput.aspect_ratio,
+      writeMetadata: toolInput.write_metadata,
+      returnContent: toolInput.return_content,
     });
   }
   if (toolName === "edit_image") {
-    return await editImage(toolInput.image_path, toolInput.output_path, toolInput.prompt, {
+    const result = await editImage(toolInput.image_path, toolInput.output_path, toolInput.prompt, {
         aspectRatio: toolInput.aspectRatio,
         resolution: toolInput.resolution,
-        numberOfImages: toolInput.numberOfImages
+        numberOfImages: toolInput.numberOfImages,
+        writeMetadata: toolInput.write_metadata,
+        negativePrompt: toolInput.negative_prompt,
+        sampler: toolInput.sampler,
+        steps: toolInput.steps,
+        cfgScale: toolInput.cfg_scale,
+        seed: toolInput.seed,
+        width: toolInput.width,
+        height: toolInput.height,
+        denoisingStrength: toolInput.denoising_strength,
+        styleProfile: toolInput.style_profile,
+        style: toolInput.style,
+        substyle: toolInput.substyle,
+        leonardoModel: toolInput.leonardo_model,
+        presetStyle: toolInput.preset_style,
+        styleUuid: toolInput.style_uuid,
+        leonardoInput: toolInput.leonardo_input,
+        safeRewrite: toolInput.safe_rewrite
     });
+    return toolInput.alt_text ? await addAltTexts(result) : result;
   }
   if (toolName === "remove_background") {
-    return await removeBackground(toolInput.image_path, toolInput.output_path);
+    return await removeBackground(toolInput.image_path, toolInput.output_path, toolInput.write_metadata);
   }
   if (toolName === "convert_image_format") {
       return await convertImage(toolInput.source_path, toolInput.output_path, toolInput.format);
@@ -962,9 +8381,135 @@ async function processToolCall(toolName, toolInput) {
   if (toolName === "resize_image") {
       return await resizeImage(toolInput.source_path, toolInput.output_path, toolInput.width, toolInput.height, toolInput.fit);
   }
+  if (toolName === "export_project") {
+      return await exportProject(toolInput.image_paths, toolInput.source_dir, toolInput.output_dir, toolInput.name_prefix, toolInput.locale);
+  }
+  if (toolName === "export_web_images") {
+      return await exportWebImages(toolInput.image_paths, toolInput.source_dir, toolInput.output_dir, toolInput.widths, toolInput.formats, toolInput.quality, toolInput.locale);
+  }
+  if (toolName === "read_metadata") {
+      return await readMetadata(toolInput.image_path);
+  }
   if (toolName === "get_image_info") {
       return await getImageInfo(toolInput.image_path);
   }
+  if (toolName === "capture_and_generate" && ENABLE_DESKTOP_TOOLS) {
+      return await captureAndGenerate(toolInput);
+  }
+  if (toolName === "smart_crop") {
+      return await smartCrop(toolInput.image_path, toolInput.aspect_ratios, toolInput.mode, toolInput.output_dir);
+  }
+  if (toolName === "generate_avatar") {
+      return await generateAvatar(toolInput);
+  }
+  if (toolName === "render_chart") {
+      return await renderChart(toolInput);
+  }
+  if (toolName === "render_diagram") {
+      return await renderDiagram(toolInput);
+  }
+  if (toolName === "render_math") {
+      return await renderMath(toolInput);
+  }
+  if (toolName === "render_html") {
+      return await renderHtml(toolInput);
+  }
+  if (toolName === "render_code") {
+      return await renderCode(toolInput);
+  }
+  if (toolName === "make_meme") {
+      return await makeMeme(toolInput);
+  }
+  if (toolName === "placeholder") {
+      return await createPlaceholder(toolInput);
+  }
+  if (toolName === "simulate_color_blindness") {
+      return await simulateColorBlindness(toolInput.image_path, toolInput.types, toolInput.output_dir);
+  }
+  if (toolName === "analyze_colors") {
+      return await analyzeColors(toolInput.image_path, toolInput.count, toolInput.palette, toolInput.tolerance);
+  }
+  if (toolName === "find_duplicates") {
+      return await findDuplicates(toolInput.directory, toolInput.recursive, toolInput.threshold);
+  }
+  if (toolName === "verify_outputs") {
+      return await verifyOutputs(toolInput.path, toolInput.recursive);
+  }
+  if (toolName === "generate_alt_text") {
+      return await generateAltTextTool(toolInput.image_path, toolInput.max_length, toolInput.context, toolInput.write_metadata);
+  }
+  if (toolName === "detect_objects") {
+      return await detectObjects(toolInput.image_path, toolInput.labels, toolInput.max_objects, toolInput.annotate_path);
+  }
+  if (toolName === "edit_region") {
+      return await editRegion(toolInput);
+  }
+  if (toolName === "inpaint") {
+      return await inpaint(toolInput);
+  }
+  if (toolName === "outpaint") {
+      return await outpaint(toolInput);
+  }
+  if (toolName === "upscale") {
+      return await upscale(toolInput);
+  }
+  if (toolName === "style_transfer") {
+      return await styleTransfer(toolInput);
+  }
+  if (toolName === "generate_with_pose") {
+      return await generateWithPose(toolInput);
+  }
+  if (toolName === "generate_texture") {
+      return await generateTexture(toolInput);
+  }
+  if (toolName === "generate_pbr_textures") {
+      return await generatePbrTextures(toolInput);
+  }
+  if (toolName === "generate_storyboard") {
+      return await generateStoryboard(toolInput);
+  }
+  if (toolName === "generate_comic_strip") {
+      return await generateComicStrip(toolInput);
+  }
+  if (toolName === "generate_cover") {
+      return await generateCover(toolInput);
+  }
+  if (toolName === "describe_as_prompt") {
+      return await describeAsPrompt(toolInput);
+  }
+  if (toolName === "create_style_profile") {
+      return await createStyleProfile(toolInput);
+  }
+  if (toolName === "list_style_profiles") {
+      return listStyleProfiles();
+  }
+  if (toolName === "create_experiment") {
+      return createExperiment(toolInput);
+  }
+  if (toolName === "run_experiment") {
+      return await runExperiment(toolInput);
+  }
+  if (toolName === "score_experiment") {
+      return scoreExperiment(toolInput);
+  }
+  if (toolName === "experiment_report") {
+      return experimentReport(toolInput);
+  }
+  if (toolName === "contact_sheet") {
+      return await contactSheet(toolInput);
+  }
+  if (toolName === "chapter_summary_page") {
+      return await chapterSummaryPage(toolInput);
+  }
+  if (toolName === "server_info") {
+      return await serverInfo();
+  }
+  if (toolName === "list_providers") {
+      return await listProviders(toolInput.probe);
+  }
+  if (pluginTools.has(toolName)) {
+      return await callPluginTool(toolName, toolInput);
+  }
   throw new Error(`Unknown tool: ${toolName}`);
 }
 
@@ -973,9 +8518,31 @@ function sendMessage(message) {
 }
 
 async function main() {
+  pluginsReady = loadPlugins().catch(err => console.error("Failed to load plugins:", err.message));
+  getHardwareInfo()
+    .then(info => console.error(`Acceleration: ${info.cpu.features.join(", ") || "no SIMD"}; GPUs: ${info.gpus.map(g => g.name).join(", ") || "none"}; sharp SIMD ${info.selected.sharp_simd ? "on" : "off"}, concurrency ${info.selected.sharp_concurrency}`))
+    .catch(err => console.error("Hardware detection failed:", err.message));
+  if (WARMUP_PROVIDERS) {
+    probeProviders()
+      .then(providers => console.error(`Provider warm-up: ${providers.map(p => {
+        const health = providerHealth.get(p);
+        return `${p} ${health.available ? `${health.latency_ms}ms` : `unavailable (${health.error})`}`;
+      }).join(", ") || "no providers"}`))
+      .catch(err => console.error("Provider warm-up failed:", err.message));
+  }
+
   const rl = readline.createInterface({ input: process.stdin, output: process.stdout, terminal: false });
 
+  // Plugin processes are stopped once the client disconnects and in-flight calls have finished.
+  let inFlight = 0;
+  let closed = false;
+  rl.on("close", () => {
+    closed = true;
+    if (inFlight === 0) stopPlugins();
+  });
+
   rl.on("line", async (line) => {
+    inFlight++;
     try {
       const request = JSON.parse(line);
       if (request.jsonrpc !== "2.0") return; // Simple validation
@@ -995,25 +8562,37 @@ async function main() {
           },
         });
       } else if (request.method === "tools/list") {
+        await pluginsReady;
         sendMessage({ jsonrpc: "2.0", id: request.id, result: { tools: tools } });
       } else if (request.method === "tools/call") {
         try {
-          const result = await processToolCall(request.params.name, request.params.arguments);
+          await pluginsReady;
+          const progressToken = request.params._meta?.progressToken;
+          const result = await runIsolated(request.params.name, () => (progressToken !== undefined
+            ? progressContext.run({ progressToken }, () => callToolWithHooks(request.params.name, request.params.arguments))
+            : callToolWithHooks(request.params.name, request.params.arguments)));
+          // Tools may attach MCP content blocks (e.g. the image itself) that go after the JSON summary.
+          const contentBlocks = result?.contentBlocks || [];
+          const summary = contentBlocks.length > 0 ? { ...result, contentBlocks: undefined } : result;
+          notifyCompletion(request.params.name, summary);
           sendMessage({
             jsonrpc: "2.0",
             id: request.id,
-            result: { content: [{ type: "text", text: JSON.stringify(result, null, 2) }] },
+            result: { content: [{ type: "text", text: JSON.stringify(summary, null, 2) }, ...contentBlocks] },
           });
         } catch (error) {
           sendMessage({
             jsonrpc: "2.0",
             id: request.id,
-            error: { code: -32603, message: error.message },
+            error: toolCallError(request.params.name, error),
           });
         }
       }
     } catch (error) {
       // Ignore malformed lines
+    } finally {
+      inFlight--;
+      if (closed && inFlight === 0) stopPlugins();
     }
   });
 }
@@ -1,4 +1,5 @@
 import { spawn } from "child_process";
+import http from "http";
 import path from "path";
 import fs from "fs";
 import sharp from "sharp";
@@ -30,9 +31,12 @@ async function createTestImage() {
     console.log("Created test image at", TEST_IMAGE_PATH);
 }
 
+// Tests run offline against the mock provider unless a case sets its own environment.
+const MOCK_ENV = { IMAGE_GENERATION_PROVIDER: "mock" };
+
 function runMcpCommand(command, envOverride) {
     return new Promise((resolve, reject) => {
-        const defaultEnv = { IMAGE_GENERATION_PROVIDER: "gemini", GEMINI_API_KEY: "dummy" };
+        const defaultEnv = MOCK_ENV;
         const proc = spawn("node", [SERVER_PATH], {
             env: { ...process.env, ...(envOverride || defaultEnv) }
         });
@@ -79,6 +83,190 @@ function runMcpCommand(command, envOverride) {
     });
 }
 
+// Sends several commands to one server process and resolves with the responses in the same order.
+function runMcpCommands(commands, env) {
+    return new Promise((resolve, reject) => {
+        const proc = spawn("node", [SERVER_PATH], { env: { ...process.env, ...env } });
+        const responses = new Map();
+        let output = "";
+        let errorOutput = "";
+
+        proc.stdout.on("data", (data) => {
+            output += data.toString();
+            const lines = output.split("\n");
+            output = lines.pop();
+            for (const line of lines) {
+                if (!line.trim()) continue;
+                try {
+                    const json = JSON.parse(line);
+                    if (commands.some(c => c.id === json.id)) responses.set(json.id, json);
+                } catch (e) {
+                    // Non-JSON output
+                }
+            }
+            if (responses.size === commands.length) {
+                resolve(commands.map(c => responses.get(c.id)));
+                proc.kill();
+            }
+        });
+
+        proc.stderr.on("data", (data) => {
+            errorOutput += data.toString();
+        });
+
+        proc.on("close", (code) => {
+            if (responses.size < commands.length) {
+                reject(new Error(`Process exited with code ${code} before answering: ${errorOutput}`));
+            }
+        });
+
+        proc.stdin.write(JSON.stringify({ jsonrpc: "2.0", id: 0, method: "initialize", params: {} }) + "\n");
+        for (const command of commands) proc.stdin.write(JSON.stringify(command) + "\n");
+    });
+}
+
+let nextCallId = 100;
+
+function toolCall(name, args) {
+    return { jsonrpc: "2.0", id: nextCallId++, method: "tools/call", params: { name, arguments: args } };
+}
+
+// Runs one tool call, or several in the same server (which keeps state such as the balanced round-robin between them),
+// and reports PASSED when `verify` returns true.
+async function smokeTest(label, calls, env, verify) {
+    console.log(`\n--- Testing ${label} ---`);
+    try {
+        const answered = await runMcpCommands(Array.isArray(calls) ? calls : [calls], { ...MOCK_ENV, ...env });
+        const failed = answered.find(r => r.error || r.result?.isError);
+        const results = answered.map(r => (r.result ? JSON.parse(r.result.content[0].text) : r.error));
+        if (failed && !verify.expectsError) {
+            console.error(`${label} failed:`, failed.error || failed.result.content[0].text);
+            console.log("FAILED");
+            return;
+        }
+        const ok = await verify(results);
+        console.log(ok ? "PASSED" : `FAILED: ${JSON.stringify(results).slice(0, 500)}`);
+    } catch (e) {
+        console.error("Test failed:", e);
+        console.log("FAILED");
+    }
+}
+
+function expectError(verify) {
+    verify.expectsError = true;
+    return verify;
+}
+
+// A stand-in Automatic1111 WebUI. `handler(request, hitCount)` returns an HTTP status; 200 answers with the test image.
+function startFakeWebUI(handler) {
+    const requests = [];
+    const server = http.createServer((req, res) => {
+        let body = "";
+        req.on("data", chunk => { body += chunk; });
+        req.on("end", () => {
+            requests.push({ url: req.url, headers: req.headers, body });
+            const status = handler(req, requests.length);
+            res.statusCode = status;
+            res.setHeader("Content-Type", "application/json");
+            if (status !== 200) {
+                res.end(JSON.stringify({ error: "unavailable" }));
+                return;
+            }
+            res.end(JSON.stringify({ images: [fs.readFileSync(TEST_IMAGE_PATH).toString("base64")], info: "{}" }));
+        });
+    });
+    return new Promise(resolve => {
+        server.listen(0, "127.0.0.1", () => {
+            resolve({ url: `http://127.0.0.1:${server.address().port}`, requests, close: () => server.close() });
+        });
+    });
+}
+
+const outputExists = (results) => results.every(r => (r.output_paths || [r.output_path]).every(p => p && fs.existsSync(p)));
+
+async function runProviderSmokeTests() {
+    const out = (name) => path.join(OUTPUT_DIR, name);
+
+    await smokeTest("generate_image_from_text (mock)", toolCall("generate_image_from_text", {
+        prompt: "a lighthouse at dusk", output_path: out("mock_text.png"), seed: 1,
+    }), {}, outputExists);
+
+    await smokeTest("generate_image (mock)", toolCall("generate_image", {
+        prompt: "a lighthouse at dusk", count: 2, output_path: out("mock_generate.png"),
+    }), {}, (results) => outputExists(results) && results[0].output_paths.length === 2);
+
+    await smokeTest("edit_image (mock)", toolCall("edit_image", {
+        image_path: TEST_IMAGE_PATH, prompt: "make it blue", output_path: out("mock_edit.png"),
+    }), {}, (results) => outputExists(results) && results[0].provider === "mock");
+
+    await smokeTest("edit_region (mock)", toolCall("edit_region", {
+        image_path: TEST_IMAGE_PATH, prompt: "add a window", box: { x: 10, y: 10, width: 40, height: 40 }, output_path: out("mock_region.png"),
+    }), {}, outputExists);
+
+
+    await smokeTest("outpaint (mock)", toolCall("outpaint", {
+        image_path: TEST_IMAGE_PATH, direction: "right", pixels: 50, output_path: out("mock_outpaint.png"),
+    }), {}, outputExists);
+
+    await smokeTest("upscale (mock)", toolCall("upscale", {
+        image_path: TEST_IMAGE_PATH, scale: 2, output_path: out("mock_upscale.png"),
+    }), {}, outputExists);
+
+    // The mock provider takes no reference images, so style_transfer must refuse it instead of dropping the style image.
+    await smokeTest("style_transfer (mock, no reference images)", toolCall("style_transfer", {
+        content_image_path: TEST_IMAGE_PATH, style_image_path: TEST_IMAGE_PATH, output_path: out("mock_style.png"),
+    }), {}, expectError((results) => /cannot take a style reference image/.test(JSON.stringify(results[0]))));
+
+    await smokeTest("export_web_images (mock)", toolCall("export_web_images", {
+        image_paths: [TEST_IMAGE_PATH], output_dir: out("web"), widths: [50], formats: ["png"],
+    }), {}, () => fs.existsSync(out("web")) && fs.readdirSync(out("web")).length > 0);
+
+    await smokeTest("export_project (mock)", toolCall("export_project", {
+        image_paths: [TEST_IMAGE_PATH], output_dir: out("project"),
+    }), {}, () => fs.existsSync(out("project")) && fs.readdirSync(out("project")).length > 0);
+
+    // The mock provider can't inpaint with a mask, so inpainting runs on the stand-in WebUI, which must receive the mask.
+    const inpaintWebUI = await startFakeWebUI(() => 200);
+    await smokeTest("inpaint (WebUI)", toolCall("inpaint", {
+        image_path: TEST_IMAGE_PATH, prompt: "a cat", box: { x: 20, y: 20, width: 30, height: 30 }, output_path: out("inpaint.png"),
+    }), {
+        IMAGE_GENERATION_PROVIDER: "automatic1111", AUTOMATIC1111_URL: inpaintWebUI.url,
+    }, (results) => outputExists(results)
+        && inpaintWebUI.requests.some(r => r.url.endsWith("/img2img") && JSON.parse(r.body).mask));
+    inpaintWebUI.close();
+
+    // The WebUI is down, so the request moves on to the mock fallback.
+    const downWebUI = await startFakeWebUI(() => 503);
+    await smokeTest("fallback from a failing provider", toolCall("generate_image_from_text", {
+        prompt: "a fallback test", output_path: out("fallback.png"),
+    }), {
+        IMAGE_GENERATION_PROVIDER: "automatic1111", AUTOMATIC1111_URL: downWebUI.url,
+        PROVIDER_FALLBACKS: "mock", HTTP_RETRIES: "0",
+    }, (results) => outputExists(results) && results[0].provider === "mock" && results[0].fallbacks?.[0]?.provider === "automatic1111");
+    downWebUI.close();
+
+    // Two members with equal weights take turns.
+    const balancedWebUI = await startFakeWebUI(() => 200);
+    await smokeTest("balanced routing", [
+        toolCall("generate_image_from_text", { prompt: "balanced one", output_path: out("balanced_1.png") }),
+        toolCall("generate_image_from_text", { prompt: "balanced two", output_path: out("balanced_2.png") }),
+    ], {
+        IMAGE_GENERATION_PROVIDER: "balanced", BALANCED_PROVIDERS: "mock:1,automatic1111:1", AUTOMATIC1111_URL: balancedWebUI.url,
+    }, (results) => outputExists(results) && new Set(results.map(r => r.provider)).size === 2 && balancedWebUI.requests.length === 1);
+    balancedWebUI.close();
+
+    // The HTTP middleware retries the 503 and adds the per-call header to the provider request.
+    const flakyWebUI = await startFakeWebUI((req, hit) => (hit === 1 ? 503 : 200));
+    await smokeTest("HTTP middleware (retry and per-call headers)", toolCall("generate_image_from_text", {
+        prompt: "a retry test", output_path: out("middleware.png"), http_headers: { "X-Smoke-Test": "yes" },
+    }), {
+        IMAGE_GENERATION_PROVIDER: "automatic1111", AUTOMATIC1111_URL: flakyWebUI.url, HTTP_RETRIES: "1", HTTP_RETRY_BASE_MS: "10",
+    }, (results) => outputExists(results)
+        && flakyWebUI.requests.length === 2
+        && flakyWebUI.requests.every(r => r.headers["x-smoke-test"] === "yes"));
+    flakyWebUI.close();
+}
+
 async function runTests() {
     await createTestImage();
 
@@ -182,6 +370,46 @@ async function runTests() {
         console.error("Test failed:", e);
     }
 
+    console.log("\n--- Testing render_chart ---");
+    const chartPath = path.join(OUTPUT_DIR, "chart.png");
+    try {
+        const response = await runMcpCommand({
+            jsonrpc: "2.0",
+            id: 5,
+            method: "tools/call",
+            params: {
+                name: "render_chart",
+                arguments: {
+                    type: "bar",
+                    labels: ["Q1", "Q2", "Q3"],
+                    series: [{ name: "Revenue", values: [10, 25, 17] }],
+                    width: 400,
+                    height: 300,
+                    output_path: chartPath
+                }
+            }
+        });
+
+        if (response.error) {
+            console.error("render_chart failed:", response.error);
+        } else {
+            const result = JSON.parse(response.result.content[0].text);
+            console.log("render_chart result:", result);
+
+            // Verify
+            const meta = await sharp(chartPath).metadata();
+            if (meta.format === "png" && meta.width === 400 && meta.height === 300) {
+                console.log("PASSED");
+            } else {
+                console.log(`FAILED: got ${meta.format} ${meta.width}x${meta.height}`);
+            }
+        }
+    } catch (e) {
+        console.error("Test failed:", e);
+    }
+
+    await runProviderSmokeTests();
+
     console.log("\n--- Testing generate_image_from_text (Replicate Nano Banana Pro) ---");
     const genPath = path.join(OUTPUT_DIR, "agent_generated.png");
     try {